@@ -0,0 +1,477 @@
+//! Record and replay of the JSON-RPC traffic of range tests.
+//!
+//! A `CassetteServer` is a local JSON-RPC stand-in that the test runner, the execution simulation
+//! and the indexer talk to instead of the node:
+//!
+//! - in `Record` mode, it forwards every call to the node and saves the responses to a cassette
+//!   file per test,
+//! - in `Replay` mode, it answers from the cassette instead, so tests run deterministically and
+//!   without an RPC_URL.
+//!
+//! Calls are matched by method and params. Calls with the same method and params are answered in
+//! the recorded order, the last response being repeated once they are exhausted.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use alloy::{
+    primitives::{keccak256, B256},
+    transports::http::reqwest::Url,
+};
+use itertools::Itertools;
+use miette::{miette, IntoDiagnostic, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+
+/// JSON-RPC error code returned for calls that can't be answered.
+const SERVER_ERROR_CODE: i64 = -32000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Forward calls to the node and record them.
+    Record,
+    /// Answer calls from the recorded cassette.
+    Replay,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    /// Hash of the canonical params. Params are not stored as they can hold large state
+    /// overrides.
+    params_hash: B256,
+    /// The response, without its `jsonrpc` and `id` fields.
+    response: Value,
+}
+
+impl Interaction {
+    fn key(&self) -> (String, B256) {
+        (self.method.clone(), self.params_hash)
+    }
+}
+
+/// The cassette of the running test.
+struct Session {
+    path: PathBuf,
+    cassette: Cassette,
+    /// Recorded responses by method and params hash, for replay.
+    responses: HashMap<(String, B256), Vec<Value>>,
+    /// Number of responses already replayed by method and params hash.
+    replayed: HashMap<(String, B256), usize>,
+}
+
+struct CassetteState {
+    mode: CassetteMode,
+    upstream: Option<Url>,
+    client: reqwest::Client,
+    session: Mutex<Option<Session>>,
+}
+
+/// Local JSON-RPC stand-in recording or replaying cassettes, see the module documentation.
+///
+/// The server is stopped when dropped.
+pub struct CassetteServer {
+    url: Url,
+    state: Arc<CassetteState>,
+    handle: JoinHandle<()>,
+}
+
+impl CassetteServer {
+    /// Starts the server on a random local port.
+    ///
+    /// Must be called from within a Tokio runtime, which keeps serving requests in the background.
+    ///
+    /// # Errors
+    /// Returns an error if `upstream` is missing in record mode or if the server can't be bound.
+    pub async fn start(mode: CassetteMode, upstream: Option<Url>) -> miette::Result<Self> {
+        if mode == CassetteMode::Record && upstream.is_none() {
+            return Err(miette!("An RPC URL is required to record cassettes"));
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to bind the cassette server")?;
+        let address = listener
+            .local_addr()
+            .into_diagnostic()?;
+        let url = format!("http://{address}")
+            .parse()
+            .into_diagnostic()?;
+        info!("Started RPC cassette server in {mode:?} mode at {url}");
+
+        let state = Arc::new(CassetteState {
+            mode,
+            upstream,
+            client: reqwest::Client::new(),
+            session: Mutex::new(None),
+        });
+        let server_state = state.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let state = server_state.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = state.serve_connection(stream).await {
+                                debug!("Cassette server connection closed: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => error!("Cassette server failed to accept connection: {e}"),
+                }
+            }
+        });
+
+        Ok(Self { url, state, handle })
+    }
+
+    /// URL to use instead of the node's.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Starts recording to, or replaying from, the cassette at `path`.
+    ///
+    /// # Errors
+    /// Returns an error in replay mode if the cassette can't be read.
+    pub fn insert(&self, path: &Path) -> miette::Result<()> {
+        let cassette = match self.state.mode {
+            CassetteMode::Record => Cassette::default(),
+            CassetteMode::Replay => {
+                let content = std::fs::read_to_string(path)
+                    .into_diagnostic()
+                    .wrap_err(format!(
+                        "Failed to read cassette {}. Run the test with --record first.",
+                        path.display()
+                    ))?;
+                serde_json::from_str(&content)
+                    .into_diagnostic()
+                    .wrap_err(format!("Failed to parse cassette {}", path.display()))?
+            }
+        };
+        info!("Using RPC cassette {}", path.display());
+
+        let mut responses: HashMap<_, Vec<_>> = HashMap::new();
+        for interaction in &cassette.interactions {
+            responses
+                .entry(interaction.key())
+                .or_default()
+                .push(interaction.response.clone());
+        }
+        *self.state.session.lock().unwrap() = Some(Session {
+            path: path.to_path_buf(),
+            cassette,
+            responses,
+            replayed: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    /// Stops using the current cassette, saving it in record mode.
+    ///
+    /// # Errors
+    /// Returns an error if the recorded cassette can't be written.
+    pub fn eject(&self) -> miette::Result<()> {
+        let Some(session) = self
+            .state
+            .session
+            .lock()
+            .unwrap()
+            .take()
+        else {
+            return Ok(());
+        };
+        match self.state.mode {
+            CassetteMode::Record => {
+                if let Some(dir) = session.path.parent() {
+                    std::fs::create_dir_all(dir)
+                        .into_diagnostic()
+                        .wrap_err("Failed to create cassette directory")?;
+                }
+                let content = serde_json::to_string_pretty(&session.cassette).into_diagnostic()?;
+                std::fs::write(&session.path, content)
+                    .into_diagnostic()
+                    .wrap_err(format!("Failed to write cassette {}", session.path.display()))?;
+                info!(
+                    "Recorded {} RPC calls to {}",
+                    session.cassette.interactions.len(),
+                    session.path.display()
+                );
+            }
+            CassetteMode::Replay => {
+                let replayed: usize = session.replayed.values().sum();
+                debug!(
+                    "Replayed {replayed} RPC calls from {} ({} recorded)",
+                    session.path.display(),
+                    session.cassette.interactions.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CassetteServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl CassetteState {
+    /// Serves the JSON-RPC requests of an HTTP/1.1 connection until it is closed.
+    async fn serve_connection(&self, stream: TcpStream) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        loop {
+            // Request line, then headers until an empty line.
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Ok(());
+            }
+            let mut content_length = 0;
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).await? == 0 {
+                    return Ok(());
+                }
+                let header = line.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await?;
+            let response = match serde_json::from_slice::<Value>(&body) {
+                Ok(Value::Array(calls)) => {
+                    // Handled sequentially so that the recorded order is deterministic.
+                    let mut responses = Vec::with_capacity(calls.len());
+                    for call in calls {
+                        responses.push(self.handle_call(call).await);
+                    }
+                    Value::Array(responses)
+                }
+                Ok(call) => self.handle_call(call).await,
+                Err(e) => json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": -32700, "message": format!("Parse error: {e}") }
+                }),
+            };
+
+            let body = response.to_string();
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            );
+            let stream = reader.get_mut();
+            stream
+                .write_all(head.as_bytes())
+                .await?;
+            stream
+                .write_all(body.as_bytes())
+                .await?;
+        }
+    }
+
+    /// Answers a single JSON-RPC call, recording or replaying it.
+    async fn handle_call(&self, call: Value) -> Value {
+        let id = call
+            .get("id")
+            .cloned()
+            .unwrap_or(Value::Null);
+        let method = call
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let params = call
+            .get("params")
+            .cloned()
+            .unwrap_or(Value::Null);
+        let params_hash = keccak256(canonical_json(&params).to_string());
+
+        let result = match self.mode {
+            CassetteMode::Record => self
+                .forward(&call)
+                .await
+                .map(|response| self.record(method, params_hash, response)),
+            CassetteMode::Replay => self.replay(&method, params_hash),
+        };
+        let mut response = match result {
+            Ok(Value::Object(response)) => response,
+            Ok(other) => Map::from_iter([("error".to_string(), server_error(format!("{other}")))]),
+            Err(message) => {
+                warn!("RPC cassette: {message}");
+                Map::from_iter([("error".to_string(), server_error(message))])
+            }
+        };
+        response.insert("jsonrpc".to_string(), json!("2.0"));
+        response.insert("id".to_string(), id);
+        Value::Object(response)
+    }
+
+    /// Forwards the call to the node and returns its response, without `jsonrpc` and `id`.
+    async fn forward(&self, call: &Value) -> Result<Value, String> {
+        let upstream = self
+            .upstream
+            .clone()
+            .ok_or("no RPC URL to forward calls to")?;
+        let mut response: Value = self
+            .client
+            .post(upstream)
+            .json(call)
+            .send()
+            .await
+            .map_err(|e| format!("failed to forward call: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("invalid response from node: {e}"))?;
+        if let Some(response) = response.as_object_mut() {
+            response.remove("jsonrpc");
+            response.remove("id");
+        }
+        Ok(response)
+    }
+
+    fn record(&self, method: String, params_hash: B256, response: Value) -> Value {
+        match self.session.lock().unwrap().as_mut() {
+            Some(session) => session
+                .cassette
+                .interactions
+                .push(Interaction { method, params_hash, response: response.clone() }),
+            None => warn!("RPC cassette: no cassette inserted, {method} call is not recorded"),
+        }
+        response
+    }
+
+    fn replay(&self, method: &str, params_hash: B256) -> Result<Value, String> {
+        let mut session = self.session.lock().unwrap();
+        let session = session
+            .as_mut()
+            .ok_or_else(|| format!("no cassette inserted to replay {method}"))?;
+        let key = (method.to_string(), params_hash);
+        let responses = session
+            .responses
+            .get(&key)
+            .ok_or_else(|| {
+                format!(
+                    "{method} call with params hash {params_hash} not found in cassette {}",
+                    session.path.display()
+                )
+            })?;
+        let replayed = session.replayed.entry(key).or_default();
+        let response = responses[(*replayed).min(responses.len() - 1)].clone();
+        *replayed += 1;
+        Ok(response)
+    }
+}
+
+fn server_error(message: String) -> Value {
+    json!({ "code": SERVER_ERROR_CODE, "message": message })
+}
+
+/// Returns the value with object keys sorted, so that equal params always hash the same.
+fn canonical_json(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                .map(|(key, value)| (key.clone(), canonical_json(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(canonical_json)
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_json_sorts_keys() {
+        let a = json!([{ "to": "0x01", "data": "0x02" }, { "b": { "y": 1, "x": 2 }, "a": 3 }]);
+        let b = json!([{ "data": "0x02", "to": "0x01" }, { "a": 3, "b": { "x": 2, "y": 1 } }]);
+
+        assert_eq!(canonical_json(&a).to_string(), canonical_json(&b).to_string());
+    }
+
+    #[tokio::test]
+    async fn test_replay_cassette() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.json");
+        let params = json!(["0x1", false]);
+        let interaction = |number: &str| Interaction {
+            method: "eth_getBlockByNumber".to_string(),
+            params_hash: keccak256(canonical_json(&params).to_string()),
+            response: json!({ "result": { "number": number } }),
+        };
+        let cassette = Cassette { interactions: vec![interaction("0x1"), interaction("0x2")] };
+        std::fs::write(&path, serde_json::to_string(&cassette).unwrap()).unwrap();
+
+        let server = CassetteServer::start(CassetteMode::Replay, None)
+            .await
+            .unwrap();
+        server.insert(&path).unwrap();
+
+        let method = "eth_getBlockByNumber";
+        let call = |id: u64, params: Value| json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let client = reqwest::Client::new();
+        let response: Value = client
+            .post(server.url().clone())
+            .json(&json!([call(1, params.clone()), call(2, params.clone())]))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response,
+            json!([
+                { "jsonrpc": "2.0", "id": 1, "result": { "number": "0x1" } },
+                { "jsonrpc": "2.0", "id": 2, "result": { "number": "0x2" } }
+            ])
+        );
+
+        let response: Value = client
+            .post(server.url().clone())
+            .json(&call(3, json!(["0x2", false])))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(response["id"], 3);
+        assert_eq!(response["error"]["code"], SERVER_ERROR_CODE);
+        server.eject().unwrap();
+    }
+}
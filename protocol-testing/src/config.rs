@@ -1,10 +1,20 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    num::NonZeroUsize,
+    str::FromStr,
+};
 
 use colored::Colorize;
+use itertools::Itertools;
+use num_bigint::{BigInt, BigUint};
+use num_rational::BigRational;
 use serde::{Deserialize, Serialize};
 use similar::{ChangeTag, TextDiff};
 use tycho_simulation::tycho_common::{dto::ProtocolComponent, Bytes};
 
+use crate::state_registry::{Decoder, TvlFilter};
+
 /// Represents a ProtocolComponent with its main attributes
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ProtocolComponentExpectation {
@@ -24,6 +34,125 @@ pub struct ProtocolComponentWithTestConfig {
     pub skip_simulation: bool,
     #[serde(default = "default_false")]
     pub skip_execution: bool,
+    /// Overrides the protocol's simulation settings for this component.
+    #[serde(default)]
+    pub simulation: SimulationConfig,
+}
+
+/// Amount of input token to simulate a swap with, `relative: 0.01` or `absolute: 1.5` in yaml.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SimulationAmount {
+    /// Fraction of the maximum input returned by `get_limits`, e.g. `0.01` for 1%.
+    Relative { relative: f64 },
+    /// Amount in units of the input token, e.g. `1.5` for 1.5 WETH.
+    Absolute { absolute: f64 },
+}
+
+impl SimulationAmount {
+    /// Returns the raw amount of input token to simulate with.
+    ///
+    /// # Errors
+    /// Returns an error if the amount is not positive, or if a relative amount is above 1.
+    pub fn amount_in(&self, max_input: &BigUint, decimals: u32) -> Result<BigUint, String> {
+        let (value, scale) = match *self {
+            SimulationAmount::Relative { relative: fraction }
+                if fraction > 0.0 && fraction <= 1.0 =>
+            {
+                (fraction, BigRational::from_integer(BigInt::from(max_input.clone())))
+            }
+            SimulationAmount::Absolute { absolute: amount } if amount > 0.0 => {
+                (amount, BigRational::from_integer(BigInt::from(10u32).pow(decimals)))
+            }
+            _ => return Err(format!("Invalid simulation amount: {self}")),
+        };
+        let value =
+            decimal_ratio(value).ok_or_else(|| format!("Invalid simulation amount: {self}"))?;
+        (value * scale)
+            .floor()
+            .to_integer()
+            .to_biguint()
+            .ok_or_else(|| format!("Invalid simulation amount: {self}"))
+    }
+}
+
+/// Returns the exact ratio of the decimal representation of a float, e.g. 1/1000 for 0.001,
+/// rather than of its binary value.
+fn decimal_ratio(value: f64) -> Option<BigRational> {
+    let decimal = value.to_string();
+    let (integer, fraction) = decimal
+        .split_once('.')
+        .unwrap_or((&decimal, ""));
+    let numerator = BigInt::from_str(&format!("{integer}{fraction}")).ok()?;
+    let denominator = BigInt::from(10u32).pow(fraction.len() as u32);
+    Some(BigRational::new(numerator, denominator))
+}
+
+impl Display for SimulationAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationAmount::Relative { relative } => {
+                write!(f, "{}% of max input", relative * 100.0)
+            }
+            SimulationAmount::Absolute { absolute } => write!(f, "{absolute} tokens"),
+        }
+    }
+}
+
+/// Simulation settings of a protocol or of a single component.
+///
+/// Unset component settings fall back to the protocol's, and unset protocol settings to the
+/// defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SimulationConfig {
+    /// Amounts to simulate swaps with, in every swap direction.
+    pub amounts: Option<Vec<SimulationAmount>>,
+    /// Maximum relative difference between the simulated and executed amounts out.
+    pub tolerance: Option<f64>,
+}
+
+impl SimulationConfig {
+    /// 0.1%, 1% and 10% of the maximum input.
+    pub const DEFAULT_AMOUNTS: [SimulationAmount; 3] = [
+        SimulationAmount::Relative { relative: 0.001 },
+        SimulationAmount::Relative { relative: 0.01 },
+        SimulationAmount::Relative { relative: 0.1 },
+    ];
+    /// 0.5%
+    pub const DEFAULT_TOLERANCE: f64 = 0.005;
+
+    /// Returns these settings, with unset values taken from `base`.
+    pub fn or(&self, base: &SimulationConfig) -> SimulationConfig {
+        SimulationConfig {
+            amounts: self
+                .amounts
+                .clone()
+                .or_else(|| base.amounts.clone()),
+            tolerance: self.tolerance.or(base.tolerance),
+        }
+    }
+
+    pub fn amounts(&self) -> Vec<SimulationAmount> {
+        self.amounts
+            .clone()
+            .unwrap_or_else(|| Self::DEFAULT_AMOUNTS.to_vec())
+    }
+
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+            .unwrap_or(Self::DEFAULT_TOLERANCE)
+    }
+
+    /// # Errors
+    /// Returns an error if the tolerance is negative.
+    pub fn validate(&self) -> Result<(), String> {
+        match self.tolerance {
+            Some(tolerance) if tolerance.is_nan() || tolerance < 0.0 => {
+                Err(format!("Invalid simulation tolerance {tolerance}, it must not be negative"))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl ProtocolComponentExpectation {
@@ -52,8 +181,8 @@ impl ProtocolComponentExpectation {
             match other_value {
                 Some(other_value) => {
                     if value != other_value {
-                        let self_value = format!("{value:?}");
-                        let other_value = format!("{other_value:?}");
+                        let self_value = describe_attribute_value(value);
+                        let other_value = describe_attribute_value(other_value);
                         let diff = self.format_diff(
                             "static_attributes",
                             &self_value,
@@ -61,15 +190,21 @@ impl ProtocolComponentExpectation {
                             colorize_output,
                         );
                         diffs.push(format!(
-                            "Field 'static_attributes' mismatch for {}:\n{}",
-                            self.id, diff
+                            "Field 'static_attributes' mismatch for {} (key '{}'):\n{}",
+                            self.id, key, diff
                         ));
                     }
                 }
                 None => {
                     diffs.push(format!(
                         "Field 'static_attributes' mismatch for {}: Key '{}' not found. Available attributes: {:?}",
-                        self.id, key, other.static_attributes,
+                        self.id,
+                        key,
+                        other
+                            .static_attributes
+                            .keys()
+                            .sorted()
+                            .collect::<Vec<_>>(),
                     ));
                 }
             }
@@ -120,10 +255,77 @@ impl ProtocolComponentExpectation {
     }
 }
 
+/// Renders a raw attribute value as hex, followed by its plausible typed interpretations.
+///
+/// Attributes are stored as raw bytes, so the actual type is unknown here. Listing the
+/// interpretations matching the encodings used by the substreams attribute codecs makes
+/// mismatches readable (e.g. a fee of `3000` instead of `0x0bb8`).
+fn describe_attribute_value(value: &Bytes) -> String {
+    let bytes = value.as_ref();
+    let mut lines = vec![format!("0x{}", hex::encode(bytes))];
+
+    if let Some(elements) = decode_json_hex_list(bytes) {
+        lines.push(format!("  list: [{}]", elements.join(", ")));
+    } else if let Ok(text) = std::str::from_utf8(bytes) {
+        if !text.is_empty() && text.chars().all(|c| !c.is_control()) {
+            lines.push(format!("  string: {text:?}"));
+        }
+    }
+    if bytes.len() == 20 {
+        lines.push(format!("  address: 0x{}", hex::encode(bytes)));
+    }
+    if let [flag @ (0 | 1)] = bytes {
+        lines.push(format!("  bool: {}", *flag == 1));
+    }
+    if !bytes.is_empty() && bytes.len() <= 32 {
+        let unsigned = BigUint::from_bytes_be(bytes);
+        let signed = BigInt::from_signed_bytes_be(bytes);
+        lines.push(format!("  uint: {unsigned}"));
+        if signed.sign() == num_bigint::Sign::Minus {
+            lines.push(format!("  int: {signed}"));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Decodes a json list of 0x prefixed hex strings, as produced by the substreams json helpers.
+fn decode_json_hex_list(bytes: &[u8]) -> Option<Vec<String>> {
+    let values: Vec<String> = serde_json::from_slice(bytes).ok()?;
+    values
+        .iter()
+        .map(|v| {
+            let element = hex::decode(v.strip_prefix("0x")?).ok()?;
+            if element.len() == 20 {
+                Some(format!("0x{}", hex::encode(element)))
+            } else {
+                Some(BigInt::from_signed_bytes_be(&element).to_string())
+            }
+        })
+        .collect()
+}
+
 fn default_false() -> bool {
     false
 }
 
+/// Returns the simulation settings of a component, falling back to the protocol's if the
+/// component is not among the expected ones or doesn't override them.
+pub fn component_simulation_config(
+    expected_components: &[ProtocolComponentWithTestConfig],
+    protocol_config: &SimulationConfig,
+    component_id: &str,
+) -> SimulationConfig {
+    expected_components
+        .iter()
+        .find(|c| {
+            c.base
+                .id
+                .eq_ignore_ascii_case(component_id)
+        })
+        .map(|c| c.simulation.or(protocol_config))
+        .unwrap_or_else(|| protocol_config.clone())
+}
+
 /// Configuration for an individual test
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct IntegrationTest {
@@ -146,5 +348,140 @@ pub struct IntegrationTestsConfig {
     pub protocol_type_names: Vec<String>,
     pub protocol_system: String,
     pub module_name: Option<String>,
+    /// Decoder of the protocol states, inferred from `protocol_system` if not set.
+    pub decoder: Option<Decoder>,
+    /// TVL thresholds of the tracked components, 100 native tokens by default.
+    pub tvl_filter: Option<TvlFilter>,
+    /// Simulation settings of all components, unless overridden per component.
+    #[serde(default)]
+    pub simulation: SimulationConfig,
+    /// Number of swaps executed per RPC request. Large state overrides can exceed RPC request
+    /// size limits, in which case this should be lowered.
+    pub execution_batch_size: Option<NonZeroUsize>,
     pub tests: Vec<IntegrationTest>,
 }
+
+impl IntegrationTestsConfig {
+    pub const DEFAULT_EXECUTION_BATCH_SIZE: usize = 30;
+
+    pub fn execution_batch_size(&self) -> usize {
+        self.execution_batch_size
+            .map_or(Self::DEFAULT_EXECUTION_BATCH_SIZE, NonZeroUsize::get)
+    }
+
+    /// Checks the settings that can't be validated while parsing.
+    ///
+    /// # Errors
+    /// Returns an error if the simulation settings of the protocol or of a component are invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        self.simulation.validate()?;
+        for test in &self.tests {
+            for component in &test.expected_components {
+                component
+                    .simulation
+                    .validate()
+                    .map_err(|e| format!("{e} for component {}", component.base.id))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulation_amount_in() {
+        let max_input = BigUint::from(2_000_000u32);
+
+        assert_eq!(
+            SimulationAmount::Relative { relative: 0.0005 }.amount_in(&max_input, 6),
+            Ok(BigUint::from(1000u32))
+        );
+        assert_eq!(
+            SimulationAmount::Absolute { absolute: 1.5 }.amount_in(&max_input, 18),
+            Ok(BigUint::from(1_500_000_000_000_000_000u64))
+        );
+        assert!(SimulationAmount::Relative { relative: 1.5 }
+            .amount_in(&max_input, 6)
+            .is_err());
+        assert!(SimulationAmount::Absolute { absolute: 0.0 }
+            .amount_in(&max_input, 6)
+            .is_err());
+        // Same amounts as `max_input * 1 / 1000`, not as the binary value of 0.001
+        let max_input = BigUint::from_str("123456789012345678901234").unwrap();
+        assert_eq!(
+            SimulationAmount::Relative { relative: 0.001 }.amount_in(&max_input, 18),
+            Ok(BigUint::from_str("123456789012345678901").unwrap())
+        );
+        assert_eq!(
+            SimulationAmount::Relative { relative: 0.1 }.amount_in(&max_input, 18),
+            Ok(BigUint::from_str("12345678901234567890123").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let config = SimulationConfig { amounts: None, tolerance: Some(-0.01) };
+        assert!(config.validate().is_err());
+        let config = SimulationConfig { amounts: None, tolerance: Some(0.0) };
+        assert_eq!(config.validate(), Ok(()));
+
+        let error = serde_yaml::from_str::<Option<NonZeroUsize>>("0").unwrap_err();
+        assert!(error.to_string().contains("nonzero"), "{error}");
+    }
+
+    #[test]
+    fn test_component_simulation_config() {
+        let yaml = r#"
+            id: "0xabc"
+            tokens: []
+            creation_tx: "0x00"
+            simulation:
+              amounts:
+                - absolute: 100
+              tolerance: 0.02
+        "#;
+        let component: ProtocolComponentWithTestConfig = serde_yaml::from_str(yaml).unwrap();
+        let protocol_config = SimulationConfig {
+            amounts: Some(vec![SimulationAmount::Relative { relative: 0.5 }]),
+            tolerance: None,
+        };
+
+        let config = component_simulation_config(&[component], &protocol_config, "0xABC");
+        assert_eq!(config.amounts(), vec![SimulationAmount::Absolute { absolute: 100.0 }]);
+        assert_eq!(config.tolerance(), 0.02);
+
+        let config = component_simulation_config(&[], &protocol_config, "0xabc");
+        assert_eq!(config.amounts(), vec![SimulationAmount::Relative { relative: 0.5 }]);
+        assert_eq!(config.tolerance(), SimulationConfig::DEFAULT_TOLERANCE);
+        assert_eq!(SimulationConfig::default().amounts(), SimulationConfig::DEFAULT_AMOUNTS);
+    }
+
+    #[test]
+    fn test_describe_attribute_value() {
+        assert_eq!(
+            describe_attribute_value(&Bytes::from(vec![0x0b, 0xb8])),
+            "0x0bb8\n  uint: 3000"
+        );
+        assert_eq!(
+            describe_attribute_value(&Bytes::from(vec![0xff])),
+            "0xff\n  uint: 255\n  int: -1"
+        );
+        assert_eq!(
+            describe_attribute_value(&Bytes::from(vec![0x01])),
+            "0x01\n  bool: true\n  uint: 1"
+        );
+        assert_eq!(
+            describe_attribute_value(&Bytes::from("Weighted".as_bytes().to_vec())),
+            "0x5765696768746564\n  string: \"Weighted\"\n  uint: 6297555546802513252"
+        );
+        assert_eq!(
+            describe_attribute_value(&Bytes::from(r#"["0x0bb8","0xff"]"#.as_bytes().to_vec()))
+                .lines()
+                .nth(1),
+            Some("  list: [3000, -1]")
+        );
+    }
+}
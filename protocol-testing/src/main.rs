@@ -1,6 +1,8 @@
 mod adapter_builder;
+mod cassette;
 mod config;
 mod execution;
+mod report;
 mod rpc;
 mod state_registry;
 mod test_runner;
@@ -16,7 +18,10 @@ use miette::{miette, IntoDiagnostic, WrapErr};
 use tracing_subscriber::EnvFilter;
 use tycho_simulation::tycho_common::dto::Chain;
 
-use crate::test_runner::{TestRunner, TestType, TestTypeFull, TestTypeRange};
+use crate::{
+    cassette::CassetteMode,
+    test_runner::{TestRunner, TestType, TestTypeFull, TestTypeRange},
+};
 
 #[derive(Parser)]
 #[command(version, long_version = Version::clap_long(), subcommand_required = false, arg_required_else_help = true)]
@@ -47,6 +52,9 @@ pub struct FullTestCommand {
 impl FullTestCommand {
     fn run(self) -> miette::Result<()> {
         let args = self.common_args;
+        if args.rpc_url.is_none() {
+            return Err(miette!("RPC_URL is required for the full test"));
+        }
         TestRunner::new(
             TestType::Full(TestTypeFull { initial_block: self.initial_block }),
             args.root_path()?,
@@ -54,6 +62,7 @@ impl FullTestCommand {
             args.package,
             args.db_url,
             args.rpc_url,
+            None,
             args.vm_simulation_traces,
             args.reuse_last_sync,
         )?
@@ -70,18 +79,45 @@ pub struct RangeTestCommand {
     /// If provided, only run the tests with a matching name
     #[arg(long)]
     match_test: Option<String>,
+
+    /// Record the RPC calls of each test to a cassette file, in the `cassettes` directory of the
+    /// package.
+    #[arg(long, conflicts_with = "replay")]
+    record: bool,
+
+    /// Answer the RPC calls of each test from its recorded cassette file instead of the node.
+    /// RPC_URL is not required in this mode.
+    #[arg(long)]
+    replay: bool,
+
+    /// Write a JSON report and a JUnit XML report of the tests to this path, with the `json` and
+    /// `xml` extensions.
+    #[arg(long)]
+    report: Option<PathBuf>,
 }
 
 impl RangeTestCommand {
     fn run(self) -> miette::Result<()> {
+        let cassette_mode = match (self.record, self.replay) {
+            (true, _) => Some(CassetteMode::Record),
+            (_, true) => Some(CassetteMode::Replay),
+            _ => None,
+        };
+        if cassette_mode != Some(CassetteMode::Replay) && self.common_args.rpc_url.is_none() {
+            return Err(miette!("RPC_URL is required unless running with --replay"));
+        }
         let args = self.common_args;
         TestRunner::new(
-            TestType::Range(TestTypeRange { match_test: self.match_test.clone() }),
+            TestType::Range(TestTypeRange {
+                match_test: self.match_test.clone(),
+                report_path: self.report.clone(),
+            }),
             args.root_path()?,
             args.chain,
             args.package,
             args.db_url,
             args.rpc_url,
+            cassette_mode,
             args.vm_simulation_traces,
             args.reuse_last_sync,
         )?
@@ -111,8 +147,9 @@ struct CommonArgs {
     )]
     db_url: String,
 
+    /// Node RPC URL. Required unless replaying recorded RPC calls.
     #[arg(long, env = "RPC_URL")]
-    rpc_url: String,
+    rpc_url: Option<String>,
 
     /// Enable tracing during vm simulations
     #[arg(long, default_value_t = false)]
@@ -0,0 +1,506 @@
+//! Machine-readable reports of range test runs.
+//!
+//! A `RunReport` collects, for each test, the result and duration of each step, and for each
+//! expected component the result of each step, its state diff and its simulated and executed
+//! swaps. It is written both as a JSON report and as JUnit XML for CI dashboards.
+use std::{
+    fmt::Write as _,
+    path::Path,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use itertools::Itertools;
+use miette::{IntoDiagnostic, WrapErr};
+use regex::Regex;
+use serde::Serialize;
+
+static ANSI_ESCAPE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*m").unwrap());
+
+/// A step of a range test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    StateValidation,
+    BalanceCheck,
+    Simulation,
+    Execution,
+}
+
+impl Step {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::StateValidation => "state_validation",
+            Step::BalanceCheck => "balance_check",
+            Step::Simulation => "simulation",
+            Step::Execution => "execution",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// Result of a step of a test.
+#[derive(Debug, Serialize)]
+pub struct StepReport {
+    pub step: Step,
+    pub status: Status,
+    pub duration_secs: f64,
+    pub error: Option<String>,
+}
+
+/// Result of a step for a single component.
+#[derive(Debug, Serialize)]
+pub struct ComponentStepReport {
+    pub step: Step,
+    pub status: Status,
+    pub error: Option<String>,
+}
+
+/// A simulated swap and, if it was executed, its execution result.
+#[derive(Debug, Serialize)]
+pub struct SwapReport {
+    pub simulation_id: String,
+    pub token_in: String,
+    pub token_out: String,
+    pub amount_in: String,
+    pub simulated_amount_out: String,
+    pub gas: String,
+    /// `None` if the swap wasn't executed.
+    pub execution_status: Option<Status>,
+    pub executed_amount_out: Option<String>,
+    /// Relative difference between the simulated and executed amounts out.
+    pub difference: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Spot price of a token pair, compared to the marginal price of a tiny swap.
+#[derive(Debug, Serialize)]
+pub struct SpotPriceReport {
+    pub base: String,
+    pub quote: String,
+    pub spot_price: f64,
+    /// Price of a tiny swap of `base` for `quote`, `None` if it couldn't be simulated.
+    pub marginal_price: Option<f64>,
+    /// Relative difference between the spot and marginal prices.
+    pub deviation: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentReport {
+    pub id: String,
+    pub steps: Vec<ComponentStepReport>,
+    /// Difference between the expected and indexed component, if they don't match.
+    pub state_diff: Option<String>,
+    pub spot_prices: Vec<SpotPriceReport>,
+    pub swaps: Vec<SwapReport>,
+}
+
+impl ComponentReport {
+    /// Records the result of a step for this component, replacing any previous result.
+    pub fn record_step(&mut self, step: Step, result: Result<(), String>) {
+        self.steps.retain(|s| s.step != step);
+        self.steps.push(ComponentStepReport {
+            step,
+            status: if result.is_ok() { Status::Passed } else { Status::Failed },
+            error: result.err(),
+        });
+    }
+
+    pub fn skip_step(&mut self, step: Step) {
+        self.steps.retain(|s| s.step != step);
+        self.steps
+            .push(ComponentStepReport { step, status: Status::Skipped, error: None });
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestReport {
+    pub name: String,
+    pub status: Status,
+    pub duration_secs: f64,
+    pub error: Option<String>,
+    pub steps: Vec<StepReport>,
+    pub components: Vec<ComponentReport>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl TestReport {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Skipped,
+            duration_secs: 0.0,
+            error: None,
+            steps: Vec::new(),
+            components: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Records the result of a step of the test.
+    pub fn record_step<T>(&mut self, step: Step, duration: Duration, result: &miette::Result<T>) {
+        self.steps.push(StepReport {
+            step,
+            status: if result.is_ok() { Status::Passed } else { Status::Failed },
+            duration_secs: duration.as_secs_f64(),
+            error: result.as_ref().err().map(error_message),
+        });
+    }
+
+    pub fn skip_step(&mut self, step: Step) {
+        self.steps.push(StepReport {
+            step,
+            status: Status::Skipped,
+            duration_secs: 0.0,
+            error: None,
+        });
+    }
+
+    /// Returns the report of a component, creating it if needed.
+    pub fn component(&mut self, id: &str) -> &mut ComponentReport {
+        let id = id.to_lowercase();
+        match self
+            .components
+            .iter()
+            .position(|c| c.id == id)
+        {
+            Some(index) => &mut self.components[index],
+            None => {
+                self.components.push(ComponentReport {
+                    id,
+                    steps: Vec::new(),
+                    state_diff: None,
+                    spot_prices: Vec::new(),
+                    swaps: Vec::new(),
+                });
+                self.components
+                    .last_mut()
+                    .expect("component was just added")
+            }
+        }
+    }
+
+    /// Returns the report of a simulated swap.
+    pub fn swap(&mut self, simulation_id: &str) -> Option<&mut SwapReport> {
+        self.components
+            .iter_mut()
+            .flat_map(|c| c.swaps.iter_mut())
+            .find(|s| s.simulation_id == simulation_id)
+    }
+
+    /// Records the final result of the test and its total duration.
+    pub fn finish(&mut self, result: &miette::Result<()>) {
+        self.duration_secs = self.started.elapsed().as_secs_f64();
+        self.status = if result.is_ok() { Status::Passed } else { Status::Failed };
+        self.error = result.as_ref().err().map(error_message);
+    }
+}
+
+/// Report of all the tests of a run.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub protocol_system: String,
+    pub chain: String,
+    pub passed: usize,
+    pub failed: usize,
+    pub duration_secs: f64,
+    pub tests: Vec<TestReport>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl RunReport {
+    pub fn new(protocol_system: &str, chain: &str) -> Self {
+        Self {
+            protocol_system: protocol_system.to_string(),
+            chain: chain.to_string(),
+            passed: 0,
+            failed: 0,
+            duration_secs: 0.0,
+            tests: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn add_test(&mut self, test: TestReport) {
+        match test.status {
+            Status::Failed => self.failed += 1,
+            _ => self.passed += 1,
+        }
+        self.duration_secs = self.started.elapsed().as_secs_f64();
+        self.tests.push(test);
+    }
+
+    /// Writes the JSON report and the JUnit XML report next to each other, at `path` with the
+    /// `json` and `xml` extensions.
+    ///
+    /// # Errors
+    /// Returns an error if a report can't be written.
+    pub fn write(&self, path: &Path) -> miette::Result<()> {
+        if let Some(dir) = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(dir)
+                .into_diagnostic()
+                .wrap_err("Failed to create report directory")?;
+        }
+        let json_path = path.with_extension("json");
+        let json = serde_json::to_string_pretty(self).into_diagnostic()?;
+        std::fs::write(&json_path, json)
+            .into_diagnostic()
+            .wrap_err(format!("Failed to write report {}", json_path.display()))?;
+
+        let xml_path = path.with_extension("xml");
+        std::fs::write(&xml_path, self.to_junit_xml())
+            .into_diagnostic()
+            .wrap_err(format!("Failed to write report {}", xml_path.display()))?;
+        Ok(())
+    }
+
+    /// Renders the report as JUnit XML.
+    ///
+    /// Each test is a test suite, with a test case per step and per component step. Components
+    /// are reported with the `<test>.<component id>` class name.
+    pub fn to_junit_xml(&self) -> String {
+        let suites = self
+            .tests
+            .iter()
+            .map(test_suite)
+            .collect::<Vec<_>>();
+        let count = |status| {
+            suites
+                .iter()
+                .flat_map(|suite| suite.cases.iter())
+                .filter(|case| case.status == status)
+                .count()
+        };
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            r#"<testsuites name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+            escape_xml(&self.protocol_system),
+            suites
+                .iter()
+                .map(|suite| suite.cases.len())
+                .sum::<usize>(),
+            count(Status::Failed),
+            count(Status::Skipped),
+            self.duration_secs,
+        );
+        for suite in suites {
+            suite.write(&mut xml);
+        }
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+}
+
+struct TestCase {
+    class_name: String,
+    name: String,
+    status: Status,
+    duration_secs: f64,
+    error: Option<String>,
+}
+
+struct TestSuite<'a> {
+    test: &'a TestReport,
+    cases: Vec<TestCase>,
+}
+
+impl TestSuite<'_> {
+    fn write(&self, xml: &mut String) {
+        let count = |status| {
+            self.cases
+                .iter()
+                .filter(|case| case.status == status)
+                .count()
+        };
+        let _ = writeln!(
+            xml,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+            escape_xml(&self.test.name),
+            self.cases.len(),
+            count(Status::Failed),
+            count(Status::Skipped),
+            self.test.duration_secs,
+        );
+        for case in &self.cases {
+            let _ = write!(
+                xml,
+                r#"    <testcase classname="{}" name="{}" time="{:.3}""#,
+                escape_xml(&case.class_name),
+                escape_xml(&case.name),
+                case.duration_secs,
+            );
+            match (case.status, &case.error) {
+                (Status::Failed, error) => {
+                    let error = error.as_deref().unwrap_or("failed");
+                    let message = error.lines().next().unwrap_or_default();
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                        escape_xml(message),
+                        escape_xml(error),
+                    );
+                }
+                (Status::Skipped, _) => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+                (Status::Passed, _) => xml.push_str("/>\n"),
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+}
+
+fn test_suite(test: &TestReport) -> TestSuite<'_> {
+    let mut cases = test
+        .steps
+        .iter()
+        .map(|step| TestCase {
+            class_name: test.name.clone(),
+            name: step.step.name().to_string(),
+            status: step.status,
+            duration_secs: step.duration_secs,
+            error: step.error.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    // Errors outside of steps, e.g. while indexing or fetching the indexed state.
+    if test.status == Status::Failed &&
+        !test
+            .steps
+            .iter()
+            .any(|step| step.status == Status::Failed)
+    {
+        cases.push(TestCase {
+            class_name: test.name.clone(),
+            name: "setup".to_string(),
+            status: Status::Failed,
+            duration_secs: test.duration_secs,
+            error: test.error.clone(),
+        });
+    }
+
+    for component in &test.components {
+        let class_name = format!("{}.{}", test.name, component.id);
+        for step in &component.steps {
+            let mut error = step.error.clone();
+            if step.step == Step::StateValidation {
+                error = error.map(|error| match &component.state_diff {
+                    Some(diff) => format!("{error}\n{diff}"),
+                    None => error,
+                });
+            }
+            cases.push(TestCase {
+                class_name: class_name.clone(),
+                name: step.step.name().to_string(),
+                status: step.status,
+                duration_secs: 0.0,
+                error,
+            });
+        }
+    }
+    TestSuite { test, cases }
+}
+
+/// Formats an error with its causes, without terminal colors.
+pub fn error_message(error: &miette::Report) -> String {
+    let message = error
+        .chain()
+        .map(|cause| cause.to_string())
+        .join(": ");
+    ANSI_ESCAPE
+        .replace_all(&message, "")
+        .into_owned()
+}
+
+/// Escapes text for XML attributes and content, dropping characters XML can't represent.
+fn escape_xml(text: &str) -> String {
+    let text = ANSI_ESCAPE.replace_all(text, "");
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' | '\t' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::miette;
+
+    use super::*;
+
+    fn report() -> RunReport {
+        let mut test = TestReport::new("test_pool_creation");
+        test.record_step(Step::StateValidation, Duration::from_millis(1500), &Ok(()));
+        test.skip_step(Step::BalanceCheck);
+        test.record_step(
+            Step::Simulation,
+            Duration::from_millis(250),
+            &Err::<(), _>(miette!("\x1b[31mAmount in\x1b[0m is zero for pool <0xabc>")),
+        );
+        test.component("0xABC")
+            .record_step(Step::Simulation, Err("Amount in is zero".to_string()));
+        test.component("0xabc")
+            .skip_step(Step::Execution);
+        test.finish(&Err(miette!("Simulation failed")));
+
+        let mut run = RunReport::new("uniswap_v2", "ethereum");
+        run.add_test(test);
+        run
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let xml = report().to_junit_xml();
+
+        assert!(xml.contains(r#"tests="5" failures="2" skipped="2""#));
+        assert!(xml.contains(
+            r#"<testcase classname="test_pool_creation" name="state_validation" time="1.500"/>"#
+        ));
+        assert!(xml.contains(concat!(
+            r#"<failure message="Amount in is zero for pool &lt;0xabc&gt;">"#,
+            "Amount in is zero for pool &lt;0xabc&gt;</failure>"
+        )));
+        let component_case = r#"<testcase classname="test_pool_creation.0xabc" name="simulation""#;
+        assert!(xml.contains(component_case));
+        assert!(!xml.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_json_report() {
+        let json = serde_json::to_value(report()).unwrap();
+
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["tests"][0]["status"], "failed");
+        assert_eq!(json["tests"][0]["steps"][1]["status"], "skipped");
+        assert_eq!(
+            json["tests"][0]["components"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(json["tests"][0]["components"][0]["steps"][1]["step"], "execution");
+    }
+}
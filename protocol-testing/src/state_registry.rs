@@ -1,3 +1,8 @@
+use std::{fmt, str::FromStr};
+
+use miette::miette;
+use serde::{Deserialize, Serialize};
+use tracing::info;
 use tycho_simulation::{
     evm::{
         engine_db::tycho_db::PreCachedDB,
@@ -13,68 +18,229 @@ use tycho_simulation::{
     tycho_client::feed::component_tracker::ComponentFilter,
 };
 
-/// Register decoder based on protocol system. Defaults to EVMPoolState.
-/// To add a new protocol, just add a case to the match statement.
-pub fn register_protocol(
-    stream_builder: ProtocolStreamBuilder,
-    protocol_system: &str,
-    decoder_context: DecoderContext,
-) -> miette::Result<ProtocolStreamBuilder> {
-    let tvl_filter = ComponentFilter::with_tvl_range(100.0, 100.0);
-    let stream_builder = match protocol_system {
-        "uniswap_v2" | "sushiswap_v2" => stream_builder
-            .exchange_with_decoder_context::<UniswapV2State>(
+use crate::config::IntegrationTestsConfig;
+
+/// State decoder used to simulate the components of a protocol.
+///
+/// Selected with `decoder` in `integration_test.tycho.yaml`. If not set, it is inferred from the
+/// protocol system, defaulting to `vm`.
+/// To add a new protocol, add a variant here along with its name and its case in `register`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Decoder {
+    UniswapV2,
+    PancakeswapV2,
+    UniswapV3,
+    UniswapV4,
+    EkuboV2,
+    EkuboV3,
+    Rocketpool,
+    Vm,
+}
+
+impl Decoder {
+    pub const ALL: [Decoder; 8] = [
+        Decoder::UniswapV2,
+        Decoder::PancakeswapV2,
+        Decoder::UniswapV3,
+        Decoder::UniswapV4,
+        Decoder::EkuboV2,
+        Decoder::EkuboV3,
+        Decoder::Rocketpool,
+        Decoder::Vm,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Decoder::UniswapV2 => "uniswap_v2",
+            Decoder::PancakeswapV2 => "pancakeswap_v2",
+            Decoder::UniswapV3 => "uniswap_v3",
+            Decoder::UniswapV4 => "uniswap_v4",
+            Decoder::EkuboV2 => "ekubo_v2",
+            Decoder::EkuboV3 => "ekubo_v3",
+            Decoder::Rocketpool => "rocketpool",
+            Decoder::Vm => "vm",
+        }
+    }
+
+    /// Returns the decoder of a protocol system natively supported by Tycho Simulation, or the VM
+    /// decoder otherwise.
+    pub fn for_protocol_system(protocol_system: &str) -> Self {
+        match protocol_system {
+            "uniswap_v2" | "sushiswap_v2" => Decoder::UniswapV2,
+            "pancakeswap_v2" => Decoder::PancakeswapV2,
+            "uniswap_v3" | "pancakeswap_v3" => Decoder::UniswapV3,
+            "uniswap_v4" | "uniswap_v4_hooks" => Decoder::UniswapV4,
+            "ekubo_v2" => Decoder::EkuboV2,
+            "ekubo_v3" => Decoder::EkuboV3,
+            "rocketpool" => Decoder::Rocketpool,
+            _ => Decoder::Vm,
+        }
+    }
+
+    /// Registers the exchange of `protocol_system` with this decoder.
+    fn register(
+        &self,
+        stream_builder: ProtocolStreamBuilder,
+        protocol_system: &str,
+        tvl_filter: ComponentFilter,
+        decoder_context: DecoderContext,
+    ) -> ProtocolStreamBuilder {
+        match self {
+            Decoder::UniswapV2 => stream_builder.exchange_with_decoder_context::<UniswapV2State>(
+                protocol_system,
+                tvl_filter,
+                None,
+                decoder_context,
+            ),
+            Decoder::PancakeswapV2 => stream_builder
+                .exchange_with_decoder_context::<PancakeswapV2State>(
+                    protocol_system,
+                    tvl_filter,
+                    None,
+                    decoder_context,
+                ),
+            Decoder::UniswapV3 => stream_builder.exchange_with_decoder_context::<UniswapV3State>(
+                protocol_system,
+                tvl_filter,
+                None,
+                decoder_context,
+            ),
+            Decoder::UniswapV4 => stream_builder.exchange_with_decoder_context::<UniswapV4State>(
+                protocol_system,
+                tvl_filter,
+                None,
+                decoder_context,
+            ),
+            Decoder::EkuboV2 => stream_builder.exchange_with_decoder_context::<EkuboState>(
                 protocol_system,
                 tvl_filter,
                 None,
                 decoder_context,
             ),
-        "pancakeswap_v2" => stream_builder.exchange_with_decoder_context::<PancakeswapV2State>(
-            protocol_system,
-            tvl_filter,
-            None,
-            decoder_context,
-        ),
-        "uniswap_v3" | "pancakeswap_v3" => stream_builder
-            .exchange_with_decoder_context::<UniswapV3State>(
+            Decoder::EkuboV3 => stream_builder.exchange_with_decoder_context::<EkuboV3State>(
                 protocol_system,
                 tvl_filter,
                 None,
                 decoder_context,
             ),
-        "ekubo_v2" => stream_builder.exchange_with_decoder_context::<EkuboState>(
-            protocol_system,
-            tvl_filter,
-            None,
-            decoder_context,
-        ),
-        "uniswap_v4" | "uniswap_v4_hooks" => stream_builder
-            .exchange_with_decoder_context::<UniswapV4State>(
+            Decoder::Rocketpool => stream_builder.exchange_with_decoder_context::<RocketpoolState>(
                 protocol_system,
                 tvl_filter,
                 None,
                 decoder_context,
             ),
-        "rocketpool" => stream_builder.exchange_with_decoder_context::<RocketpoolState>(
-            protocol_system,
-            tvl_filter,
-            None,
-            decoder_context,
-        ),
-        "ekubo_v3" => stream_builder.exchange_with_decoder_context::<EkuboV3State>(
-            protocol_system,
-            tvl_filter,
-            None,
-            decoder_context,
-        ),
-        // Default to EVMPoolState for all other protocols
-        _ => stream_builder.exchange_with_decoder_context::<EVMPoolState<PreCachedDB>>(
-            protocol_system,
-            tvl_filter,
-            None,
-            decoder_context,
-        ),
-    };
-
-    Ok(stream_builder)
+            Decoder::Vm => stream_builder
+                .exchange_with_decoder_context::<EVMPoolState<PreCachedDB>>(
+                    protocol_system,
+                    tvl_filter,
+                    None,
+                    decoder_context,
+                ),
+        }
+    }
+}
+
+impl fmt::Display for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Decoder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Decoder::ALL
+            .into_iter()
+            .find(|decoder| decoder.name() == s)
+            .ok_or_else(|| {
+                let supported = Decoder::ALL
+                    .iter()
+                    .map(Decoder::name)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("unknown decoder '{s}', supported decoders are: {supported}")
+            })
+    }
+}
+
+impl TryFrom<String> for Decoder {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Decoder> for String {
+    fn from(value: Decoder) -> Self {
+        value.name().to_string()
+    }
+}
+
+/// TVL thresholds, in native token, of the components tracked by the protocol stream.
+///
+/// Components are added once their TVL goes above `max` and removed once it drops below `min`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct TvlFilter {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for TvlFilter {
+    fn default() -> Self {
+        Self { min: 100.0, max: 100.0 }
+    }
+}
+
+/// Registers the protocol of the config with its decoder and TVL filter.
+///
+/// The decoder is `decoder` if set in the config, otherwise it is inferred from the protocol
+/// system, see [`Decoder::for_protocol_system`].
+pub fn register_protocol(
+    stream_builder: ProtocolStreamBuilder,
+    config: &IntegrationTestsConfig,
+    decoder_context: DecoderContext,
+) -> miette::Result<ProtocolStreamBuilder> {
+    let protocol_system = config.protocol_system.as_str();
+    let decoder = config
+        .decoder
+        .unwrap_or_else(|| Decoder::for_protocol_system(protocol_system));
+    let tvl_filter = config.tvl_filter.unwrap_or_default();
+    if tvl_filter.min > tvl_filter.max {
+        return Err(miette!(
+            "Invalid TVL filter for {protocol_system}: min {} is greater than max {}.",
+            tvl_filter.min,
+            tvl_filter.max
+        ));
+    }
+    info!("Registering {protocol_system} with the {decoder} decoder");
+
+    Ok(decoder.register(
+        stream_builder,
+        protocol_system,
+        ComponentFilter::with_tvl_range(tvl_filter.min, tvl_filter.max),
+        decoder_context,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder_from_str() {
+        for decoder in Decoder::ALL {
+            assert_eq!(decoder.name().parse::<Decoder>(), Ok(decoder));
+        }
+        assert_eq!(Decoder::for_protocol_system("sushiswap_v2"), Decoder::UniswapV2);
+        assert_eq!(Decoder::for_protocol_system("vm:balancer_v2"), Decoder::Vm);
+        assert_eq!(
+            "curve".parse::<Decoder>(),
+            Err("unknown decoder 'curve', supported decoders are: uniswap_v2, pancakeswap_v2, \
+                 uniswap_v3, uniswap_v4, ekubo_v2, ekubo_v3, rocketpool, vm"
+                .to_string())
+        );
+    }
 }
@@ -3,12 +3,14 @@ use std::{
     path::PathBuf,
     str::FromStr,
     sync::{Arc, LazyLock, RwLock},
+    time::Instant,
 };
 
 use alloy::{
     eips::BlockNumberOrTag,
     primitives::{Address, U256},
     rpc::types::Block,
+    transports::http::reqwest::Url,
 };
 use figment::{
     providers::{Format, Yaml},
@@ -42,6 +44,7 @@ use tycho_simulation::{
     tycho_common::{
         dto::{Chain, ProtocolComponent, ResponseProtocolState},
         models::{token::Token, Chain as ChainModel},
+        simulation::protocol_sim::ProtocolSim,
         Bytes,
     },
 };
@@ -56,8 +59,16 @@ use tycho_test::{
 
 use crate::{
     adapter_builder::AdapterContractBuilder,
-    config::{IntegrationTest, IntegrationTestsConfig, ProtocolComponentWithTestConfig},
+    cassette::{CassetteMode, CassetteServer},
+    config::{
+        component_simulation_config, IntegrationTest, IntegrationTestsConfig,
+        ProtocolComponentWithTestConfig, SimulationAmount, SimulationConfig,
+    },
     execution,
+    report::{
+        error_message, ComponentReport, RunReport, SpotPriceReport, Status, Step, SwapReport,
+        TestReport,
+    },
     rpc::RPCProvider,
     state_registry::register_protocol,
     tycho_rpc::TychoClient,
@@ -72,6 +83,13 @@ static CLONE_TO_BASE_PROTOCOL: LazyLock<HashMap<&str, &str>> = LazyLock::new(||
     ])
 });
 
+/// Fraction of the max input swapped to compute the marginal price of a pair.
+const MARGINAL_PRICE_DIVISOR: u64 = 1_000_000;
+
+/// Relative difference between the spot price and the marginal price above which a warning is
+/// logged. Only reported, as spot prices may or may not include fees depending on the protocol.
+const SPOT_PRICE_TOLERANCE: f64 = 0.05;
+
 pub enum TestType {
     Full(TestTypeFull),
     Range(TestTypeRange),
@@ -83,6 +101,8 @@ pub struct TestTypeFull {
 
 pub struct TestTypeRange {
     pub match_test: Option<String>,
+    /// Path to write the JSON and JUnit XML reports to, without extension.
+    pub report_path: Option<PathBuf>,
 }
 
 pub struct TestRunner {
@@ -95,6 +115,11 @@ pub struct TestRunner {
     adapter_contract_builder: AdapterContractBuilder,
     runtime: Runtime,
     rpc_provider: RPCProvider,
+    /// Local stand-in recording or replaying the RPC calls, if enabled.
+    cassette: Option<CassetteServer>,
+    cassette_dir: PathBuf,
+    /// Prefix of the cassette file names, set for clones of a protocol as they share its package.
+    cassette_prefix: Option<String>,
     protocol_components: Arc<RwLock<HashMap<String, ProtocolComponentModel>>>,
     reuse_last_sync: bool,
 }
@@ -107,7 +132,8 @@ impl TestRunner {
         chain: Chain,
         protocol: String,
         db_url: String,
-        rpc_url: String,
+        rpc_url: Option<String>,
+        cassette_mode: Option<CassetteMode>,
         vm_simulation_traces: bool,
         reuse_last_sync: bool,
     ) -> miette::Result<Self> {
@@ -135,9 +161,25 @@ impl TestRunner {
             "integration_test.tycho.yaml".to_string()
         };
         let config_file_path = substreams_path.join(&config_file_name);
+        let cassette_dir = substreams_path.join("cassettes");
+        let cassette_prefix = (protocol != base_protocol).then(|| protocol.clone());
 
-        let rpc_provider = RPCProvider::new(rpc_url);
         let runtime = Runtime::new().into_diagnostic()?;
+        let upstream_url = rpc_url
+            .map(|url| url.parse::<Url>())
+            .transpose()
+            .into_diagnostic()
+            .wrap_err("Invalid RPC URL")?;
+        let cassette = cassette_mode
+            .map(|mode| runtime.block_on(CassetteServer::start(mode, upstream_url.clone())))
+            .transpose()?;
+        // When recording or replaying, all RPC calls go through the cassette server.
+        let rpc_url = match (&cassette, upstream_url) {
+            (Some(cassette), _) => cassette.url().clone(),
+            (None, Some(url)) => url,
+            (None, None) => return Err(miette!("An RPC URL is required")),
+        };
+        let rpc_provider = RPCProvider::new(rpc_url.to_string());
 
         Ok(Self {
             test_type,
@@ -149,6 +191,9 @@ impl TestRunner {
             adapter_contract_builder,
             runtime,
             rpc_provider,
+            cassette,
+            cassette_dir,
+            cassette_prefix,
             reuse_last_sync,
             protocol_components: Arc::new(RwLock::new(HashMap::new())),
         })
@@ -205,6 +250,9 @@ impl TestRunner {
             .extract::<IntegrationTestsConfig>()
             .into_diagnostic()
             .wrap_err("Failed to load test configuration:")?;
+        config
+            .validate()
+            .map_err(|e| miette!("Invalid test configuration: {e}"))?;
         Ok(config)
     }
 
@@ -306,7 +354,7 @@ impl TestRunner {
             decoder_context = decoder_context.vm_adapter_path(vm_adapter_path);
         }
         let protocol_stream_builder =
-            register_protocol(protocol_stream_builder, &config.protocol_system, decoder_context)?;
+            register_protocol(protocol_stream_builder, config, decoder_context)?;
 
         let stream_builder = protocol_stream_builder
             .skip_state_decode_failures(true)
@@ -367,12 +415,17 @@ impl TestRunner {
                         }
                     };
 
+                    // Reports are only written for range tests
+                    let mut report = TestReport::new(&config.protocol_system);
+
                     // Step 1: Run simulation get amount out
                     let execution_data = match self.run_simulation(
                         &update,
                         &protocol_components,
                         &[], // No skip filters for live testing
                         &config.protocol_system,
+                        &config.simulation,
+                        &mut report,
                     ) {
                         Ok(data) => data,
                         Err(e) => {
@@ -418,8 +471,9 @@ impl TestRunner {
                         .run_execution(
                             execution_data,
                             &block,
-                            &config.protocol_system,
+                            config,
                             &[], // No skip filters for live testing
+                            &mut report,
                         )
                         .await
                     {
@@ -473,46 +527,15 @@ impl TestRunner {
 
         let mut failed_tests: Vec<String> = Vec::new();
         let mut count = 1;
+        let mut run_report = RunReport::new(&config.protocol_system, &self.chain.to_string());
 
         for test in &tests {
             info!("TEST {}: {}", count, test.name);
-            let mut raw_initialized_accounts = config
-                .initialized_accounts
-                .clone()
-                .unwrap_or_default();
-            raw_initialized_accounts.extend(
-                test.initialized_accounts
-                    .clone()
-                    .unwrap_or_default(),
-            );
-            let initialized_accounts: Vec<Bytes> = raw_initialized_accounts
-                .iter()
-                .map(|account| {
-                    Bytes::from_str(account).expect("Invalid initialized_account address")
-                })
-                .collect();
-            let tycho_runner = self
-                .runtime
-                .block_on(self.tycho_runner(&raw_initialized_accounts))?;
-            if self.reuse_last_sync {
-                info!("Skipping indexing and using existent DB")
-            } else {
-                let spkg_path = build_spkg(substreams_yaml_path, test.start_block)
-                    .wrap_err("Failed to build spkg")?;
-
-                tycho_runner
-                    .run_tycho(
-                        &spkg_path,
-                        test.start_block,
-                        test.stop_block,
-                        &config.protocol_type_names,
-                        &config.protocol_system,
-                        config.module_name.clone(),
-                    )
-                    .wrap_err("Failed to run Tycho")?;
-            }
-            let rpc_server = tycho_runner.start_rpc_server()?;
-            match self.run_test(test, &config, test.stop_block, &initialized_accounts) {
+            let mut test_report = TestReport::new(&test.name);
+            let result = self.run_range_test(test, &config, substreams_yaml_path, &mut test_report);
+            test_report.finish(&result);
+            run_report.add_test(test_report);
+            match result {
                 Ok(_) => {
                     info!("✅ {} passed\n", test.name);
                 }
@@ -521,13 +544,16 @@ impl TestRunner {
                     error!("❗️{} failed: {:#}\n", test.name, e);
                 }
             }
-            tycho_runner.stop_rpc_server(rpc_server)?;
             info!("{}\n", "-".repeat(terminal_width));
             count += 1;
         }
 
         info!("Tests finished!");
         info!("Passed {}/{}\n", tests_count - failed_tests.len(), tests_count);
+        if let Some(report_path) = &test_type.report_path {
+            run_report.write(report_path)?;
+            info!("Test report written to {}", report_path.display());
+        }
         if !failed_tests.is_empty() {
             Err(miette!("Failed tests: {}", failed_tests.join(", ")))
         } else {
@@ -535,12 +561,82 @@ impl TestRunner {
         }
     }
 
+    /// Indexes the block range of a test with Tycho and runs the test against the indexed state,
+    /// using the test's cassette if RPC calls are recorded or replayed.
+    ///
+    /// Errors while setting up the test, e.g. building the spkg or indexing, fail the test like
+    /// any other error, so they are part of the report.
+    fn run_range_test(
+        &self,
+        test: &IntegrationTest,
+        config: &IntegrationTestsConfig,
+        substreams_yaml_path: &PathBuf,
+        report: &mut TestReport,
+    ) -> miette::Result<()> {
+        if let Some(cassette) = &self.cassette {
+            cassette.insert(&self.cassette_path(&test.name))?;
+        }
+        let result = self.index_and_run_test(test, config, substreams_yaml_path, report);
+        let ejected = match &self.cassette {
+            Some(cassette) => cassette.eject(),
+            None => Ok(()),
+        };
+        result.and(ejected)
+    }
+
+    fn index_and_run_test(
+        &self,
+        test: &IntegrationTest,
+        config: &IntegrationTestsConfig,
+        substreams_yaml_path: &PathBuf,
+        report: &mut TestReport,
+    ) -> miette::Result<()> {
+        let mut raw_initialized_accounts = config
+            .initialized_accounts
+            .clone()
+            .unwrap_or_default();
+        raw_initialized_accounts.extend(
+            test.initialized_accounts
+                .clone()
+                .unwrap_or_default(),
+        );
+        let initialized_accounts: Vec<Bytes> = raw_initialized_accounts
+            .iter()
+            .map(|account| Bytes::from_str(account).expect("Invalid initialized_account address"))
+            .collect();
+        let tycho_runner = self
+            .runtime
+            .block_on(self.tycho_runner(&raw_initialized_accounts))?;
+        if self.reuse_last_sync {
+            info!("Skipping indexing and using existent DB")
+        } else {
+            let spkg_path = build_spkg(substreams_yaml_path, test.start_block)
+                .wrap_err("Failed to build spkg")?;
+
+            tycho_runner
+                .run_tycho(
+                    &spkg_path,
+                    test.start_block,
+                    test.stop_block,
+                    &config.protocol_type_names,
+                    &config.protocol_system,
+                    config.module_name.clone(),
+                )
+                .wrap_err("Failed to run Tycho")?;
+        }
+        let rpc_server = tycho_runner.start_rpc_server()?;
+        let result = self.run_test(test, config, test.stop_block, &initialized_accounts, report);
+        tycho_runner.stop_rpc_server(rpc_server)?;
+        result
+    }
+
     fn run_test(
         &self,
         test: &IntegrationTest,
         config: &IntegrationTestsConfig,
         stop_block: u64,
         initialized_accounts: &[Bytes],
+        report: &mut TestReport,
     ) -> miette::Result<()> {
         // Fetch protocol data from Tycho RPC
         let expected_ids = test
@@ -585,17 +681,27 @@ impl TestRunner {
             .collect();
 
         // Step 1: Validate that all expected components are present on Tycho after indexing
-        self.validate_state(&test.expected_components, protocol_components)?;
+        let started = Instant::now();
+        let result = self.validate_state(&test.expected_components, protocol_components, report);
+        report.record_step(Step::StateValidation, started.elapsed(), &result);
+        result?;
 
         // Step 2: Validate Token Balances
         match config.skip_balance_check {
-            true => info!("Skipping balance check"),
+            true => {
+                info!("Skipping balance check");
+                report.skip_step(Step::BalanceCheck);
+            }
             false => {
-                self.validate_token_balances(
+                let started = Instant::now();
+                let result = self.validate_token_balances(
                     &tokens_by_component,
                     &response_protocol_states_by_id,
                     stop_block,
-                )?;
+                    report,
+                );
+                report.record_step(Step::BalanceCheck, started.elapsed(), &result);
+                result?;
                 info!("All token balances match the values found onchain")
             }
         }
@@ -608,7 +714,7 @@ impl TestRunner {
         )?;
 
         let update = self.decode_snapshot(
-            &config.protocol_system,
+            config,
             &block,
             snapshot,
             all_tokens,
@@ -620,23 +726,31 @@ impl TestRunner {
             update.new_pairs.clone();
 
         // Step 4: Run Tycho Simulation
-        let execution_data = self.run_simulation(
+        let started = Instant::now();
+        let result = self.run_simulation(
             &update,
             &protocol_components_simulation,
             &test.expected_components,
             &config.protocol_system,
-        )?;
+            &config.simulation,
+            report,
+        );
+        report.record_step(Step::Simulation, started.elapsed(), &result);
+        let execution_data = result?;
 
         // Step 5: Run Tycho Execution
-        self.runtime
+        let started = Instant::now();
+        let result = self
+            .runtime
             .block_on(self.run_execution(
                 execution_data,
                 &block,
-                &config.protocol_system,
+                config,
                 &test.expected_components,
-            ))?;
-
-        Ok(())
+                report,
+            ));
+        report.record_step(Step::Execution, started.elapsed(), &result);
+        result
     }
 
     async fn empty_database(&self) -> Result<(), tokio_postgres::Error> {
@@ -680,7 +794,28 @@ impl TestRunner {
                 .into_diagnostic()
                 .wrap_err("Failed to empty the database")?;
         }
-        Ok(TychoRunner::new(self.chain, self.db_url.to_string(), initialized_accounts.to_vec()))
+        // The indexer's RPC calls go through the cassette server too when recording or replaying,
+        // as `rpc_provider` points to it then.
+        Ok(TychoRunner::new(
+            self.chain,
+            self.db_url.to_string(),
+            self.rpc_provider.url.to_string(),
+            initialized_accounts.to_vec(),
+        ))
+    }
+
+    /// Path of the RPC cassette of a test.
+    fn cassette_path(&self, test_name: &str) -> PathBuf {
+        let name = match &self.cassette_prefix {
+            Some(prefix) => format!("{prefix}_{test_name}"),
+            None => test_name.to_string(),
+        };
+        let file_name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        self.cassette_dir
+            .join(format!("{file_name}.json"))
     }
 
     fn run_tvl_import(&self) -> miette::Result<()> {
@@ -865,7 +1000,7 @@ impl TestRunner {
 
     fn decode_snapshot(
         &self,
-        protocol_system: &str,
+        config: &IntegrationTestsConfig,
         block: &Block,
         snapshot: Snapshot,
         all_tokens: HashMap<Bytes, Token>,
@@ -886,13 +1021,13 @@ impl TestRunner {
             }
         }
         let protocol_stream_builder =
-            register_protocol(protocol_stream_builder, protocol_system, decoder_context)?;
+            register_protocol(protocol_stream_builder, config, decoder_context)?;
 
         let decoder = protocol_stream_builder.get_decoder();
         initialize_hook_handlers().into_diagnostic()?;
 
         let state_msgs: HashMap<String, StateSyncMessage<BlockHeader>> = HashMap::from([(
-            String::from(protocol_system),
+            config.protocol_system.clone(),
             StateSyncMessage {
                 header: BlockHeader {
                     hash: (*block.hash()).into(),
@@ -949,6 +1084,7 @@ impl TestRunner {
         &self,
         expected_components: &Vec<ProtocolComponentWithTestConfig>,
         protocol_components: Vec<ProtocolComponent>,
+        report: &mut TestReport,
     ) -> miette::Result<()> {
         if expected_components.is_empty() {
             debug!("No expected components defined for this test. Skipping state validation.");
@@ -966,15 +1102,25 @@ impl TestRunner {
                 .id
                 .to_lowercase();
 
-            let component = protocol_components_by_id
-                .get(&component_id)
-                .ok_or_else(|| miette!("Component {:?} was not found on Tycho", component_id))?;
+            let component_report = report.component(&component_id);
+            let Some(component) = protocol_components_by_id.get(&component_id) else {
+                let error = format!("Component {component_id:?} was not found on Tycho");
+                component_report.record_step(Step::StateValidation, Err(error.clone()));
+                return Err(miette!(error));
+            };
 
             let diff = expected_component
                 .base
                 .compare(component, true);
+            component_report.state_diff = expected_component
+                .base
+                .compare(component, false);
             match diff {
                 Some(diff) => {
+                    component_report.record_step(
+                        Step::StateValidation,
+                        Err("Component does not match the expected state".to_string()),
+                    );
                     return Err(miette!(
                         "Component {} does not match the expected state:\n{}",
                         component_id,
@@ -982,6 +1128,7 @@ impl TestRunner {
                     ));
                 }
                 None => {
+                    component_report.record_step(Step::StateValidation, Ok(()));
                     info!("Component {} matches the expected state", component_id);
                 }
             }
@@ -995,8 +1142,9 @@ impl TestRunner {
     /// Runs simulations for all protocol components and swap directions.
     ///
     /// This method performs comprehensive simulation testing on protocol components by:
-    /// 1. Computing spot prices for all token pairs
-    /// 2. Simulating swaps with different input amounts (0.1%, 1%, 10% of limits)
+    /// 1. Computing spot prices for all ordered token pairs, checked against the marginal price of
+    ///    a tiny swap
+    /// 2. Simulating swaps with the configured input amounts (by default 0.1%, 1%, 10% of limits)
     /// 3. Testing all possible swap directions between tokens
     /// 4. Preparing execution data for each simulation
     ///
@@ -1008,6 +1156,8 @@ impl TestRunner {
     /// * `protocol_components` - Mapping of component IDs to their ProtocolComponent models
     /// * `expected_components` - Test configuration to determine which components to skip
     /// * `protocol_system` - The protocol system identifier
+    /// * `simulation_config` - Protocol simulation settings, unless overridden per component
+    /// * `report` - Test report to record the simulated swaps in
     ///
     /// # Returns
     /// Returns a HashMap of simulation IDs to TychoExecutionInput data for execution.
@@ -1025,6 +1175,8 @@ impl TestRunner {
         protocol_components: &HashMap<String, ProtocolComponentModel>,
         expected_components: &[ProtocolComponentWithTestConfig],
         protocol_system: &String,
+        simulation_config: &SimulationConfig,
+        report: &mut TestReport,
     ) -> miette::Result<HashMap<String, TychoExecutionInput>> {
         let skip_simulation: HashSet<_> = expected_components
             .iter()
@@ -1041,142 +1193,249 @@ impl TestRunner {
         let mut execution_data = HashMap::new();
 
         for (id, state) in update.states.iter() {
+            let component_report = report.component(id);
             if skip_simulation.contains(id) {
                 info!("Skipping simulation for component {id}");
+                component_report.skip_step(Step::Simulation);
                 continue;
             }
             let component = protocol_components
                 .get(id)
                 .ok_or_else(|| miette!("Couldn't find protocol component {id}"))?;
 
-            let tokens = component.tokens.clone();
-            let formatted_token_str = format!("{:}/{:}", &tokens[0].symbol, &tokens[1].symbol);
-            state
-                .spot_price(&tokens[0], &tokens[1])
-                .map(|price| info!("[{}] Spot price {:?}: {:?}", id, formatted_token_str, price))
+            let amounts =
+                component_simulation_config(expected_components, simulation_config, id).amounts();
+            let result = self.simulate_component(
+                id,
+                state.as_ref(),
+                component,
+                &amounts,
+                skip_execution.contains(id),
+                protocol_system,
+                component_report,
+                &mut execution_data,
+            );
+            component_report.record_step(
+                Step::Simulation,
+                result
+                    .as_ref()
+                    .map_err(error_message)
+                    .copied(),
+            );
+            result?;
+        }
+
+        Ok(execution_data)
+    }
+
+    /// Returns the price of `token_in` in `token_out` given by swapping a tiny amount, a
+    /// millionth of the max input.
+    ///
+    /// For a consistent state, this should roughly match the spot price. They can still differ by
+    /// the fee, depending on whether the protocol includes it in its spot price.
+    fn marginal_price(
+        state: &dyn ProtocolSim,
+        token_in: &Token,
+        token_out: &Token,
+        max_input: &BigUint,
+    ) -> Result<f64, String> {
+        let amount_in = max_input / BigUint::from(MARGINAL_PRICE_DIVISOR);
+        if amount_in.is_zero() {
+            return Err(format!("max input of {max_input} {} is too small", token_in.symbol));
+        }
+        let amount_out = state
+            .get_amount_out(amount_in.clone(), token_in, token_out)
+            .map_err(|e| e.to_string())?
+            .amount;
+        price(&amount_in, token_in.decimals, &amount_out, token_out.decimals)
+            .ok_or_else(|| format!("could not compute price of {amount_in} -> {amount_out}"))
+    }
+
+    /// Simulates swaps of a single component in all directions, see `run_simulation`.
+    ///
+    /// Simulated swaps are added to the component report, and their execution data to
+    /// `execution_data` unless the component skips execution.
+    #[allow(clippy::too_many_arguments)]
+    fn simulate_component(
+        &self,
+        id: &str,
+        state: &dyn ProtocolSim,
+        component: &ProtocolComponentModel,
+        amounts: &[SimulationAmount],
+        skip_execution: bool,
+        protocol_system: &str,
+        report: &mut ComponentReport,
+        execution_data: &mut HashMap<String, TychoExecutionInput>,
+    ) -> miette::Result<()> {
+        let tokens = component.tokens.clone();
+
+        // Test get_amount_out with the configured amounts, by default percentages of limits. The
+        // reserves or limits are relevant because we need to know how much to test with. We
+        // don't know if a pool is going to revert with 10 or 10 million
+        // USDC, for example, so by using the limits we can use "safe
+        // values" where the sim shouldn't break. We then retrieve the
+        // amount out for each configured amount, 0.1%, 1% and 10% of the max input by default.
+        // Test all permutations of swap directions
+        let swap_directions: Vec<_> = tokens
+            .iter()
+            .permutations(2)
+            .map(|perm| (perm[0], perm[1]))
+            .collect();
+
+        for (token_in, token_out) in &swap_directions {
+            let (max_input, max_output) = state
+                .get_limits(token_in.address.clone(), token_out.address.clone())
                 .into_diagnostic()
-                .wrap_err(format!("Error calculating spot price for Pool {id:?}."))?;
-
-            // Test get_amount_out with different percentages of limits. The reserves or limits
-            // are relevant because we need to know how much to test with. We
-            // don't know if a pool is going to revert with 10 or 10 million
-            // USDC, for example, so by using the limits we can use "safe
-            // values" where the sim shouldn't break. We then retrieve the
-            // amount out for 0.1%, 1% and 10%.
-            let percentages = [0.001, 0.01, 0.1];
-
-            // Test all permutations of swap directions
-            let swap_directions: Vec<_> = tokens
-                .iter()
-                .permutations(2)
-                .map(|perm| (perm[0], perm[1]))
-                .collect();
+                .wrap_err(format!(
+                    "Error getting limits for Pool {id:?} for in token: {}, and out token: {}",
+                    token_in.address, token_out.address
+                ))?;
 
-            for (token_in, token_out) in &swap_directions {
-                let (max_input, max_output) = state
-                    .get_limits(token_in.address.clone(), token_out.address.clone())
+            info!(
+                "[{}] Retrieved limits. | Max input: {max_input} {} | Max output: {max_output} {}",
+                id, token_in.symbol, token_out.symbol
+            );
+
+            let spot_price = state
+                .spot_price(token_in, token_out)
+                .into_diagnostic()
+                .wrap_err(format!(
+                    "Error calculating spot price for Pool {id:?} for {}/{}.",
+                    token_in.symbol, token_out.symbol
+                ))?;
+            let marginal_price = Self::marginal_price(state, token_in, token_out, &max_input);
+            let deviation = marginal_price
+                .as_ref()
+                .ok()
+                .map(|marginal_price| price_deviation(spot_price, *marginal_price));
+            match (&marginal_price, deviation) {
+                (Ok(marginal_price), Some(deviation)) if deviation > SPOT_PRICE_TOLERANCE => warn!(
+                    "[{}] Spot price {}/{}: {spot_price} deviates by {:.2}% from the marginal price {marginal_price}",
+                    id,
+                    token_in.symbol,
+                    token_out.symbol,
+                    deviation * 100.0
+                ),
+                (Ok(marginal_price), _) => info!(
+                    "[{}] Spot price {}/{}: {spot_price} (marginal price: {marginal_price})",
+                    id, token_in.symbol, token_out.symbol
+                ),
+                (Err(e), _) => warn!(
+                    "[{}] Spot price {}/{}: {spot_price} (marginal price unavailable: {e})",
+                    id, token_in.symbol, token_out.symbol
+                ),
+            }
+            report
+                .spot_prices
+                .push(SpotPriceReport {
+                    base: token_in.symbol.clone(),
+                    quote: token_out.symbol.clone(),
+                    spot_price,
+                    deviation,
+                    error: marginal_price.as_ref().err().cloned(),
+                    marginal_price: marginal_price.ok(),
+                });
+
+            for amount in amounts {
+                let amount_in = amount
+                    .amount_in(&max_input, token_in.decimals)
+                    .map_err(|e| miette!("{e} for pool {id}."))?;
+
+                if amount_in.is_zero() {
+                    return Err(miette!("Amount in for {amount} is zero for pool {id}."));
+                }
+
+                let amount_out_result = state
+                    .get_amount_out(amount_in.clone(), token_in, token_out)
                     .into_diagnostic()
                     .wrap_err(format!(
-                        "Error getting limits for Pool {id:?} for in token: {}, and out token: {}",
-                        token_in.address, token_out.address
+                        "Error calculating amount out for Pool {id:?} at {amount} with input of {amount_in} {}.",
+                        token_in.symbol,
                     ))?;
 
                 info!(
-                    "[{}] Retrieved limits. | Max input: {max_input} {} | Max output: {max_output} {}",
-                    id, token_in.symbol, token_out.symbol
+                    "[{}] Simulated amount out for trading {}: ({} {} -> {} {}) (gas: {})",
+                    id,
+                    amount,
+                    amount_in,
+                    token_in.symbol,
+                    amount_out_result.amount,
+                    token_out.symbol,
+                    amount_out_result.gas
                 );
 
-                for percentage in percentages.iter() {
-                    // For precision, multiply by 1000 then divide by 1000
-                    let percentage_biguint = BigUint::from((percentage * 1000.0) as u32);
-                    let thousand = BigUint::from(1000u32);
-                    let amount_in = (&max_input * &percentage_biguint) / &thousand;
-
-                    // Skip if amount is zero
-                    if amount_in.is_zero() {
-                        return Err(miette!(
-                                "Amount in multiplied by percentage {percentage} is zero for pool {id}."
-                            ));
-                    }
-
-                    let amount_out_result = state
-                            .get_amount_out(amount_in.clone(), token_in, token_out)
-                            .into_diagnostic()
-                            .wrap_err(format!(
-                                "Error calculating amount out for Pool {id:?} at {:.1}% with input of {amount_in} {}.",
-                                percentage * 100.0,
-                                token_in.symbol,
-                            ))?;
-
-                    info!(
-                            "[{}] Simulated amount out for trading {:.1}% of max: ({} {} -> {} {}) (gas: {})",
-                            id,
-                            percentage * 100.0,
-                            amount_in,
-                            token_in.symbol,
-                            amount_out_result.amount,
-                            token_out.symbol,
-                            amount_out_result.gas
-                        );
+                // Create unique simulation ID
+                let simulation_id = format!(
+                    "test_{}_{}_{}_{}",
+                    component.id, token_in.symbol, token_out.symbol, amount_in
+                );
+                report.swaps.push(SwapReport {
+                    simulation_id: simulation_id.clone(),
+                    token_in: token_in.symbol.clone(),
+                    token_out: token_out.symbol.clone(),
+                    amount_in: amount_in.to_string(),
+                    simulated_amount_out: amount_out_result.amount.to_string(),
+                    gas: amount_out_result.gas.to_string(),
+                    execution_status: None,
+                    executed_amount_out: None,
+                    difference: None,
+                    error: None,
+                });
+
+                if skip_execution {
+                    info!("Skipping execution for component {id}");
+                    continue;
+                }
 
-                    if skip_execution.contains(id) {
-                        info!("Skipping execution for component {id}");
-                        continue;
+                let executors_json = json!({
+                    "ethereum": {
+                        (protocol_system): EXECUTOR_ADDRESS
                     }
+                });
+                let chain_model = ChainModel::from(self.chain);
+                let (solution, calldata) = encode_swap(
+                    component,
+                    None,
+                    token_in,
+                    token_out,
+                    amount_in.clone(),
+                    chain_model,
+                    Some(executors_json.to_string()),
+                )?;
 
-                    let executors_json = json!({
-                        "ethereum": {
-                            (protocol_system): EXECUTOR_ADDRESS
-                        }
-                    });
-                    let chain_model = ChainModel::from(self.chain);
-                    let (solution, calldata) = encode_swap(
-                        component,
-                        None,
-                        token_in,
-                        token_out,
-                        amount_in.clone(),
-                        chain_model,
-                        Some(executors_json.to_string()),
-                    )?;
-
-                    // Create unique simulation ID
-                    let simulation_id = format!(
-                        "test_{}_{}_{}_{}",
-                        component.id, token_in.symbol, token_out.symbol, amount_in
-                    );
-
-                    execution_data.insert(
-                        simulation_id,
-                        TychoExecutionInput {
-                            solution: solution.clone(),
-                            transaction: calldata.clone(),
-                            expected_amount_out: amount_out_result.amount.clone(),
-                            protocol_system: protocol_system.to_string(),
-                            component_id: component.id.to_string(),
-                            token_in: token_in.symbol.clone(),
-                            token_out: token_out.symbol.clone(),
-                        },
-                    );
-                }
+                execution_data.insert(
+                    simulation_id,
+                    TychoExecutionInput {
+                        solution: solution.clone(),
+                        transaction: calldata.clone(),
+                        expected_amount_out: amount_out_result.amount.clone(),
+                        protocol_system: protocol_system.to_string(),
+                        component_id: component.id.to_string(),
+                        token_in: token_in.symbol.clone(),
+                        token_out: token_out.symbol.clone(),
+                    },
+                );
             }
         }
 
-        Ok(execution_data)
+        Ok(())
     }
 
     /// Simulates executing trades through RPC requests using historical block data
     /// and validates the accuracy of the Tycho simulation predictions.
     ///
-    /// This method processes large execution sets using batching (batch size: 30) to avoid
-    /// RPC request size limits caused by large state overwrites. Each batch is processed
-    /// sequentially to maintain stability and provide detailed progress reporting.
+    /// This method processes large execution sets using batching (configured with
+    /// `execution_batch_size`, 30 by default) to avoid RPC request size limits caused by large
+    /// state overwrites. Each batch is processed sequentially to maintain stability and provide
+    /// detailed progress reporting.
     ///
     /// # Arguments
     /// * `execution_data` - HashMap of simulation IDs to TychoExecutionInput data
     /// * `block` - The historical block to use for execution testing
-    /// * `protocol_system` - The protocol system identifier
-    /// * `expected_components` - Test configuration to determine which components to skip
+    /// * `config` - The protocol test configuration
+    /// * `expected_components` - Test configuration to determine which components to skip, and
+    ///   their simulation settings
+    /// * `report` - Test report to record the execution results in
     ///
     /// # Returns
     /// Returns `Ok(())` if all executions complete successfully within tolerance.
@@ -1184,7 +1443,8 @@ impl TestRunner {
     /// # Errors
     /// Returns an error if:
     /// - Execution simulation fails or reverts
-    /// - Difference between simulation and execution exceeds 0.5% tolerance
+    /// - Difference between simulation and execution exceeds the configured tolerance (0.5% by
+    ///   default)
     /// - Any critical execution failures occur
     ///
     /// Components can be skipped using `skip_execution` flag in the test configuration.
@@ -1192,19 +1452,25 @@ impl TestRunner {
         &self,
         execution_data: HashMap<String, TychoExecutionInput>,
         block: &Block,
-        protocol_system: &str,
+        config: &IntegrationTestsConfig,
         expected_components: &[ProtocolComponentWithTestConfig],
+        report: &mut TestReport,
     ) -> miette::Result<()> {
-        if execution_data.is_empty() {
-            info!("No execution data to process");
-            return Ok(());
-        }
-
         let skip_execution: HashSet<_> = expected_components
             .iter()
             .filter(|c| c.skip_execution)
             .map(|c| c.base.id.to_lowercase())
             .collect();
+        for id in &skip_execution {
+            report
+                .component(id)
+                .skip_step(Step::Execution);
+        }
+
+        if execution_data.is_empty() {
+            info!("No execution data to process");
+            return Ok(());
+        }
 
         // Filter out skipped components
         let filtered_execution_data: HashMap<_, _> = execution_data
@@ -1222,18 +1488,20 @@ impl TestRunner {
 
         // Prepare router overwrites data
         let router_overwrites_data =
-            Some(execution::create_router_overwrites_data(protocol_system)?);
+            Some(execution::create_router_overwrites_data(&config.protocol_system)?);
 
         info!("Executing {} simulations in batches ...", filtered_execution_data.len());
 
         // Split execution data into smaller batches to avoid RPC request size limits
-        // This happens because our overwrites are colossal
-        const BATCH_SIZE: usize = 30;
+        // This happens because our overwrites are colossal. Simulations are sorted so that batches
+        // and their RPC calls are the same across runs.
+        let batch_size = config.execution_batch_size();
         let execution_batches: Vec<HashMap<String, TychoExecutionInput>> = filtered_execution_data
             .clone()
             .into_iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
             .collect::<Vec<_>>()
-            .chunks(BATCH_SIZE)
+            .chunks(batch_size)
             .map(|chunk| chunk.iter().cloned().collect())
             .collect();
 
@@ -1271,9 +1539,16 @@ impl TestRunner {
 
         let mut success_count = 0;
         let mut failure_count = 0;
+        // Failed executions by component, for the report.
+        let mut component_failures: HashMap<String, Vec<String>> = HashMap::new();
 
-        for (simulation_id, expected_input) in &filtered_execution_data {
-            match results.get(simulation_id) {
+        for (simulation_id, expected_input) in filtered_execution_data
+            .iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        {
+            let mut executed_amount_out = None;
+            let mut difference = None;
+            let failure = match results.get(simulation_id) {
                 Some(TychoExecutionResult::Success { amount_out, .. }) => {
                     info!(
                         "[{}] Execution passed: {} {} -> {} {}",
@@ -1292,39 +1567,79 @@ impl TestRunner {
                     ) - BigInt::from(amount_out.clone());
                     let slippage: BigRational =
                         BigRational::new(diff.abs(), BigInt::from(amount_out.clone()));
+                    executed_amount_out = Some(amount_out.to_string());
+                    difference = slippage.to_f64();
 
-                    if slippage.to_f64() > Some(0.005) {
-                        failure_count += 1;
+                    let tolerance = component_simulation_config(
+                        expected_components,
+                        &config.simulation,
+                        &expected_input.component_id,
+                    )
+                    .tolerance();
+                    if slippage.to_f64() > Some(tolerance) {
                         error!(
-                            "[{}] Execution amount and simulation amount differ more than 0.05% for {}: simulation={}, execution={}",
-                            expected_input.component_id, simulation_id, expected_input.expected_amount_out, amount_out
+                            "[{}] Execution amount and simulation amount differ more than {}% for {}: simulation={}, execution={}",
+                            expected_input.component_id, tolerance * 100.0, simulation_id, expected_input.expected_amount_out, amount_out
                         );
+                        let simulated = &expected_input.expected_amount_out;
+                        Some(format!(
+                            "executed {amount_out}, simulated {simulated}: over {}% apart",
+                            tolerance * 100.0
+                        ))
                     } else {
-                        success_count += 1;
+                        None
                     }
                 }
                 Some(TychoExecutionResult::Revert { reason, .. }) => {
-                    failure_count += 1;
                     error!(
                         "[{}] Execution reverted for {}: {}",
                         expected_input.component_id, simulation_id, reason
                     );
+                    Some(format!("execution reverted: {reason}"))
                 }
                 Some(TychoExecutionResult::Failed { error_msg }) => {
-                    failure_count += 1;
                     error!(
                         "[{}] Execution failed for {}: {}",
                         expected_input.component_id, simulation_id, error_msg
                     );
+                    Some(format!("execution failed: {error_msg}"))
                 }
                 None => {
-                    failure_count += 1;
                     error!(
                         "[{}] No result found for simulation {}",
                         expected_input.component_id, simulation_id
                     );
+                    Some("no execution result found".to_string())
+                }
+            };
+
+            let component_id = expected_input
+                .component_id
+                .to_lowercase();
+            let component_failures = component_failures
+                .entry(component_id)
+                .or_default();
+            match &failure {
+                Some(failure) => {
+                    failure_count += 1;
+                    component_failures.push(format!("{simulation_id}: {failure}"));
                 }
+                None => success_count += 1,
             }
+            if let Some(swap) = report.swap(simulation_id) {
+                swap.execution_status =
+                    Some(if failure.is_some() { Status::Failed } else { Status::Passed });
+                swap.executed_amount_out = executed_amount_out;
+                swap.difference = difference;
+                swap.error = failure;
+            }
+        }
+
+        for (component_id, failures) in component_failures {
+            let result = if failures.is_empty() { Ok(()) } else { Err(failures.join("\n")) };
+            report
+                .component(&component_id)
+                .record_step(Step::Execution, result);
         }
 
         info!("Batch execution complete: {} successes, {} failures", success_count, failure_count);
@@ -1347,6 +1662,7 @@ impl TestRunner {
         component_tokens: &HashMap<String, Vec<Token>>,
         protocol_states_by_id: &HashMap<String, ResponseProtocolState>,
         stop_block: u64,
+        report: &mut TestReport,
     ) -> miette::Result<()> {
         for (id, component) in protocol_states_by_id.iter() {
             let tokens = component_tokens.get(id);
@@ -1374,18 +1690,29 @@ impl TestRunner {
                                 stop_block,
                             ))?;
                     if balance != node_balance {
-                        return Err(miette!(
+                        let error = format!(
                             "Token balance mismatch for component {id} and token {}. Balance: {balance}, Node balance: {node_balance}",
                             token.symbol
-                        ));
+                        );
+                        report
+                            .component(id)
+                            .record_step(Step::BalanceCheck, Err(error.clone()));
+                        return Err(miette!(error));
                     }
                     info!(
                         "Token balance for component {} and token {} matches the expected value",
                         id, token.symbol
                     );
                 }
+                report
+                    .component(id)
+                    .record_step(Step::BalanceCheck, Ok(()));
             } else {
-                return Err(miette!("Couldn't find tokens for component {}", id,));
+                let error = format!("Couldn't find tokens for component {id}");
+                report
+                    .component(id)
+                    .record_step(Step::BalanceCheck, Err(error.clone()));
+                return Err(miette!(error));
             }
         }
         Ok(())
@@ -1425,6 +1752,32 @@ impl TestRunner {
     }
 }
 
+/// Returns the price of one unit of the in token in units of the out token, adjusting both
+/// amounts by their decimals.
+fn price(
+    amount_in: &BigUint,
+    decimals_in: u32,
+    amount_out: &BigUint,
+    decimals_out: u32,
+) -> Option<f64> {
+    if amount_in.is_zero() {
+        return None;
+    }
+    let price = BigRational::new(
+        BigInt::from(amount_out.clone()) * BigInt::from(10u32).pow(decimals_in),
+        BigInt::from(amount_in.clone()) * BigInt::from(10u32).pow(decimals_out),
+    );
+    price.to_f64()
+}
+
+/// Returns the relative difference between a spot price and a marginal price.
+fn price_deviation(spot_price: f64, marginal_price: f64) -> f64 {
+    if spot_price == 0.0 {
+        return if marginal_price == 0.0 { 0.0 } else { f64::INFINITY };
+    }
+    ((spot_price - marginal_price) / spot_price).abs()
+}
+
 #[cfg(test)]
 mod tests {
     use std::{collections::HashMap, env, str::FromStr};
@@ -1498,15 +1851,16 @@ mod tests {
 
     fn get_mocked_runner() -> TestRunner {
         dotenv().ok();
-        let rpc_url = env::var("RPC_URL").unwrap();
+        let rpc_url = env::var("RPC_URL").ok();
         let current_dir = std::env::current_dir().unwrap();
         TestRunner::new(
-            TestType::Range(TestTypeRange { match_test: None }),
+            TestType::Range(TestTypeRange { match_test: None, report_path: None }),
             current_dir,
             Chain::Ethereum,
             "test-protocol".to_string(),
             "".to_string(),
             rpc_url,
+            None,
             false,
             false,
         )
@@ -1539,8 +1893,12 @@ mod tests {
         let mut protocol_states_by_id = HashMap::new();
         protocol_states_by_id.insert(component_id.clone(), protocol_state.clone());
 
-        let result =
-            runner.validate_token_balances(&component_tokens, &protocol_states_by_id, block_number);
+        let result = runner.validate_token_balances(
+            &component_tokens,
+            &protocol_states_by_id,
+            block_number,
+            &mut TestReport::new("test"),
+        );
         assert!(result.is_ok(), "Should pass when balance check is performed and balances match");
     }
 
@@ -1570,11 +1928,26 @@ mod tests {
         protocol_states_by_id.insert(component_id.clone(), protocol_state.clone());
 
         dotenv().ok();
-        let result =
-            runner.validate_token_balances(&component_tokens, &protocol_states_by_id, block_number);
+        let result = runner.validate_token_balances(
+            &component_tokens,
+            &protocol_states_by_id,
+            block_number,
+            &mut TestReport::new("test"),
+        );
         assert!(
             result.is_err(),
             "Should fail when balance check is performed and balances do not match"
         );
     }
+
+    #[test]
+    fn test_price() {
+        // 1 WETH -> 2500 USDC
+        let weth_price =
+            price(&BigUint::from(10u64.pow(18)), 18, &BigUint::from(2_500_000_000u64), 6);
+        assert_eq!(weth_price, Some(2500.0));
+        assert_eq!(price(&BigUint::zero(), 18, &BigUint::from(1u8), 6), None);
+        assert!((price_deviation(2500.0, 2492.5) - 0.003).abs() < 1e-12);
+        assert_eq!(price_deviation(0.0, 1.0), f64::INFINITY);
+    }
 }
@@ -13,6 +13,7 @@ use tycho_simulation::tycho_common::dto::Chain;
 pub struct TychoRunner {
     chain: Chain,
     db_url: String,
+    rpc_url: String,
     initialized_accounts: Vec<String>,
 }
 
@@ -22,8 +23,13 @@ pub struct TychoRpcServer {
 }
 
 impl TychoRunner {
-    pub fn new(chain: Chain, db_url: String, initialized_accounts: Vec<String>) -> Self {
-        Self { chain, db_url, initialized_accounts }
+    pub fn new(
+        chain: Chain,
+        db_url: String,
+        rpc_url: String,
+        initialized_accounts: Vec<String>,
+    ) -> Self {
+        Self { chain, db_url, rpc_url, initialized_accounts }
     }
 
     pub fn run_tycho(
@@ -39,7 +45,8 @@ impl TychoRunner {
 
         let mut cmd = Command::new("tycho-indexer");
         cmd.env("RUST_LOG", std::env::var("RUST_LOG").unwrap_or("tycho_indexer=info".to_string()))
-            .env("AUTH_API_KEY", "dummy");
+            .env("AUTH_API_KEY", "dummy")
+            .env("RPC_URL", self.rpc_url.as_str());
 
         let all_accounts = self.initialized_accounts.clone();
 
@@ -197,7 +204,8 @@ impl TychoRunner {
 
         let mut cmd = Command::new("tycho-indexer");
         cmd.env("RUST_LOG", std::env::var("RUST_LOG").unwrap_or("tycho_indexer=info".to_string()))
-            .env("AUTH_API_KEY", "dummy");
+            .env("AUTH_API_KEY", "dummy")
+            .env("RPC_URL", self.rpc_url.as_str());
 
         cmd.args([
             "--database-url",
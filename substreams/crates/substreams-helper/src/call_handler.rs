@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use ethabi::ethereum_types::Address;
+use substreams_ethereum::{
+    pb::eth::v2::{self as eth, CallType},
+    rpc::RPCDecodable,
+    Function,
+};
+
+use crate::common::HasAddresser;
+
+/// Utility struct to declaratively decode calls of a block and assign them handlers.
+///
+/// It is the call counterpart of [`EventHandler`](crate::event_handler::EventHandler): instead of
+/// hand-decoding calldata, register a handler per ABI function and receive the decoded input,
+/// optionally the decoded output, the call context and the storage changes of the call.
+///
+/// Usage:
+/// ```ignore
+/// let mut ch = CallHandler::new(&block);
+/// ch.filter_by_address(vec![pool_address]); // Optional, matches the storage or code address.
+/// ch.on::<abi::pool::functions::Swap, _>(&mut on_swap);
+/// ch.on_with_output::<abi::pool::functions::Mint, (BigInt, BigInt), _>(&mut on_mint);
+/// ch.handle_calls(); // this will run all handlers
+/// ```
+///
+/// Only calls of successful transactions whose state was not reverted are handled. Handlers are
+/// run in the order the calls appear in the block (by ordinal), and in registration order when
+/// several handlers match the same call.
+///
+/// Delegated calls are resolved to the context they run in: the storage address of a
+/// DELEGATECALL or CALLCODE is the address of the frame that issued it, see [`CallContext`]. A
+/// proxy forwarding its calldata unchanged to an implementation is reported once, as a call to
+/// the proxy with the implementation as code address, and the storage changes of the forwarded
+/// frames are included.
+pub struct CallHandler<'a> {
+    block: &'a eth::Block,
+    handlers: Vec<CallCallback<'a>>,
+    addresses: Option<Box<dyn HasAddresser + 'a>>,
+}
+
+/// Where a call executes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallContext {
+    /// Address whose storage the call reads and writes, e.g. the proxy of a delegated call.
+    pub storage_address: Vec<u8>,
+    /// Address whose code is executed, e.g. the implementation behind a proxy.
+    pub code_address: Vec<u8>,
+}
+
+impl CallContext {
+    /// Whether the executed code lives at another address than the storage it operates on.
+    pub fn is_delegated(&self) -> bool {
+        self.storage_address != self.code_address
+    }
+}
+
+/// A decoded call, as passed to the handlers of a [`CallHandler`].
+pub struct DecodedCall<'b, I, O = ()> {
+    pub tx: &'b eth::TransactionTrace,
+    pub call: &'b eth::Call,
+    /// The decoded calldata.
+    pub input: I,
+    /// The decoded return data, `()` if the handler doesn't decode outputs.
+    pub output: O,
+    pub context: CallContext,
+    /// Storage changes of the call to its storage address, in ordinal order.
+    pub storage_changes: Vec<&'b eth::StorageChange>,
+}
+
+type CallCallback<'a> = Box<dyn FnMut(&CallView<'_>) + 'a>;
+
+/// A call in its resolved context, before decoding.
+struct CallView<'b> {
+    tx: &'b eth::TransactionTrace,
+    call: &'b eth::Call,
+    context: CallContext,
+    storage_changes: Vec<&'b eth::StorageChange>,
+}
+
+impl<'b> CallView<'b> {
+    fn decoded<I, O>(&self, input: I, output: O) -> DecodedCall<'b, I, O> {
+        DecodedCall {
+            tx: self.tx,
+            call: self.call,
+            input,
+            output,
+            context: self.context.clone(),
+            storage_changes: self.storage_changes.clone(),
+        }
+    }
+}
+
+impl<'a> CallHandler<'a> {
+    pub fn new(block: &'a eth::Block) -> Self {
+        Self { block, handlers: Vec::new(), addresses: None }
+    }
+
+    /// Sets the HasAddresser as a filter for which calls to handle.
+    /// Only one at a time can be set. Setting it twice will remove the first one.
+    ///
+    /// A call is handled if either its storage address or its code address is found in the
+    /// `HasAddresser`.
+    pub fn filter_by_address(&mut self, addresser: impl HasAddresser + 'a) {
+        self.addresses = Some(Box::new(addresser));
+    }
+
+    /// Registers a handler to be run on calls to a given function. The handler should have the
+    /// signature: `|call: DecodedCall<SomeFunction>|`.
+    pub fn on<C: Function, F>(&mut self, mut handler: F)
+    where
+        F: FnMut(DecodedCall<C>) + 'a,
+    {
+        self.handlers
+            .push(Box::new(move |view: &CallView| {
+                if let Some(input) = C::match_and_decode(view.call) {
+                    handler(view.decoded(input, ()));
+                }
+            }));
+    }
+
+    /// Registers a handler to be run on calls to a given function, with the return data decoded
+    /// as well. The handler should have the signature: `|call: DecodedCall<SomeFunction, O>|`.
+    ///
+    /// Calls whose return data can't be decoded are skipped.
+    pub fn on_with_output<C, O, F>(&mut self, mut handler: F)
+    where
+        C: Function + RPCDecodable<O>,
+        F: FnMut(DecodedCall<C, O>) + 'a,
+    {
+        self.handlers
+            .push(Box::new(move |view: &CallView| {
+                let Some(input) = C::match_and_decode(view.call) else { return };
+                if let Ok(output) = C::output(&view.call.return_data) {
+                    handler(view.decoded(input, output));
+                }
+            }));
+    }
+
+    /// Will run all registered handlers for all calls present on the block that match the
+    /// address filter, in ordinal order. You'll likely want to run this just once.
+    pub fn handle_calls(&mut self) {
+        for tx in self.block.transactions() {
+            let calls: HashMap<u32, &eth::Call> = tx
+                .calls
+                .iter()
+                .map(|call| (call.index, call))
+                .collect();
+
+            let mut ordered: Vec<&eth::Call> = tx
+                .calls
+                .iter()
+                .filter(|call| !call.state_reverted)
+                .collect();
+            ordered.sort_by_key(|call| call.begin_ordinal);
+
+            for call in ordered {
+                if call.input.len() < 4 || is_forwarded(call, &calls) {
+                    continue;
+                }
+
+                let forwarded = forwarded_frames(call, &tx.calls);
+                let context = CallContext {
+                    storage_address: storage_address(call, &calls),
+                    code_address: forwarded
+                        .last()
+                        .map_or(&call.address, |frame| &frame.address)
+                        .clone(),
+                };
+                if !self.is_included(&context) {
+                    continue;
+                }
+
+                let mut storage_changes: Vec<&eth::StorageChange> = std::iter::once(call)
+                    .chain(forwarded)
+                    .flat_map(|frame| frame.storage_changes.iter())
+                    .filter(|change| change.address == context.storage_address)
+                    .collect();
+                storage_changes.sort_by_key(|change| change.ordinal);
+
+                let view = CallView { tx, call, context, storage_changes };
+                for handler in self.handlers.iter_mut() {
+                    handler(&view);
+                }
+            }
+        }
+    }
+
+    fn is_included(&self, context: &CallContext) -> bool {
+        match &self.addresses {
+            Some(addresses) => {
+                addresses.has_address(Address::from_slice(&context.storage_address)) ||
+                    addresses.has_address(Address::from_slice(&context.code_address))
+            }
+            None => true,
+        }
+    }
+}
+
+fn is_delegated(call: &eth::Call) -> bool {
+    matches!(CallType::from_i32(call.call_type), Some(CallType::Delegate | CallType::Callcode))
+}
+
+/// Returns whether the call is a delegated frame forwarding its parent's calldata unchanged, i.e.
+/// a proxy delegating to its implementation.
+fn is_forwarded(call: &eth::Call, calls: &HashMap<u32, &eth::Call>) -> bool {
+    is_delegated(call) &&
+        calls
+            .get(&call.parent_index)
+            .is_some_and(|parent| parent.input == call.input)
+}
+
+/// Returns the chain of delegated frames forwarding the calldata of `call`, outermost first.
+fn forwarded_frames<'b>(call: &'b eth::Call, tx_calls: &'b [eth::Call]) -> Vec<&'b eth::Call> {
+    let mut frames = Vec::new();
+    let mut current = call;
+    while let Some(next) = tx_calls.iter().find(|child| {
+        child.parent_index == current.index &&
+            !child.state_reverted &&
+            is_delegated(child) &&
+            child.input == current.input
+    }) {
+        frames.push(next);
+        current = next;
+    }
+    frames
+}
+
+/// Returns the address whose storage the call operates on, following delegated frames up to the
+/// first regular call.
+fn storage_address(call: &eth::Call, calls: &HashMap<u32, &eth::Call>) -> Vec<u8> {
+    let mut current = call;
+    while is_delegated(current) {
+        match calls.get(&current.parent_index) {
+            Some(parent) => current = parent,
+            // Without the parent frame, the caller is the best approximation of the context.
+            None => return current.caller.clone(),
+        }
+    }
+    current.address.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    const SELECTOR: [u8; 4] = [0xaa, 0xbb, 0xcc, 0xdd];
+
+    struct Swap(Vec<u8>);
+
+    impl Function for Swap {
+        const NAME: &'static str = "swap";
+
+        fn match_call(call: &eth::Call) -> bool {
+            call.input.starts_with(&SELECTOR)
+        }
+
+        fn decode(call: &eth::Call) -> Result<Self, String> {
+            Ok(Swap(call.input[4..].to_vec()))
+        }
+
+        fn encode(&self) -> Vec<u8> {
+            [SELECTOR.to_vec(), self.0.clone()].concat()
+        }
+    }
+
+    impl RPCDecodable<u8> for Swap {
+        fn output(data: &[u8]) -> Result<u8, String> {
+            data.first()
+                .copied()
+                .ok_or_else(|| "empty return data".to_string())
+        }
+    }
+
+    fn call(index: u32, parent_index: u32, call_type: CallType, address: u8) -> eth::Call {
+        eth::Call {
+            index,
+            parent_index,
+            call_type: call_type.into(),
+            address: vec![address; 20],
+            input: [SELECTOR.to_vec(), vec![index as u8]].concat(),
+            begin_ordinal: index as u64,
+            ..Default::default()
+        }
+    }
+
+    fn storage_change(address: u8, ordinal: u64) -> eth::StorageChange {
+        eth::StorageChange { address: vec![address; 20], ordinal, ..Default::default() }
+    }
+
+    fn block(calls: Vec<eth::Call>) -> eth::Block {
+        eth::Block {
+            transaction_traces: vec![eth::TransactionTrace {
+                status: eth::TransactionTraceStatus::Succeeded.into(),
+                calls,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_handle_calls_resolves_proxies() {
+        // A proxy (0x01) forwarding to its implementation (0x02), which delegates a different
+        // call to a library (0x03).
+        let mut proxy = call(1, 0, CallType::Call, 1);
+        proxy.return_data = vec![7];
+        let mut implementation = call(2, 1, CallType::Delegate, 2);
+        implementation.input = proxy.input.clone();
+        implementation.storage_changes = vec![storage_change(1, 3)];
+        let mut library = call(3, 2, CallType::Delegate, 3);
+        library.storage_changes = vec![storage_change(1, 4)];
+        let block = block(vec![proxy, implementation, library]);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut ch = CallHandler::new(&block);
+            let s = seen.clone();
+            ch.on_with_output::<Swap, u8, _>(move |decoded| {
+                s.borrow_mut().push((
+                    decoded.input.0,
+                    decoded.output,
+                    decoded.context,
+                    decoded
+                        .storage_changes
+                        .iter()
+                        .map(|change| change.ordinal)
+                        .collect::<Vec<_>>(),
+                ))
+            });
+            ch.handle_calls();
+        }
+
+        let context =
+            |code| CallContext { storage_address: vec![1; 20], code_address: vec![code; 20] };
+        // The library call has no return data, so only the proxied call is handled with output.
+        assert_eq!(*seen.borrow(), vec![(vec![1], 7, context(2), vec![3])]);
+    }
+
+    #[test]
+    fn test_handle_calls_filters() {
+        let mut reverted = call(2, 1, CallType::Call, 2);
+        reverted.state_reverted = true;
+        let delegated = call(3, 1, CallType::Delegate, 3);
+        let other = call(4, 1, CallType::Call, 4);
+        let block = block(vec![call(1, 0, CallType::Call, 1), reverted, delegated, other]);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut ch = CallHandler::new(&block);
+            let s = seen.clone();
+            ch.on::<Swap, _>(move |decoded| {
+                s.borrow_mut()
+                    .push((decoded.call.index, decoded.context.is_delegated()))
+            });
+            ch.filter_by_address(vec![Address::repeat_byte(1), Address::repeat_byte(2)]);
+            ch.handle_calls();
+        }
+
+        // The delegated call runs in the storage of 0x01 and is included by the filter.
+        assert_eq!(*seen.borrow(), vec![(1, false), (3, true)]);
+    }
+}
@@ -10,17 +10,30 @@ use crate::common::HasAddresser;
 
 /// Utility struct to easily filter events and assign them handlers.
 ///
+/// Calls are handled by its counterpart, [`CallHandler`](crate::call_handler::CallHandler).
+///
 /// Usage:
 /// ```ignore
 /// let eh = EventHandler::new(&block);
 /// eh.filter_by_address(store); // This is optional, if omitted it will handle all events that match the type, independently of the emitting contract.
 /// eh.on::<Transfer, _>(&mut on_transfer);
 /// eh.on::<Approval, _>(&mut on_approval);
-/// eh.on::<Mint, _>(&mut on_mint);
+/// eh.on::<Mint, _>(&mut on_mint)
+///     .filter_by_address(pools)
+///     .filter_by_address(vec![router]); // Per handler filters, any of them has to match.
 /// eh.on::<Burn, _>(&mut on_burn);
 /// eh.handle_events(); // this will run all handlers
 /// ```
 ///
+/// Handlers are run in the order the logs appear in the block (by ordinal). When several handlers
+/// match the same log, they run in the order they were registered.
+///
+/// Handlers are keyed by the topic0 of their event, so each log is only matched against the
+/// handlers registered for its topic0. Several handlers can be registered for events with the
+/// same name, e.g. `Swap` events from different ABIs. Events generated by `Abigen` only match
+/// logs whose topic0 is their `TOPIC_ID`, which isn't exposed by the `Event` trait, so the topic0
+/// of a handler is taken from the first log its event matches.
+///
 /// You'll likely want to mutate some value from the handlers that is in the current scope.
 /// For that, make your handlers be closures, that close over the variable you want to mutate, and
 /// have the whole EventHandler block of code in its own scope (either by wrapping it in an aux
@@ -43,61 +56,277 @@ use crate::common::HasAddresser;
 /// ```
 pub struct EventHandler<'a> {
     block: &'a eth::Block,
-    #[allow(clippy::type_complexity)]
-    handlers: HashMap<&'static str, Box<dyn FnMut(&eth::Log, &eth::TransactionTrace) + 'a>>,
+    handlers: Vec<Handler<'a>>,
+    /// Handlers by the topic0 of the logs their event matches, in registration order.
+    by_topic: HashMap<Vec<u8>, Vec<usize>>,
+    /// Handlers whose event didn't match any log yet, their topic0 is unknown.
+    unkeyed: Vec<usize>,
     addresses: Option<Box<dyn HasAddresser + 'a>>,
 }
 
 impl<'a> EventHandler<'a> {
     pub fn new(block: &'a eth::Block) -> Self {
-        Self { block, handlers: HashMap::new(), addresses: None }
+        Self {
+            block,
+            handlers: Vec::new(),
+            by_topic: HashMap::new(),
+            unkeyed: Vec::new(),
+            addresses: None,
+        }
     }
 
     /// Sets the HasAddresser as a filter for which events to handle.
     /// Only one at a time can be set. Setting it twice will remove the first one.
     /// Addresses found in the `HasAddresser` will be the ones we'll handle events from.
+    ///
+    /// This filter applies to all handlers, on top of the handlers' own filters.
     pub fn filter_by_address(&mut self, addresser: impl HasAddresser + 'a) {
         self.addresses = Some(Box::new(addresser));
     }
 
     /// Registers a handler to be run on a given event. The handler should have the signature:
     /// `|ev: SomeEvent, tx: &pbeth::v2::TransactionTrace, log: &pbeth::v2::Log|`.
-    /// You can only assign one handler to each Event type.
-    /// Handlers are keyed by the name of the event they are handling, so be careful to not assign
-    /// handlers for 2 different events named equal.
-    pub fn on<E: Event, F>(&mut self, mut handler: F)
+    ///
+    /// Returns the handler's options, to restrict it to logs emitted by given addresses.
+    pub fn on<E: Event, F>(&mut self, mut handler: F) -> HandlerOptions<'_, 'a>
     where
         F: FnMut(E, &eth::TransactionTrace, &eth::Log) + 'a,
     {
-        self.handlers.insert(
-            E::NAME,
-            Box::new(move |log: &eth::Log, tx: &eth::TransactionTrace| {
+        self.unkeyed.push(self.handlers.len());
+        self.handlers.push(Handler {
+            matches: E::match_log,
+            addresses: Vec::new(),
+            callback: Box::new(move |log: &eth::Log, tx: &eth::TransactionTrace| {
                 if let Some(event) = E::match_and_decode(log) {
                     handler(event, tx, log);
                 }
             }),
-        );
+        });
+        HandlerOptions {
+            addresses: &mut self
+                .handlers
+                .last_mut()
+                .expect("handler was just pushed")
+                .addresses,
+        }
     }
 
     /// Will run all registered handlers for all events present on the block that match the given
-    /// filters. You'll likely want to run this just once.
+    /// filters, in ordinal order. You'll likely want to run this just once.
     pub fn handle_events(&mut self) {
         // Here we don't need to filter out failed transactions because logs only exist for
         // successful ones.
         for log in self.block.logs() {
-            if self.addresses.is_some() &&
-                !&self
-                    .addresses
-                    .as_ref()
-                    .unwrap()
-                    .has_address(Address::from_slice(log.log.address.as_slice()))
-            {
+            let Some(topic0) = log.log.topics.first() else { continue };
+            if !self.is_included(&log.log.address) {
                 continue;
             }
+            self.learn_topic(topic0, log.log);
 
-            for handler in self.handlers.values_mut() {
-                handler(log.log, log.receipt.transaction);
+            let Some(candidates) = self.by_topic.get(topic0) else { continue };
+            for &idx in candidates {
+                let handler = &mut self.handlers[idx];
+                if handler.is_included(&log.log.address) {
+                    (handler.callback)(log.log, log.receipt.transaction);
+                }
             }
         }
     }
+
+    /// Keys the unkeyed handlers whose event matches the log by its topic0.
+    fn learn_topic(&mut self, topic0: &[u8], log: &eth::Log) {
+        let (matched, unmatched): (Vec<usize>, Vec<usize>) = self
+            .unkeyed
+            .iter()
+            .partition(|&&idx| (self.handlers[idx].matches)(log));
+        if matched.is_empty() {
+            return;
+        }
+        let keyed = self
+            .by_topic
+            .entry(topic0.to_vec())
+            .or_default();
+        keyed.extend(matched);
+        keyed.sort_unstable();
+        self.unkeyed = unmatched;
+    }
+
+    fn is_included(&self, address: &[u8]) -> bool {
+        match &self.addresses {
+            Some(addresses) => addresses.has_address(Address::from_slice(address)),
+            None => true,
+        }
+    }
+}
+
+/// Options of a registered handler.
+pub struct HandlerOptions<'h, 'a> {
+    addresses: &'h mut Vec<Box<dyn HasAddresser + 'a>>,
+}
+
+impl<'a> HandlerOptions<'_, 'a> {
+    /// Adds a filter on the address emitting the log. Once filters are set, the handler only runs
+    /// if any of them contains the address.
+    pub fn filter_by_address(&mut self, addresser: impl HasAddresser + 'a) -> &mut Self {
+        self.addresses.push(Box::new(addresser));
+        self
+    }
+}
+
+type LogCallback<'a> = Box<dyn FnMut(&eth::Log, &eth::TransactionTrace) + 'a>;
+
+struct Handler<'a> {
+    matches: fn(&eth::Log) -> bool,
+    addresses: Vec<Box<dyn HasAddresser + 'a>>,
+    callback: LogCallback<'a>,
+}
+
+impl Handler<'_> {
+    fn is_included(&self, address: &[u8]) -> bool {
+        self.addresses.is_empty() ||
+            self.addresses
+                .iter()
+                .any(|addresses| addresses.has_address(Address::from_slice(address)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    const SWAP_TOPIC: [u8; 32] = [0x11; 32];
+    const SYNC_TOPIC: [u8; 32] = [0x22; 32];
+
+    /// A `Swap` event with one indexed topic.
+    struct Swap;
+
+    impl Event for Swap {
+        const NAME: &'static str = "Swap";
+
+        fn match_log(log: &eth::Log) -> bool {
+            log.topics.len() == 2 && log.topics[0] == SWAP_TOPIC
+        }
+
+        fn decode(_log: &eth::Log) -> Result<Self, String> {
+            Ok(Swap)
+        }
+    }
+
+    /// Another `Swap` event, with the same topic0 but two indexed topics.
+    struct IndexedSwap;
+
+    impl Event for IndexedSwap {
+        const NAME: &'static str = "Swap";
+
+        fn match_log(log: &eth::Log) -> bool {
+            log.topics.len() == 3 && log.topics[0] == SWAP_TOPIC
+        }
+
+        fn decode(_log: &eth::Log) -> Result<Self, String> {
+            Ok(IndexedSwap)
+        }
+    }
+
+    /// An event matching any topic0, unlike the events generated by `Abigen`.
+    struct AnyEvent;
+
+    impl Event for AnyEvent {
+        const NAME: &'static str = "Any";
+
+        fn match_log(log: &eth::Log) -> bool {
+            !log.topics.is_empty()
+        }
+
+        fn decode(_log: &eth::Log) -> Result<Self, String> {
+            Ok(AnyEvent)
+        }
+    }
+
+    fn log(address: u8, topic0: [u8; 32], topics: usize, ordinal: u64) -> eth::Log {
+        eth::Log {
+            address: vec![address; 20],
+            topics: std::iter::once(topic0.to_vec())
+                .chain((1..topics).map(|_| vec![0; 32]))
+                .collect(),
+            ordinal,
+            ..Default::default()
+        }
+    }
+
+    fn block() -> eth::Block {
+        eth::Block {
+            transaction_traces: vec![eth::TransactionTrace {
+                status: eth::TransactionTraceStatus::Succeeded.into(),
+                receipt: Some(eth::TransactionReceipt {
+                    logs: vec![
+                        log(1, SWAP_TOPIC, 2, 1),
+                        log(2, SWAP_TOPIC, 3, 2),
+                        log(2, SYNC_TOPIC, 1, 3),
+                        log(3, SWAP_TOPIC, 2, 4),
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_handle_events_in_ordinal_order() {
+        let block = block();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut eh = EventHandler::new(&block);
+            let s = seen.clone();
+            eh.on::<IndexedSwap, _>(move |_, _, log| {
+                s.borrow_mut()
+                    .push(format!("indexed_swap@{}", log.ordinal))
+            });
+            let s = seen.clone();
+            eh.on::<Swap, _>(move |_, _, log| {
+                s.borrow_mut()
+                    .push(format!("swap@{}", log.ordinal))
+            });
+            eh.handle_events();
+        }
+
+        assert_eq!(*seen.borrow(), vec!["swap@1", "indexed_swap@2", "swap@4"]);
+    }
+
+    #[test]
+    fn test_handle_events_address_filters() {
+        let block = block();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut eh = EventHandler::new(&block);
+            let s = seen.clone();
+            eh.on::<Swap, _>(move |_, _, log| s.borrow_mut().push(log.ordinal))
+                .filter_by_address(vec![Address::repeat_byte(1)])
+                .filter_by_address(vec![Address::repeat_byte(3)]);
+            let s = seen.clone();
+            eh.on::<IndexedSwap, _>(move |_, _, log| s.borrow_mut().push(log.ordinal));
+            eh.filter_by_address(vec![Address::repeat_byte(1), Address::repeat_byte(2)]);
+            eh.handle_events();
+        }
+
+        // The log at ordinal 4 is excluded by the global filter
+        assert_eq!(*seen.borrow(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_handle_events_keyed_by_first_match() {
+        let block = block();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut eh = EventHandler::new(&block);
+            let s = seen.clone();
+            eh.on::<AnyEvent, _>(move |_, _, log| s.borrow_mut().push(log.ordinal));
+            eh.handle_events();
+        }
+
+        // Once keyed by the topic0 of the first log, logs with another topic0 are skipped
+        assert_eq!(*seen.borrow(), vec![1, 2, 4]);
+    }
 }
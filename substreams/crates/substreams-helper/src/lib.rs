@@ -1,3 +1,4 @@
+pub mod call_handler;
 pub mod common;
 
 pub mod event_handler;
@@ -1,3 +1,5 @@
 #![allow(clippy::all)]
 pub mod erc20;
+pub mod erc4626;
+pub mod steth;
 pub mod weth;
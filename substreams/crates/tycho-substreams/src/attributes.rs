@@ -65,3 +65,314 @@ pub fn json_serialize_bigint_list(values: &[BigInt]) -> Vec<u8> {
             .collect::<Vec<_>>(),
     )
 }
+
+/// Typed encoding and decoding of attribute values.
+///
+/// Implementations follow the encodings already used by integrations, so typed and untyped
+/// attributes can be mixed freely:
+/// - Integers, including 256 bit values represented as `BigInt`, are encoded as big endian two's
+///   complement with minimal length (i.e. `BigInt::to_signed_bytes_be`).
+/// - Booleans are encoded as a single `0` or `1` byte.
+/// - Byte arrays (e.g. addresses) and strings are encoded as is.
+/// - Lists are encoded as a json list of 0x prefixed hex strings, see [`List`].
+pub trait AttributeCodec: Sized {
+    /// Encodes the value into its attribute byte representation.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Decodes a value from its attribute byte representation.
+    fn decode(bytes: &[u8]) -> Result<Self, String>;
+}
+
+impl AttributeCodec for BigInt {
+    fn encode(&self) -> Vec<u8> {
+        self.to_signed_bytes_be()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        Ok(BigInt::from_signed_bytes_be(bytes))
+    }
+}
+
+macro_rules! impl_unsigned_codec {
+    ($($t:ty),*) => {
+        $(
+            impl AttributeCodec for $t {
+                fn encode(&self) -> Vec<u8> {
+                    num_bigint::BigInt::from(*self).to_signed_bytes_be()
+                }
+
+                fn decode(bytes: &[u8]) -> Result<Self, String> {
+                    if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+                        return Err(format!(
+                            "Negative value 0x{} can't be decoded as {}",
+                            hex::encode(bytes),
+                            stringify!($t)
+                        ));
+                    }
+                    let value = trim_leading(bytes, 0);
+                    let mut buf = [0u8; std::mem::size_of::<$t>()];
+                    if value.len() > buf.len() {
+                        return Err(format!(
+                            "Value 0x{} overflows {}",
+                            hex::encode(bytes),
+                            stringify!($t)
+                        ));
+                    }
+                    let offset = buf.len() - value.len();
+                    buf[offset..].copy_from_slice(value);
+                    Ok(<$t>::from_be_bytes(buf))
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed_codec {
+    ($($t:ty),*) => {
+        $(
+            impl AttributeCodec for $t {
+                fn encode(&self) -> Vec<u8> {
+                    num_bigint::BigInt::from(*self).to_signed_bytes_be()
+                }
+
+                fn decode(bytes: &[u8]) -> Result<Self, String> {
+                    let padding = if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+                        0xff
+                    } else {
+                        0
+                    };
+                    // Strip redundant sign extension bytes.
+                    let mut value = bytes;
+                    while value.len() > 1 &&
+                        value[0] == padding &&
+                        (value[1] & 0x80) == (padding & 0x80)
+                    {
+                        value = &value[1..];
+                    }
+                    let mut buf = [padding; std::mem::size_of::<$t>()];
+                    if value.len() > buf.len() {
+                        return Err(format!(
+                            "Value 0x{} overflows {}",
+                            hex::encode(bytes),
+                            stringify!($t)
+                        ));
+                    }
+                    let offset = buf.len() - value.len();
+                    buf[offset..].copy_from_slice(value);
+                    Ok(<$t>::from_be_bytes(buf))
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned_codec!(u8, u16, u32, u64, u128);
+impl_signed_codec!(i8, i16, i32, i64, i128);
+
+fn trim_leading(bytes: &[u8], value: u8) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| *b != value)
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+impl AttributeCodec for bool {
+    fn encode(&self) -> Vec<u8> {
+        vec![u8::from(*self)]
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(format!("Invalid bool value 0x{}", hex::encode(bytes))),
+        }
+    }
+}
+
+/// Fixed size byte arrays, e.g. addresses (`[u8; 20]`) or hashes (`[u8; 32]`).
+impl<const N: usize> AttributeCodec for [u8; N] {
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        bytes
+            .try_into()
+            .map_err(|_| format!("Expected {N} bytes, got {}", bytes.len()))
+    }
+}
+
+impl AttributeCodec for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl AttributeCodec for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| format!("Invalid utf-8 value: {e}"))
+    }
+}
+
+/// A fixed point decimal number with `DECIMALS` decimal places.
+///
+/// Encoded as its scaled integer representation, e.g. `FixedPoint::<18>` encodes `1.5` as
+/// `1500000000000000000`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixedPoint<const DECIMALS: u32>(pub BigInt);
+
+impl<const DECIMALS: u32> AttributeCodec for FixedPoint<DECIMALS> {
+    fn encode(&self) -> Vec<u8> {
+        self.0.encode()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        BigInt::decode(bytes).map(Self)
+    }
+}
+
+impl<const DECIMALS: u32> std::fmt::Display for FixedPoint<DECIMALS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.0.absolute().to_string();
+        let sign = if self.0 < BigInt::zero() { "-" } else { "" };
+        let decimals = DECIMALS as usize;
+        if decimals == 0 {
+            return write!(f, "{sign}{digits}");
+        }
+        let digits = format!("{digits:0>width$}", width = decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        write!(f, "{sign}{integer}.{fraction}")
+    }
+}
+
+/// A list of values, encoded as a json list of 0x prefixed hex strings of each encoded element.
+///
+/// This is compatible with `json_serialize_address_list` and `json_serialize_bigint_list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T: AttributeCodec> AttributeCodec for List<T> {
+    fn encode(&self) -> Vec<u8> {
+        json_serialize_value(
+            self.0
+                .iter()
+                .map(|v| format!("0x{}", hex::encode(v.encode())))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let values: Vec<String> =
+            serde_json::from_slice(bytes).map_err(|e| format!("Invalid json list: {e}"))?;
+        values
+            .iter()
+            .map(|v| {
+                let encoded = hex::decode(v.strip_prefix("0x").unwrap_or(v))
+                    .map_err(|e| format!("Invalid hex list element {v}: {e}"))?;
+                T::decode(&encoded)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::{fmt::Debug, str::FromStr};
+
+    fn roundtrip<T: AttributeCodec + PartialEq + Debug>(value: T) -> Vec<u8> {
+        let encoded = value.encode();
+        assert_eq!(T::decode(&encoded).unwrap(), value);
+        encoded
+    }
+
+    #[rstest]
+    #[case::zero(0, vec![0x00])]
+    #[case::small(127, vec![0x7f])]
+    #[case::sign_bit(128, vec![0x00, 0x80])]
+    #[case::max(u64::MAX, vec![0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff])]
+    fn test_unsigned_codec(#[case] value: u64, #[case] expected: Vec<u8>) {
+        assert_eq!(roundtrip(value), expected);
+        assert_eq!(expected, BigInt::from(value).to_signed_bytes_be());
+    }
+
+    #[rstest]
+    #[case::negative(-1, vec![0xff])]
+    #[case::negative_sign_bit(-129, vec![0xff, 0x7f])]
+    #[case::positive_sign_bit(128, vec![0x00, 0x80])]
+    #[case::min(i32::MIN, vec![0x80, 0x00, 0x00, 0x00])]
+    fn test_signed_codec(#[case] value: i32, #[case] expected: Vec<u8>) {
+        assert_eq!(roundtrip(value), expected);
+    }
+
+    #[test]
+    fn test_integer_decoding_errors() {
+        assert!(u8::decode(&[0x01, 0x00]).is_err());
+        assert!(u8::decode(&[0xff]).is_err());
+        assert!(i8::decode(&[0x00, 0x80]).is_err());
+        assert_eq!(i8::decode(&[0xff, 0x80]), Ok(-128));
+        assert_eq!(u8::decode(&[0x00, 0x00, 0xff]), Ok(255));
+    }
+
+    #[test]
+    fn test_bigint_codec() {
+        let value = BigInt::from_str(
+            "-115792089237316195423570985008687907853269984665640564039457584007913129639935",
+        )
+        .unwrap();
+        roundtrip(value);
+    }
+
+    #[test]
+    fn test_bool_codec() {
+        assert_eq!(roundtrip(true), vec![1]);
+        assert_eq!(roundtrip(false), vec![0]);
+        assert!(bool::decode(&[2]).is_err());
+    }
+
+    #[test]
+    fn test_address_codec() {
+        let address = [0x42u8; 20];
+        assert_eq!(roundtrip(address), address.to_vec());
+        assert!(<[u8; 20]>::decode(&[0x42; 32]).is_err());
+    }
+
+    #[test]
+    fn test_fixed_point_codec() {
+        let value = FixedPoint::<4>(BigInt::from(-15000));
+        assert_eq!(roundtrip(value.clone()), BigInt::from(-15000).to_signed_bytes_be());
+        assert_eq!(value.to_string(), "-1.5000");
+        assert_eq!(FixedPoint::<4>(BigInt::from(5)).to_string(), "0.0005");
+        assert_eq!(FixedPoint::<0>(BigInt::from(5)).to_string(), "5");
+    }
+
+    #[test]
+    fn test_list_codec_compatible_with_json_helpers() {
+        let addresses = vec![[0x01u8; 20], [0x02u8; 20]];
+        let bigints = vec![BigInt::from(1), BigInt::from(-1)];
+
+        assert_eq!(
+            roundtrip(List(addresses.clone())),
+            json_serialize_address_list(
+                &addresses
+                    .iter()
+                    .map(|a| a.to_vec())
+                    .collect::<Vec<_>>()
+            )
+        );
+        assert_eq!(roundtrip(List(bigints.clone())), json_serialize_bigint_list(&bigints));
+        assert!(List::<bool>::decode(b"[\"0x02\"]").is_err());
+    }
+}
@@ -23,17 +23,83 @@
 
 use crate::{
     abi,
-    pb::tycho::evm::v1::{BalanceChange, BlockBalanceDeltas, Transaction},
+    pb::tycho::evm::v1::{
+        Attribute, BalanceChange, BlockBalanceDeltas, ChangeType, EntityChanges, Transaction,
+    },
     prelude::BalanceDelta,
+    store_key::{KeySegment, StoreKey, SEPARATOR},
 };
-use itertools::Itertools;
 use std::{collections::HashMap, str::FromStr};
 use substreams::{
-    key,
-    pb::substreams::StoreDeltas,
+    pb::substreams::{StoreDelta, StoreDeltas},
     prelude::{BigInt, StoreAdd},
 };
-use substreams_ethereum::{pb::eth::v2::TransactionTrace, Event};
+use substreams_ethereum::{
+    pb::eth::v2::{Log, TransactionTrace},
+    Event,
+};
+
+/// Store key of a component's token balance, `component_id:token_hex`.
+///
+/// Used by `store_balance_changes` and `aggregate_balances_changes`, e.g. to read the balances
+/// stored by the former.
+///
+/// Unlike keys declared with `store_key!`, the component id is kept as is and may contain `:`, so
+/// the token is read from the last segment. Use `decode` rather than `segment_at` on these keys.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BalanceKey {
+    pub component_id: String,
+    pub token: Vec<u8>,
+}
+
+impl StoreKey for BalanceKey {
+    const PREFIX: Option<&'static str> = None;
+
+    fn segments(&self) -> Vec<String> {
+        vec![self.component_id.clone(), self.token.encode_segment()]
+    }
+
+    fn from_segments(segments: &[&str]) -> Result<Self, String> {
+        match segments.split_last() {
+            Some((token, component_id)) if !component_id.is_empty() => Ok(Self {
+                component_id: component_id.join(&SEPARATOR.to_string()),
+                token: Vec::<u8>::decode_segment(token)
+                    .map_err(|e| format!("invalid token: {e}"))?,
+            }),
+            _ => Err(format!("expected at least 2 segments, got {}", segments.len())),
+        }
+    }
+
+    fn try_encode(&self) -> Result<String, String> {
+        Ok(self
+            .segments()
+            .join(&SEPARATOR.to_string()))
+    }
+
+    fn decode(key: &str) -> Result<Self, String> {
+        let segments = key.split(SEPARATOR).collect::<Vec<_>>();
+        Self::from_segments(&segments).map_err(|e| format!("invalid key {key}: {e}"))
+    }
+}
+
+impl BalanceKey {
+    /// Returns the balance key of a delta.
+    ///
+    /// ## Errors
+    /// Returns `BalanceError::InvalidComponentId` if the component id is not valid UTF-8.
+    fn try_from_delta(delta: &BalanceDelta) -> Result<(Self, String), BalanceError> {
+        let component_id = String::from_utf8(delta.component_id.clone()).map_err(|_| {
+            BalanceError::InvalidComponentId {
+                component_id: delta.component_id.clone(),
+                token: delta.token.clone(),
+                ordinal: delta.ord,
+            }
+        })?;
+        let key = Self { component_id, token: delta.token.clone() };
+        let encoded = key.encode();
+        Ok((key, encoded))
+    }
+}
 
 /// Stores relative balance changes in an additive manner.
 ///
@@ -58,39 +124,82 @@ use substreams_ethereum::{pb::eth::v2::TransactionTrace, Event};
 /// This function will panic if:
 /// - The `component_id` of any delta is not valid UTF-8.
 /// - The ordinals for any given token address are not strictly increasing.
+///
+/// See `try_store_balance_changes` for a non-panicking version.
 pub fn store_balance_changes(deltas: BlockBalanceDeltas, store: impl StoreAdd<BigInt>) {
+    try_store_balance_changes(deltas, store).unwrap_or_else(|e| panic!("{e}"));
+}
+
+/// Stores relative balance changes in an additive manner, see `store_balance_changes`.
+///
+/// All deltas are validated before any of them is added to the store, so the store is left
+/// untouched if an error is returned.
+///
+/// ## Errors
+/// - `BalanceError::InvalidComponentId` if the `component_id` of any delta is not valid UTF-8.
+/// - `BalanceError::InvalidOrdinal` if the ordinals for any given token address are not strictly
+///   increasing.
+pub fn try_store_balance_changes(
+    deltas: BlockBalanceDeltas,
+    store: impl StoreAdd<BigInt>,
+) -> Result<(), BalanceError> {
     let mut previous_ordinal = HashMap::<String, u64>::new();
-    deltas
-        .balance_deltas
-        .iter()
-        .for_each(|delta| {
-            let balance_key = format!(
-                "{0}:{1}",
-                String::from_utf8(delta.component_id.clone())
-                    .expect("delta.component_id is not valid utf-8!"),
-                hex::encode(&delta.token)
-            );
-            let current_ord = delta.ord;
-            previous_ordinal
-                .entry(balance_key.clone())
-                .and_modify(|ord| {
-                    // ordinals must arrive in increasing order
-                    if *ord >= current_ord {
-                        panic!(
-                            "Invalid ordinal sequence for {}: {} >= {}",
-                            balance_key, *ord, current_ord
-                        );
-                    }
-                    *ord = current_ord;
-                })
-                .or_insert(delta.ord);
-            store.add(delta.ord, balance_key, BigInt::from_signed_bytes_be(&delta.delta));
-        });
+    let mut additions = Vec::with_capacity(deltas.balance_deltas.len());
+    for delta in deltas.balance_deltas.iter() {
+        let (BalanceKey { component_id, .. }, balance_key) = BalanceKey::try_from_delta(delta)?;
+        // ordinals must arrive in increasing order
+        if let Some(previous) = previous_ordinal.insert(balance_key.clone(), delta.ord) {
+            if previous >= delta.ord {
+                return Err(BalanceError::InvalidOrdinal {
+                    component_id,
+                    token: delta.token.clone(),
+                    ordinal: delta.ord,
+                    previous_ordinal: previous,
+                });
+            }
+        }
+        additions.push((delta.ord, balance_key, BigInt::from_signed_bytes_be(&delta.delta)));
+    }
+
+    for (ord, balance_key, value) in additions {
+        store.add(ord, balance_key, value);
+    }
+    Ok(())
 }
 
-type TxAggregatedBalances =
+/// Absolute balance changes per transaction hash, then component id and token.
+pub type TxAggregatedBalances =
     HashMap<Vec<u8>, (Transaction, HashMap<Vec<u8>, HashMap<Vec<u8>, BalanceChange>>)>;
 
+/// Prefix of the diagnostic attributes emitted by `NegativeBalancePolicy::Diagnostic`.
+///
+/// The attribute name is the prefix followed by the hex encoded token address.
+pub const NEGATIVE_BALANCE_ATTRIBUTE_PREFIX: &str = "negative_balance_";
+
+/// Defines how negative absolute balances are handled during aggregation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NegativeBalancePolicy {
+    /// Clip the balance to zero.
+    #[default]
+    Clip,
+    /// Fail the aggregation with `BalanceError::NegativeBalance`.
+    Error,
+    /// Clip the balance to zero and emit an attribute on the component recording the negative
+    /// balance, see `NEGATIVE_BALANCE_ATTRIBUTE_PREFIX`.
+    Diagnostic,
+}
+
+/// Absolute balances per transaction, as returned by `try_aggregate_balances_changes`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AggregatedBalances {
+    /// A map of transactions hashes to a tuple of `Transaction` and aggregated absolute balance
+    /// changes.
+    pub balances: TxAggregatedBalances,
+    /// A map of transaction hashes to diagnostic attributes recording negative balances. Only
+    /// populated with `NegativeBalancePolicy::Diagnostic`.
+    pub diagnostics: HashMap<Vec<u8>, Vec<EntityChanges>>,
+}
+
 /// Aggregates absolute balances per transaction and token.
 ///
 /// ## Arguments
@@ -98,20 +207,20 @@ type TxAggregatedBalances =
 /// * `deltas` - A `BlockBalanceDeltas` message containing the relative balances changes.
 ///
 /// This function reads absolute balance values from an additive store (see `store_balance_changes`
-/// for how to create such a store). It zips these values with the relative balance deltas to
-/// associate balance values with tokens and components, ensuring the last balance change for each
-/// unique combination of component, token, and transaction is kept if there are multiple changes.
-/// Negative balances are set to 0, adhering to the expectation that absolute balances must be
-/// non-negative.
+/// for how to create such a store). It joins these values with the relative balance deltas by
+/// balance key (`component_id:token`) and ordinal to associate balance values with transactions,
+/// ensuring the last balance change for each unique combination of component, token, and
+/// transaction is kept if there are multiple changes.
 ///
-/// Will keep the last balance change for each unique combination of component, token, and
-/// transaction if there are multiple changes. In case a balance ends up being negative, it will be
-/// clipped to 0 since absolute balances are expected to be either zero or positive.
+/// Negative balances are clipped to 0 (`NegativeBalancePolicy::Clip`), since absolute balances are
+/// expected to be either zero or positive. Use `try_aggregate_balances_changes` to fail or emit a
+/// diagnostic attribute instead.
 ///
 /// ## Panics
 /// May panic if the store deltas values are not in the correct format. Values are
 /// expected to be utf-8 encoded string integers, which is the default behaviour
-/// for substreams stores.
+/// for substreams stores. Panics as well if store deltas and balance deltas do not match
+/// one-to-one. See `try_aggregate_balances_changes` for a non-panicking version.
 ///
 /// ## Returns
 /// A map of transactions hashes to a tuple of `Transaction` and aggregated
@@ -120,56 +229,220 @@ pub fn aggregate_balances_changes(
     balance_store: StoreDeltas,
     deltas: BlockBalanceDeltas,
 ) -> TxAggregatedBalances {
-    balance_store
-        .deltas
-        .into_iter()
-        .zip(deltas.balance_deltas)
-        .map(|(store_delta, balance_delta)| {
-            let component_id = key::segment_at(&store_delta.key, 0);
-            let token_id = key::segment_at(&store_delta.key, 1);
-            // store_delta.new_value is an ASCII string representing an integer
-            let ascii_string =
-                String::from_utf8(store_delta.new_value.clone()).expect("Invalid UTF-8 sequence");
-            let balance = BigInt::from_str(&ascii_string).expect("Failed to parse integer");
-
-            // If the absolute balance is negative, we set it to zero.
-            let big_endian_bytes_balance = if balance < BigInt::zero() {
-                BigInt::zero().to_bytes_be().1
-            } else {
-                balance.to_bytes_be().1
-            };
+    try_aggregate_balances_changes(balance_store, deltas, NegativeBalancePolicy::Clip)
+        .unwrap_or_else(|e| panic!("{e}"))
+        .balances
+}
 
-            (
-                balance_delta
-                    .tx
-                    .expect("Missing transaction on delta"),
+/// Aggregates absolute balances per transaction and token, see `aggregate_balances_changes`.
+///
+/// ## Arguments
+/// * `balance_store` - A `StoreDeltas` with all changes that occurred in the source store module.
+/// * `deltas` - A `BlockBalanceDeltas` message containing the relative balances changes.
+/// * `negative_balance_policy` - How to handle absolute balances that end up being negative.
+///
+/// ## Errors
+/// - `BalanceError::InvalidStoreDelta` if a store delta key or value is malformed.
+/// - `BalanceError::InvalidComponentId` if the `component_id` of any delta is not valid UTF-8.
+/// - `BalanceError::MissingTransaction` if a balance delta has no transaction.
+/// - `BalanceError::DeltaMismatch` if a store delta has no balance delta with the same key and
+///   ordinal, or vice versa.
+/// - `BalanceError::NegativeBalance` if a balance is negative and the policy is
+///   `NegativeBalancePolicy::Error`.
+pub fn try_aggregate_balances_changes(
+    balance_store: StoreDeltas,
+    deltas: BlockBalanceDeltas,
+    negative_balance_policy: NegativeBalancePolicy,
+) -> Result<AggregatedBalances, BalanceError> {
+    // Index the transactions of the balance deltas by store key and ordinal. Deltas are joined on
+    // these instead of their position, since the store may emit its deltas in a different order.
+    let mut transactions: HashMap<(String, u64), (&Transaction, bool)> = HashMap::new();
+    for delta in deltas.balance_deltas.iter() {
+        let (BalanceKey { component_id, .. }, balance_key) = BalanceKey::try_from_delta(delta)?;
+        let tx = delta
+            .tx
+            .as_ref()
+            .ok_or_else(|| BalanceError::MissingTransaction {
+                component_id: component_id.clone(),
+                token: delta.token.clone(),
+                ordinal: delta.ord,
+            })?;
+        if let Some((other_tx, _)) =
+            transactions.insert((balance_key.clone(), delta.ord), (tx, false))
+        {
+            if other_tx.hash != tx.hash {
+                return Err(BalanceError::DeltaMismatch {
+                    key: balance_key,
+                    ordinal: delta.ord,
+                    reason: "balance deltas of different transactions share the same ordinal"
+                        .to_string(),
+                });
+            }
+        }
+    }
+
+    let mut store_deltas = balance_store.deltas;
+    store_deltas.sort_by_key(|delta| delta.ordinal);
+
+    let mut aggregated = AggregatedBalances::default();
+    for store_delta in store_deltas {
+        let (component_id, token) = parse_balance_key(&store_delta.key, store_delta.ordinal)?;
+        let balance = parse_balance_value(&store_delta)?;
+        let tx = match transactions.get_mut(&(store_delta.key.clone(), store_delta.ordinal)) {
+            Some((tx, matched)) => {
+                *matched = true;
+                (*tx).clone()
+            }
+            None => {
+                return Err(BalanceError::DeltaMismatch {
+                    key: store_delta.key,
+                    ordinal: store_delta.ordinal,
+                    reason: "no balance delta for store delta".to_string(),
+                })
+            }
+        };
+
+        let balance = if balance < BigInt::zero() {
+            match negative_balance_policy {
+                NegativeBalancePolicy::Clip => BigInt::zero(),
+                NegativeBalancePolicy::Error => {
+                    return Err(BalanceError::NegativeBalance {
+                        component_id,
+                        token,
+                        ordinal: store_delta.ordinal,
+                        balance,
+                    })
+                }
+                NegativeBalancePolicy::Diagnostic => {
+                    aggregated
+                        .diagnostics
+                        .entry(tx.hash.clone())
+                        .or_default()
+                        .push(EntityChanges {
+                            component_id: component_id.clone(),
+                            attributes: vec![Attribute {
+                                name: format!(
+                                    "{NEGATIVE_BALANCE_ATTRIBUTE_PREFIX}{}",
+                                    hex::encode(&token)
+                                ),
+                                value: balance.to_signed_bytes_be(),
+                                change: ChangeType::Update.into(),
+                            }],
+                        });
+                    BigInt::zero()
+                }
+            }
+        } else {
+            balance
+        };
+
+        let (_, components) = aggregated
+            .balances
+            .entry(tx.hash.clone())
+            .or_insert_with(|| (tx, HashMap::new()));
+        // Insert or overwrite the balance change for the specific token, store deltas are sorted
+        // by ordinal so the last change is kept.
+        components
+            .entry(component_id.as_bytes().to_vec())
+            .or_default()
+            .insert(
+                token.clone(),
                 BalanceChange {
-                    token: hex::decode(token_id).expect("Token ID not valid hex"),
-                    balance: big_endian_bytes_balance,
+                    token,
+                    balance: balance.to_bytes_be().1,
                     component_id: component_id.as_bytes().to_vec(),
                 },
-            )
-        })
-        // Group balance changes by tx hash so callers can merge them into `TransactionChanges`
-        .group_by(|(tx, _)| tx.hash.clone())
+            );
+    }
+
+    if let Some(((key, ordinal), _)) = transactions
         .into_iter()
-        .map(|(txh, group)| {
-            let (mut transactions, balance_changes): (Vec<_>, Vec<_>) = group.into_iter().unzip();
-            let mut balances: HashMap<Vec<u8>, HashMap<Vec<u8>, BalanceChange>> = HashMap::new();
+        .filter(|(_, (_, matched))| !matched)
+        .min_by(|(a, _), (b, _)| (a.1, &a.0).cmp(&(b.1, &b.0)))
+    {
+        return Err(BalanceError::DeltaMismatch {
+            key,
+            ordinal,
+            reason: "no store delta for balance delta".to_string(),
+        });
+    }
+    Ok(aggregated)
+}
 
-            for balance_change in balance_changes.into_iter() {
-                let component_entry = balances
-                    .entry(balance_change.component_id.clone())
-                    .or_default();
+/// Parses a balance store key of the form `component_id:token_hex`.
+fn parse_balance_key(key: &str, ordinal: u64) -> Result<(String, Vec<u8>), BalanceError> {
+    let BalanceKey { component_id, token } = BalanceKey::decode(key).map_err(|reason| {
+        BalanceError::InvalidStoreDelta { key: key.to_string(), ordinal, reason }
+    })?;
+    Ok((component_id, token))
+}
 
-                // Insert or overwrite the balance change for the specific token
-                component_entry.insert(balance_change.token.clone(), balance_change);
-            }
-            (txh, (transactions.pop().unwrap(), balances))
+/// Parses a balance store value, an utf-8 encoded string integer.
+fn parse_balance_value(store_delta: &StoreDelta) -> Result<BigInt, BalanceError> {
+    std::str::from_utf8(&store_delta.new_value)
+        .ok()
+        .and_then(|value| BigInt::from_str(value).ok())
+        .ok_or_else(|| BalanceError::InvalidStoreDelta {
+            key: store_delta.key.clone(),
+            ordinal: store_delta.ordinal,
+            reason: format!("invalid integer value 0x{}", hex::encode(&store_delta.new_value)),
         })
-        .collect()
 }
 
+/// Error raised while storing or aggregating balance changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceError {
+    /// The component id of a balance delta is not valid utf-8.
+    InvalidComponentId { component_id: Vec<u8>, token: Vec<u8>, ordinal: u64 },
+    /// The balance deltas of a component's token do not have strictly increasing ordinals.
+    InvalidOrdinal { component_id: String, token: Vec<u8>, ordinal: u64, previous_ordinal: u64 },
+    /// A balance delta is missing its transaction.
+    MissingTransaction { component_id: String, token: Vec<u8>, ordinal: u64 },
+    /// A store delta has a malformed key or value.
+    InvalidStoreDelta { key: String, ordinal: u64, reason: String },
+    /// Store deltas and balance deltas do not match one-to-one by key and ordinal, e.g. because
+    /// one of them was filtered.
+    DeltaMismatch { key: String, ordinal: u64, reason: String },
+    /// An absolute balance is negative.
+    NegativeBalance { component_id: String, token: Vec<u8>, ordinal: u64, balance: BigInt },
+}
+
+impl std::fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidComponentId { component_id, token, ordinal } => write!(
+                f,
+                "Component id 0x{} is not valid utf-8 (token: 0x{}, ordinal: {ordinal})",
+                hex::encode(component_id),
+                hex::encode(token)
+            ),
+            Self::InvalidOrdinal { component_id, token, ordinal, previous_ordinal } => write!(
+                f,
+                "Invalid ordinal sequence for {component_id}:{}: {previous_ordinal} >= {ordinal}",
+                hex::encode(token)
+            ),
+            Self::MissingTransaction { component_id, token, ordinal } => write!(
+                f,
+                "Missing transaction on delta for {component_id}:{} (ordinal: {ordinal})",
+                hex::encode(token)
+            ),
+            Self::InvalidStoreDelta { key, ordinal, reason } => {
+                write!(f, "Invalid store delta for {key} (ordinal: {ordinal}): {reason}")
+            }
+            Self::DeltaMismatch { key, ordinal, reason } => {
+                write!(f, "Balance delta mismatch for {key} (ordinal: {ordinal}): {reason}")
+            }
+            Self::NegativeBalance { component_id, token, ordinal, balance } => write!(
+                f,
+                "Negative balance {balance} for {component_id}:{} (ordinal: {ordinal})",
+                hex::encode(token)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
 /// Extracts balance deltas from a transaction trace based on a given address predicate.
 ///
 /// This function processes the logs within a transaction trace to identify ERC-20 token
@@ -207,41 +480,237 @@ pub fn aggregate_balances_changes(
 ///   design, this function may not be applicable.
 /// - The `address_predicate` is applied to both the log address and the `from`/`to` addresses in
 ///   the transfer event.
+/// - Use `extract_balance_deltas_with_registry` for tokens that do not follow the standard ERC-20
+///   accounting, e.g. rebasing tokens.
 pub fn extract_balance_deltas_from_tx<F: Fn(&[u8], &[u8]) -> bool>(
     tx: &TransactionTrace,
     address_predicate: F,
+) -> Vec<BalanceDelta> {
+    extract_balance_deltas_with_registry(tx, &TokenRegistry::default(), address_predicate)
+}
+
+/// Decodes custom balance changing events, see `TokenBehaviour::Custom`.
+///
+/// Returns the holder and the signed balance change for each balance affected by the log.
+pub type BalanceEventDecoder = Box<dyn Fn(&Log) -> Vec<(Vec<u8>, BigInt)>>;
+
+/// Describes how the balances of a token deviating from the standard ERC-20 accounting are
+/// observed.
+///
+/// Tokens charging a fee on transfer need no special handling: balance deltas are derived from
+/// the emitted `Transfer` events, which reflect the amounts actually moved.
+pub enum TokenBehaviour {
+    /// A rebasing token backed by shares, e.g. stETH.
+    ///
+    /// Balances of rebasing tokens change without any event, so they are tracked in shares
+    /// instead, using the `TransferShares` events. `Transfer` events of the token are ignored.
+    RebasingShares,
+    /// An ERC-4626 vault, registered under the vault address.
+    ///
+    /// The underlying `asset` held by the vault is tracked using the vault's `Deposit` and
+    /// `Withdraw` events, since the vault may hold its assets elsewhere. `Transfer` events of the
+    /// asset to or from the vault are ignored. Vault shares are tracked like any ERC-20 token.
+    Erc4626Vault { asset: Vec<u8> },
+    /// A token emitting custom events, e.g. mints or burns without a `Transfer` event.
+    ///
+    /// The decoder is called for every log emitted by the token, in addition to the standard
+    /// ERC-20 handling.
+    Custom(BalanceEventDecoder),
+}
+
+/// Registry of tokens with non standard balance accounting.
+///
+/// Used by `extract_balance_deltas_with_registry`, tokens not part of the registry are handled as
+/// standard ERC-20 tokens.
+///
+/// ## Example
+/// ```ignore
+/// let registry = TokenRegistry::default()
+///     .with_token(&STETH_ADDRESS, TokenBehaviour::RebasingShares)
+///     .with_native_token(&ETH_ADDRESS);
+///
+/// let balance_deltas = extract_balance_deltas_with_registry(&tx, &registry, predicate);
+/// ```
+#[derive(Default)]
+pub struct TokenRegistry {
+    tokens: HashMap<Vec<u8>, TokenBehaviour>,
+    native_token: Option<Vec<u8>>,
+}
+
+impl TokenRegistry {
+    /// Registers the behaviour of a token, replacing any previously registered one.
+    ///
+    /// ## Parameters
+    /// - `address`: The token address, or the vault address for `TokenBehaviour::Erc4626Vault`.
+    /// - `behaviour`: How balance changes of the token are observed.
+    pub fn with_token(mut self, address: &[u8], behaviour: TokenBehaviour) -> Self {
+        self.tokens
+            .insert(address.to_vec(), behaviour);
+        self
+    }
+
+    /// Enables tracking of native balances using the balance changes of the extended block
+    /// model, see `extract_native_balance_deltas_from_tx`.
+    ///
+    /// ## Parameters
+    /// - `address`: The token address native balance deltas are emitted under.
+    pub fn with_native_token(mut self, address: &[u8]) -> Self {
+        self.native_token = Some(address.to_vec());
+        self
+    }
+
+    /// Returns the behaviour registered for an address, if any.
+    pub fn get(&self, address: &[u8]) -> Option<&TokenBehaviour> {
+        self.tokens.get(address)
+    }
+
+    /// Whether transfers of `token` from or to `holder` are tracked by a vault's events instead.
+    fn is_vault_asset(&self, token: &[u8], holder: &[u8]) -> bool {
+        matches!(self.get(holder), Some(TokenBehaviour::Erc4626Vault { asset }) if asset == token)
+    }
+}
+
+/// Extracts balance deltas from a transaction trace, taking token specific behaviours into
+/// account.
+///
+/// Works like `extract_balance_deltas_from_tx`, but handles the tokens of the registry according
+/// to their `TokenBehaviour`. If a native token is configured, native balance deltas are
+/// extracted from the balance changes of the extended block model as well.
+///
+/// ## Arguments
+/// * `tx` - The transaction trace.
+/// * `registry` - The tokens with non standard balance accounting.
+/// * `address_predicate` - A predicate taking a token and a component, deciding whether the balance
+///   changes of the component should be extracted.
+///
+/// ## Returns
+/// The balance deltas of the transaction, sorted by ordinal.
+pub fn extract_balance_deltas_with_registry<F: Fn(&[u8], &[u8]) -> bool>(
+    tx: &TransactionTrace,
+    registry: &TokenRegistry,
+    address_predicate: F,
 ) -> Vec<BalanceDelta> {
     let mut balance_deltas = vec![];
 
     tx.logs_with_calls()
         .for_each(|(log, _)| {
-            let mut create_balance_delta = |transactor: &[u8], delta: BigInt| {
-                balance_deltas.push(BalanceDelta {
-                    ord: log.ordinal,
-                    tx: Some(tx.into()),
-                    token: log.address.clone(),
-                    delta: delta.to_signed_bytes_be(),
-                    component_id: hex::encode(transactor).into(),
-                });
+            let mut create_balance_delta = |token: &[u8], transactor: &[u8], delta: BigInt| {
+                if address_predicate(token, transactor) {
+                    balance_deltas.push(BalanceDelta {
+                        ord: log.ordinal,
+                        tx: Some(tx.into()),
+                        token: token.to_vec(),
+                        delta: delta.to_signed_bytes_be(),
+                        component_id: hex::encode(transactor).into(),
+                    });
+                }
             };
+            match registry.get(&log.address) {
+                Some(TokenBehaviour::RebasingShares) => {
+                    if let Some(transfer) =
+                        abi::steth::events::TransferShares::match_and_decode(log)
+                    {
+                        create_balance_delta(
+                            &log.address,
+                            &transfer.from,
+                            transfer.shares_value.neg(),
+                        );
+                        create_balance_delta(&log.address, &transfer.to, transfer.shares_value);
+                    }
+                    return;
+                }
+                Some(TokenBehaviour::Erc4626Vault { asset }) => {
+                    if let Some(deposit) = abi::erc4626::events::Deposit::match_and_decode(log) {
+                        create_balance_delta(asset, &log.address, deposit.assets);
+                    } else if let Some(withdraw) =
+                        abi::erc4626::events::Withdraw::match_and_decode(log)
+                    {
+                        create_balance_delta(asset, &log.address, withdraw.assets.neg());
+                    }
+                }
+                Some(TokenBehaviour::Custom(decoder)) => {
+                    for (holder, delta) in decoder(log) {
+                        create_balance_delta(&log.address, &holder, delta);
+                    }
+                }
+                None => {}
+            }
+
             if let Some(transfer) = abi::erc20::events::Transfer::match_and_decode(log) {
-                if address_predicate(&log.address, &transfer.from) {
-                    create_balance_delta(&transfer.from, transfer.value.neg());
+                if !registry.is_vault_asset(&log.address, &transfer.from) {
+                    create_balance_delta(&log.address, &transfer.from, transfer.value.neg());
                 }
-                if address_predicate(&log.address, &transfer.to) {
-                    create_balance_delta(&transfer.to, transfer.value);
+                if !registry.is_vault_asset(&log.address, &transfer.to) {
+                    create_balance_delta(&log.address, &transfer.to, transfer.value);
                 }
             } else if let Some(deposit) = abi::weth::events::Deposit::match_and_decode(log) {
-                if address_predicate(&log.address, &deposit.dst) {
-                    create_balance_delta(&deposit.dst, deposit.wad);
-                }
+                create_balance_delta(&log.address, &deposit.dst, deposit.wad);
             } else if let Some(withdrawal) = abi::weth::events::Withdrawal::match_and_decode(log) {
-                if address_predicate(&log.address, &withdrawal.src) {
-                    create_balance_delta(&withdrawal.src, withdrawal.wad.neg());
-                }
+                create_balance_delta(&log.address, &withdrawal.src, withdrawal.wad.neg());
             }
         });
 
+    if let Some(native_token) = registry.native_token.as_ref() {
+        balance_deltas.extend(extract_native_balance_deltas_from_tx(
+            tx,
+            native_token,
+            &address_predicate,
+        ));
+    }
+
+    balance_deltas.sort_by_key(|delta| delta.ord);
+    balance_deltas
+}
+
+/// Extracts native balance deltas from a transaction trace based on a given address predicate.
+///
+/// Native balances (e.g. ETH) do not emit any events. Instead, this function derives the deltas
+/// from the balance changes recorded on the calls of the extended block model. Balance changes of
+/// calls whose state was reverted are ignored, since they were never applied.
+///
+/// The deltas are emitted under `native_token`, so they can be stored and aggregated like any
+/// ERC-20 balance using `store_balance_changes`.
+///
+/// ## Arguments
+/// * `tx` - The transaction trace, from an extended block.
+/// * `native_token` - The token address native balance deltas are emitted under, e.g. the zero
+///   address.
+/// * `address_predicate` - A predicate taking the native token and a component, deciding whether
+///   the balance changes of the component should be extracted.
+///
+/// ## Returns
+/// The native balance deltas of the transaction, sorted by ordinal.
+///
+/// ## Warning
+/// ⚠️ Balance changes are *only* available on the **extended block model**.
+pub fn extract_native_balance_deltas_from_tx<F: Fn(&[u8], &[u8]) -> bool>(
+    tx: &TransactionTrace,
+    native_token: &[u8],
+    address_predicate: F,
+) -> Vec<BalanceDelta> {
+    let mut balance_deltas = tx
+        .calls
+        .iter()
+        .filter(|call| !call.state_reverted)
+        .flat_map(|call| call.balance_changes.iter())
+        .filter(|change| address_predicate(native_token, &change.address))
+        .filter_map(|change| {
+            let value = |v: &Option<substreams_ethereum::pb::eth::v2::BigInt>| {
+                v.as_ref()
+                    .map(|v| BigInt::from_unsigned_bytes_be(&v.bytes))
+                    .unwrap_or_else(BigInt::zero)
+            };
+            let delta = value(&change.new_value) - value(&change.old_value);
+            (delta != BigInt::zero()).then(|| BalanceDelta {
+                ord: change.ordinal,
+                tx: Some(tx.into()),
+                token: native_token.to_vec(),
+                delta: delta.to_signed_bytes_be(),
+                component_id: hex::encode(&change.address).into(),
+            })
+        })
+        .collect::<Vec<_>>();
+    balance_deltas.sort_by_key(|delta| delta.ord);
     balance_deltas
 }
 
@@ -249,10 +718,14 @@ pub fn extract_balance_deltas_from_tx<F: Fn(&[u8], &[u8]) -> bool>(
 mod tests {
     use super::*;
     use crate::{pb::tycho::evm::v1::BalanceDelta, testing::mock_store::MockStore};
+    use rstest::rstest;
     use substreams::{
         pb::substreams::StoreDelta,
         prelude::{StoreGet, StoreNew},
     };
+    use substreams_ethereum::pb::eth::v2::{
+        BalanceChange as EthBalanceChange, BigInt as EthBigInt, Call, TransactionTraceStatus,
+    };
 
     fn block_balance_deltas() -> BlockBalanceDeltas {
         let comp_id = "0x42c0ffee"
@@ -469,4 +942,540 @@ mod tests {
         let res = aggregate_balances_changes(store_deltas, balance_deltas);
         assert_eq!(res, exp);
     }
+
+    #[test]
+    fn test_try_store_balances_invalid_ordinal() {
+        let mut deltas = block_balance_deltas();
+        deltas.balance_deltas[3].ord = 0;
+        let store = <MockStore as StoreNew>::new();
+
+        let res = try_store_balance_changes(deltas, store.clone());
+
+        assert_eq!(
+            res,
+            Err(BalanceError::InvalidOrdinal {
+                component_id: "0x42c0ffee".to_string(),
+                token: hex::decode("bad999").unwrap(),
+                ordinal: 0,
+                previous_ordinal: 0,
+            })
+        );
+        // Nothing is stored if any delta is invalid
+        assert_eq!(store.get_last("0x42c0ffee:babe00"), None);
+    }
+
+    #[test]
+    fn test_try_store_balances_invalid_component_id() {
+        let mut deltas = block_balance_deltas();
+        deltas.balance_deltas[1].component_id = vec![0xff, 0xfe];
+
+        let res = try_store_balance_changes(deltas, <MockStore as StoreNew>::new());
+
+        assert_eq!(
+            res,
+            Err(BalanceError::InvalidComponentId {
+                component_id: vec![0xff, 0xfe],
+                token: hex::decode("babe00").unwrap(),
+                ordinal: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_store_balances_component_id_with_separator() {
+        let mut deltas = block_balance_deltas();
+        for delta in deltas.balance_deltas.iter_mut() {
+            delta.component_id = b"pool:0x42c0ffee".to_vec();
+        }
+        let store = <MockStore as StoreNew>::new();
+
+        store_balance_changes(deltas, store.clone());
+
+        assert_eq!(store.get_last("pool:0x42c0ffee:babe00"), Some(BigInt::from(150)));
+        assert_eq!(
+            BalanceKey::decode("pool:0x42c0ffee:babe00"),
+            Ok(BalanceKey {
+                component_id: "pool:0x42c0ffee".to_string(),
+                token: hex::decode("babe00").unwrap(),
+            })
+        );
+        assert_eq!(
+            BalanceKey::decode("babe00"),
+            Err("invalid key babe00: expected at least 2 segments, got 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_try_aggregate_balances_changes_invalid_store_value() {
+        let mut store_deltas = store_deltas();
+        store_deltas.deltas[2].new_value = b"15O".to_vec();
+
+        let res = try_aggregate_balances_changes(
+            store_deltas,
+            block_balance_deltas(),
+            NegativeBalancePolicy::Clip,
+        );
+
+        assert_eq!(
+            res,
+            Err(BalanceError::InvalidStoreDelta {
+                key: "0x42c0ffee:babe00".to_string(),
+                ordinal: 3,
+                reason: "invalid integer value 0x31354f".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_aggregate_balances_changes_missing_tx() {
+        let mut balance_deltas = block_balance_deltas();
+        balance_deltas.balance_deltas[0].tx = None;
+
+        let res = try_aggregate_balances_changes(
+            store_deltas(),
+            balance_deltas,
+            NegativeBalancePolicy::Clip,
+        );
+
+        assert_eq!(
+            res,
+            Err(BalanceError::MissingTransaction {
+                component_id: "0x42c0ffee".to_string(),
+                token: hex::decode("bad999").unwrap(),
+                ordinal: 0,
+            })
+        );
+    }
+
+    #[rstest]
+    #[case::clip(NegativeBalancePolicy::Clip)]
+    #[case::error(NegativeBalancePolicy::Error)]
+    #[case::diagnostic(NegativeBalancePolicy::Diagnostic)]
+    fn test_try_aggregate_balances_changes_negative_balance(#[case] policy: NegativeBalancePolicy) {
+        let mut store_deltas = store_deltas();
+        store_deltas.deltas[3].new_value = b"-1".to_vec();
+        let token_0 = hex::decode("bad999").unwrap();
+
+        let res = try_aggregate_balances_changes(store_deltas, block_balance_deltas(), policy);
+
+        if policy == NegativeBalancePolicy::Error {
+            assert_eq!(
+                res,
+                Err(BalanceError::NegativeBalance {
+                    component_id: "0x42c0ffee".to_string(),
+                    token: token_0,
+                    ordinal: 10,
+                    balance: BigInt::from(-1),
+                })
+            );
+            return;
+        }
+
+        let aggregated = res.unwrap();
+        let (_, components) = &aggregated.balances[&vec![0, 1]];
+        assert_eq!(components[b"0x42c0ffee".as_slice()][&token_0].balance, vec![0]);
+        if policy == NegativeBalancePolicy::Diagnostic {
+            assert_eq!(
+                aggregated.diagnostics[&vec![0, 1]],
+                vec![EntityChanges {
+                    component_id: "0x42c0ffee".to_string(),
+                    attributes: vec![Attribute {
+                        name: "negative_balance_bad999".to_string(),
+                        value: vec![0xff],
+                        change: ChangeType::Update.into(),
+                    }],
+                }]
+            );
+        } else {
+            assert!(aggregated.diagnostics.is_empty());
+        }
+    }
+
+    /// Minimal xorshift generator, keeps the randomized tests deterministic.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                items.swap(i, self.below(i + 1));
+            }
+        }
+    }
+
+    /// Generates random balance deltas over a few transactions, components and tokens, with
+    /// strictly increasing ordinals.
+    fn random_balance_deltas(rng: &mut Rng) -> BlockBalanceDeltas {
+        let mut ord = 0;
+        let balance_deltas = (0..1 + rng.below(4))
+            .flat_map(|tx_index| {
+                let tx = Transaction {
+                    hash: vec![tx_index as u8; 32],
+                    from: vec![9, 9],
+                    to: vec![8, 8],
+                    index: tx_index as u64,
+                };
+                (0..1 + rng.below(6))
+                    .map(|_| {
+                        ord += 1 + rng.below(3) as u64;
+                        BalanceDelta {
+                            ord,
+                            tx: Some(tx.clone()),
+                            token: vec![rng.below(3) as u8; 20],
+                            delta: BigInt::from(rng.below(2000) as i64 - 500).to_signed_bytes_be(),
+                            component_id: format!("0x{:02x}", rng.below(3))
+                                .as_bytes()
+                                .to_vec(),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        BlockBalanceDeltas { balance_deltas }
+    }
+
+    /// Computes the expected absolute balances by replaying the deltas in order.
+    fn expected_balances(deltas: &BlockBalanceDeltas) -> TxAggregatedBalances {
+        let mut running = HashMap::<(Vec<u8>, Vec<u8>), BigInt>::new();
+        let mut expected = TxAggregatedBalances::new();
+        for delta in deltas.balance_deltas.iter() {
+            let balance = running
+                .entry((delta.component_id.clone(), delta.token.clone()))
+                .or_insert_with(BigInt::zero);
+            *balance = balance.clone() + BigInt::from_signed_bytes_be(&delta.delta);
+            let clipped = if *balance < BigInt::zero() { BigInt::zero() } else { balance.clone() };
+            let tx = delta.tx.clone().unwrap();
+            expected
+                .entry(tx.hash.clone())
+                .or_insert_with(|| (tx, HashMap::new()))
+                .1
+                .entry(delta.component_id.clone())
+                .or_default()
+                .insert(
+                    delta.token.clone(),
+                    BalanceChange {
+                        token: delta.token.clone(),
+                        balance: clipped.to_bytes_be().1,
+                        component_id: delta.component_id.clone(),
+                    },
+                );
+        }
+        expected
+    }
+
+    #[test]
+    fn test_aggregate_balances_changes_shuffled_deltas() {
+        for seed in 1..200 {
+            let mut rng = Rng(seed);
+            let deltas = random_balance_deltas(&mut rng);
+            let store = <MockStore as StoreNew>::new();
+            store_balance_changes(deltas.clone(), store.clone());
+            let expected = expected_balances(&deltas);
+
+            let mut store_deltas = store.deltas();
+            let mut shuffled_deltas = deltas.clone();
+            rng.shuffle(&mut store_deltas.deltas);
+            rng.shuffle(&mut shuffled_deltas.balance_deltas);
+
+            assert_eq!(
+                aggregate_balances_changes(store_deltas, shuffled_deltas),
+                expected,
+                "seed {seed}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_aggregate_balances_changes_filtered_deltas() {
+        for seed in 1..200 {
+            let mut rng = Rng(seed);
+            let deltas = random_balance_deltas(&mut rng);
+            let store = <MockStore as StoreNew>::new();
+            store_balance_changes(deltas.clone(), store.clone());
+
+            // Drop a balance delta, its store delta is left unmatched
+            let mut filtered_deltas = deltas.clone();
+            let removed = filtered_deltas
+                .balance_deltas
+                .remove(rng.below(deltas.balance_deltas.len()));
+            let res = try_aggregate_balances_changes(
+                store.deltas(),
+                filtered_deltas,
+                NegativeBalancePolicy::Clip,
+            );
+            let mismatched_ordinal = match res {
+                Err(BalanceError::DeltaMismatch { ordinal, .. }) => ordinal,
+                _ => panic!("seed {seed}: expected a mismatch, got {res:?}"),
+            };
+            assert_eq!(mismatched_ordinal, removed.ord, "seed {seed}");
+
+            // Drop a store delta, its balance delta is left unmatched
+            let mut filtered_store_deltas = store.deltas();
+            let removed = filtered_store_deltas
+                .deltas
+                .remove(rng.below(deltas.balance_deltas.len()));
+            let res = try_aggregate_balances_changes(
+                filtered_store_deltas,
+                deltas,
+                NegativeBalancePolicy::Clip,
+            );
+            assert_eq!(
+                res,
+                Err(BalanceError::DeltaMismatch {
+                    key: removed.key,
+                    ordinal: removed.ordinal,
+                    reason: "no store delta for balance delta".to_string(),
+                }),
+                "seed {seed}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_aggregate_balances_changes_conflicting_transactions() {
+        let mut balance_deltas = block_balance_deltas();
+        let mut duplicate = balance_deltas.balance_deltas[1].clone();
+        duplicate.tx.as_mut().unwrap().hash = vec![0, 2];
+        balance_deltas
+            .balance_deltas
+            .push(duplicate);
+
+        let res = try_aggregate_balances_changes(
+            store_deltas(),
+            balance_deltas,
+            NegativeBalancePolicy::Clip,
+        );
+
+        assert!(matches!(res, Err(BalanceError::DeltaMismatch { ordinal: 2, .. })));
+    }
+
+    const TRANSFER_TOPIC: &str = "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+    const TRANSFER_SHARES_TOPIC: &str =
+        "9d9c909296d9c674451c0c24f02cb64981eb3b727f99865939192f880a755dcb";
+    const VAULT_DEPOSIT_TOPIC: &str =
+        "dcbc1c05240f31ff3ad067ef1ee35ce4997762752e3a095284754544f4c709d7";
+
+    const TOKEN: [u8; 20] = [0x11; 20];
+    const VAULT: [u8; 20] = [0x22; 20];
+    const COMPONENT: [u8; 20] = [0x33; 20];
+    const USER: [u8; 20] = [0x44; 20];
+
+    fn word(value: &[u8]) -> Vec<u8> {
+        let mut word = vec![0u8; 32 - value.len()];
+        word.extend_from_slice(value);
+        word
+    }
+
+    fn event_log(
+        address: &[u8],
+        topic: &str,
+        indexed: &[&[u8]],
+        data: &[u64],
+        ordinal: u64,
+    ) -> Log {
+        Log {
+            address: address.to_vec(),
+            topics: std::iter::once(hex::decode(topic).unwrap())
+                .chain(indexed.iter().map(|a| word(a)))
+                .collect(),
+            data: data
+                .iter()
+                .flat_map(|v| word(&v.to_be_bytes()))
+                .collect(),
+            ordinal,
+            ..Default::default()
+        }
+    }
+
+    fn transaction_trace(calls: Vec<Call>) -> TransactionTrace {
+        TransactionTrace {
+            hash: vec![0xab; 32],
+            status: TransactionTraceStatus::Succeeded.into(),
+            calls,
+            ..Default::default()
+        }
+    }
+
+    /// Returns (token, component, delta) of each balance delta.
+    fn summarize(deltas: Vec<BalanceDelta>) -> Vec<(Vec<u8>, Vec<u8>, BigInt)> {
+        deltas
+            .into_iter()
+            .map(|d| {
+                (
+                    d.token,
+                    hex::decode(String::from_utf8(d.component_id).unwrap()).unwrap(),
+                    BigInt::from_signed_bytes_be(&d.delta),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_balance_deltas_rebasing_shares() {
+        let tx = transaction_trace(vec![Call {
+            logs: vec![
+                event_log(&TOKEN, TRANSFER_TOPIC, &[&USER, &COMPONENT], &[1000], 1),
+                event_log(&TOKEN, TRANSFER_SHARES_TOPIC, &[&USER, &COMPONENT], &[900], 2),
+            ],
+            ..Default::default()
+        }]);
+        let registry = TokenRegistry::default().with_token(&TOKEN, TokenBehaviour::RebasingShares);
+
+        let deltas =
+            extract_balance_deltas_with_registry(&tx, &registry, |_, addr| addr == COMPONENT);
+
+        assert_eq!(
+            summarize(deltas),
+            vec![(TOKEN.to_vec(), COMPONENT.to_vec(), BigInt::from(900))]
+        );
+    }
+
+    #[test]
+    fn test_extract_balance_deltas_erc4626_vault() {
+        let tx = transaction_trace(vec![Call {
+            logs: vec![
+                event_log(&TOKEN, TRANSFER_TOPIC, &[&USER, &VAULT], &[1000], 1),
+                event_log(&VAULT, TRANSFER_TOPIC, &[&[0u8; 20], &USER], &[500], 2),
+                event_log(&VAULT, VAULT_DEPOSIT_TOPIC, &[&USER, &USER], &[1000, 500], 3),
+            ],
+            ..Default::default()
+        }]);
+        let registry = TokenRegistry::default()
+            .with_token(&VAULT, TokenBehaviour::Erc4626Vault { asset: TOKEN.to_vec() });
+
+        let deltas = extract_balance_deltas_with_registry(&tx, &registry, |_, _| true);
+
+        assert_eq!(
+            summarize(deltas),
+            vec![
+                (TOKEN.to_vec(), USER.to_vec(), BigInt::from(-1000)),
+                (VAULT.to_vec(), vec![0u8; 20], BigInt::from(-500)),
+                (VAULT.to_vec(), USER.to_vec(), BigInt::from(500)),
+                (TOKEN.to_vec(), VAULT.to_vec(), BigInt::from(1000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_balance_deltas_custom_events() {
+        let tx = transaction_trace(vec![Call {
+            logs: vec![event_log(&TOKEN, "aa", &[&COMPONENT], &[42], 1)],
+            ..Default::default()
+        }]);
+        let registry = TokenRegistry::default().with_token(
+            &TOKEN,
+            TokenBehaviour::Custom(Box::new(|log: &Log| {
+                vec![(log.topics[1][12..].to_vec(), BigInt::from_unsigned_bytes_be(&log.data))]
+            })),
+        );
+
+        let deltas = extract_balance_deltas_with_registry(&tx, &registry, |_, _| true);
+
+        assert_eq!(summarize(deltas), vec![(TOKEN.to_vec(), COMPONENT.to_vec(), BigInt::from(42))]);
+    }
+
+    #[test]
+    fn test_extract_balance_deltas_without_registry() {
+        let tx = transaction_trace(vec![Call {
+            logs: vec![
+                event_log(&TOKEN, TRANSFER_TOPIC, &[&USER, &COMPONENT], &[1000], 1),
+                event_log(&TOKEN, TRANSFER_SHARES_TOPIC, &[&USER, &COMPONENT], &[900], 2),
+            ],
+            ..Default::default()
+        }]);
+
+        let deltas = extract_balance_deltas_from_tx(&tx, |_, addr| addr == COMPONENT);
+
+        assert_eq!(
+            summarize(deltas),
+            vec![(TOKEN.to_vec(), COMPONENT.to_vec(), BigInt::from(1000))]
+        );
+    }
+
+    fn native_balance_change(address: &[u8], old: u64, new: u64, ordinal: u64) -> EthBalanceChange {
+        let value = |v: u64| (v > 0).then(|| EthBigInt { bytes: BigInt::from(v).to_bytes_be().1 });
+        EthBalanceChange {
+            address: address.to_vec(),
+            old_value: value(old),
+            new_value: value(new),
+            ordinal,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_extract_native_balance_deltas() {
+        let native_token = [0u8; 20];
+        let tx = transaction_trace(vec![
+            Call {
+                balance_changes: vec![
+                    native_balance_change(&USER, 5000, 4000, 1),
+                    native_balance_change(&COMPONENT, 0, 1000, 2),
+                ],
+                ..Default::default()
+            },
+            Call {
+                state_reverted: true,
+                balance_changes: vec![native_balance_change(&COMPONENT, 1000, 0, 4)],
+                ..Default::default()
+            },
+            Call {
+                balance_changes: vec![
+                    native_balance_change(&COMPONENT, 1000, 1000, 5),
+                    native_balance_change(&COMPONENT, 1000, 300, 3),
+                ],
+                ..Default::default()
+            },
+        ]);
+
+        let deltas =
+            extract_native_balance_deltas_from_tx(&tx, &native_token, |_, addr| addr == COMPONENT);
+
+        assert_eq!(
+            deltas
+                .iter()
+                .map(|d| d.ord)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(
+            summarize(deltas),
+            vec![
+                (native_token.to_vec(), COMPONENT.to_vec(), BigInt::from(1000)),
+                (native_token.to_vec(), COMPONENT.to_vec(), BigInt::from(-700)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_balance_deltas_with_native_token() {
+        let native_token = [0xee; 20];
+        let tx = transaction_trace(vec![Call {
+            logs: vec![event_log(&TOKEN, TRANSFER_TOPIC, &[&USER, &COMPONENT], &[1000], 2)],
+            balance_changes: vec![native_balance_change(&COMPONENT, 0, 50, 1)],
+            ..Default::default()
+        }]);
+        let registry = TokenRegistry::default().with_native_token(&native_token);
+
+        let deltas =
+            extract_balance_deltas_with_registry(&tx, &registry, |_, addr| addr == COMPONENT);
+
+        assert_eq!(
+            summarize(deltas),
+            vec![
+                (native_token.to_vec(), COMPONENT.to_vec(), BigInt::from(50)),
+                (TOKEN.to_vec(), COMPONENT.to_vec(), BigInt::from(1000)),
+            ]
+        );
+    }
 }
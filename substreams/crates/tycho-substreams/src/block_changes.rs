@@ -0,0 +1,387 @@
+//! Block level aggregation of transaction changes.
+//!
+//! The final module of an integration usually collects changes from several sources (new
+//! components, aggregated balances, contract changes, ...) into one `TransactionChangesBuilder`
+//! per transaction, then assembles them into a `BlockChanges` sorted by transaction index. The
+//! `BlockChangesBuilder` does this bookkeeping and validates the result.
+//!
+//! ## Example
+//! ```ignore
+//! let registry = ComponentRegistry::new(components_store);
+//! let mut builder = BlockChangesBuilder::new(&block);
+//! for tx_components in grouped_components.tx_components.iter() {
+//!     let tx = tx_components.tx.as_ref().unwrap();
+//!     for component in tx_components.components.iter() {
+//!         builder.add_protocol_component(tx, component);
+//!     }
+//! }
+//! builder.add_aggregated_balances(&aggregate_balances_changes(balance_store, deltas));
+//! builder.extract_contract_changes(registry.contract_predicate());
+//! builder.mark_updated_components(|address| registry.component_for(address).map(|c| c.id));
+//! builder.with_storage_changes();
+//! Ok(builder.try_build()?)
+//! ```
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use substreams_ethereum::pb::eth::v2 as sf;
+
+use crate::{
+    balances::TxAggregatedBalances,
+    block_storage::get_block_storage_changes,
+    contract::extract_contract_changes_builder,
+    models::{
+        BalanceChange, BlockChanges, ComponentLifecycleError, EntityChanges, EntryPoint,
+        EntryPointError, EntryPointParams, InterimContractChange, ProtocolComponent, Transaction,
+        TransactionChangesBuilder,
+    },
+    schema::{AttributeDefinition, SchemaError},
+};
+
+type EntryPointLookup<'a> = Box<dyn Fn(&str) -> bool + 'a>;
+
+/// Builds `BlockChanges` from changes keyed by transaction.
+///
+/// Owns one `TransactionChangesBuilder` per transaction, created on first use. Transactions
+/// without any change are omitted from the result.
+pub struct BlockChangesBuilder<'a> {
+    block: &'a sf::Block,
+    transactions: HashMap<u64, TransactionChangesBuilder>,
+    attribute_schema: Vec<AttributeDefinition>,
+    known_entrypoints: Option<EntryPointLookup<'a>>,
+    storage_changes: bool,
+}
+
+impl<'a> BlockChangesBuilder<'a> {
+    pub fn new(block: &'a sf::Block) -> Self {
+        Self {
+            block,
+            transactions: HashMap::new(),
+            attribute_schema: Vec::new(),
+            known_entrypoints: None,
+            storage_changes: false,
+        }
+    }
+
+    /// Sets the attribute schema used to validate the entity changes of every transaction, see
+    /// `TransactionChangesBuilder::set_attribute_schema`.
+    pub fn set_attribute_schema(&mut self, schema: &[AttributeDefinition]) {
+        self.attribute_schema = schema.to_vec();
+        for builder in self.transactions.values_mut() {
+            builder.set_attribute_schema(schema);
+        }
+    }
+
+    /// Sets how entrypoints emitted in previous blocks are recognized, e.g. by looking up a store
+    /// of entrypoint ids.
+    ///
+    /// By default, entrypoint params may only reference entrypoints of this block.
+    pub fn set_known_entrypoints<F: Fn(&str) -> bool + 'a>(&mut self, is_known: F) {
+        self.known_entrypoints = Some(Box::new(is_known));
+    }
+
+    /// Includes the storage changes of the block, as input for the Dynamic Contract Indexer.
+    ///
+    /// ## Panics
+    /// `build` and `try_build` panic if the block is not an extended block model.
+    pub fn with_storage_changes(&mut self) {
+        self.storage_changes = true;
+    }
+
+    /// Returns the builder of a transaction, creating it if needed.
+    pub fn transaction(&mut self, tx: &Transaction) -> &mut TransactionChangesBuilder {
+        let schema = &self.attribute_schema;
+        self.transactions
+            .entry(tx.index)
+            .or_insert_with(|| {
+                let mut builder = TransactionChangesBuilder::new(tx);
+                builder.set_attribute_schema(schema);
+                builder
+            })
+    }
+
+    /// Adds a new protocol component, see `TransactionChangesBuilder::add_protocol_component`.
+    pub fn add_protocol_component(&mut self, tx: &Transaction, component: &ProtocolComponent) {
+        self.transaction(tx)
+            .add_protocol_component(component);
+    }
+
+    /// Adds a balance change, see `TransactionChangesBuilder::add_balance_change`.
+    pub fn add_balance_change(&mut self, tx: &Transaction, change: &BalanceChange) {
+        self.transaction(tx)
+            .add_balance_change(change);
+    }
+
+    /// Adds the absolute balances returned by `aggregate_balances_changes`.
+    pub fn add_aggregated_balances(&mut self, balances: &TxAggregatedBalances) {
+        for (tx, changes) in balances.values() {
+            let builder = self.transaction(tx);
+            for change in changes
+                .values()
+                .flat_map(HashMap::values)
+            {
+                builder.add_balance_change(change);
+            }
+        }
+    }
+
+    /// Adds an entity change, see `TransactionChangesBuilder::add_entity_change`.
+    pub fn add_entity_change(&mut self, tx: &Transaction, change: &EntityChanges) {
+        self.transaction(tx)
+            .add_entity_change(change);
+    }
+
+    /// Adds a contract change, see `TransactionChangesBuilder::add_contract_changes`.
+    pub fn add_contract_changes(&mut self, tx: &Transaction, change: &InterimContractChange) {
+        self.transaction(tx)
+            .add_contract_changes(change);
+    }
+
+    /// Adds an entrypoint, see `TransactionChangesBuilder::add_entrypoint`.
+    pub fn add_entrypoint(&mut self, tx: &Transaction, entrypoint: &EntryPoint) {
+        self.transaction(tx)
+            .add_entrypoint(entrypoint);
+    }
+
+    /// Adds entrypoint params, see `TransactionChangesBuilder::add_entrypoint_params`.
+    pub fn add_entrypoint_params(&mut self, tx: &Transaction, params: &EntryPointParams) {
+        self.transaction(tx)
+            .add_entrypoint_params(params);
+    }
+
+    /// Extracts the contract changes of the block, see `extract_contract_changes_builder`.
+    ///
+    /// ## Panics
+    /// Panics if the block is not an extended block model.
+    pub fn extract_contract_changes<F: Fn(&[u8]) -> bool>(&mut self, inclusion_predicate: F) {
+        extract_contract_changes_builder(self.block, inclusion_predicate, &mut self.transactions);
+        for builder in self.transactions.values_mut() {
+            builder.set_attribute_schema(&self.attribute_schema);
+        }
+    }
+
+    /// Marks the components owning a changed contract as updated.
+    ///
+    /// ## Arguments
+    /// * `component_of` - Returns the id of the component a contract belongs to, if any.
+    pub fn mark_updated_components<F: Fn(&[u8]) -> Option<String>>(&mut self, component_of: F) {
+        for builder in self.transactions.values_mut() {
+            let component_ids = builder
+                .changed_contracts()
+                .filter_map(&component_of)
+                .collect::<HashSet<_>>();
+            for component_id in component_ids {
+                builder.mark_component_as_updated(&component_id);
+            }
+        }
+    }
+
+    /// Builds the `BlockChanges`, with transactions sorted by index.
+    ///
+    /// ## Panics
+    /// Panics if the changes are invalid, see `try_build`.
+    pub fn build(self) -> BlockChanges {
+        self.try_build()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Builds the `BlockChanges`, with transactions sorted by index, after validating them.
+    ///
+    /// ## Errors
+    /// - `BlockChangesError::Attributes` if entity changes violate their schema.
+    /// - `BlockChangesError::EntryPoint` if entrypoint params reference an entrypoint that is
+    ///   neither part of the block nor known, or if an entrypoint lacks a component.
+    /// - `BlockChangesError::Lifecycle` if a component is updated after its deletion.
+    ///
+    /// ## Panics
+    /// Panics if storage changes are included and the block is not an extended block model.
+    pub fn try_build(self) -> Result<BlockChanges, BlockChangesError> {
+        let builders = self
+            .transactions
+            .into_iter()
+            .sorted_unstable_by_key(|(index, _)| *index)
+            .collect::<Vec<_>>();
+
+        let block_entrypoints: HashSet<String> = builders
+            .iter()
+            .flat_map(|(_, builder)| builder.entrypoint_ids())
+            .collect();
+        let is_known = |id: &str| {
+            block_entrypoints.contains(id) ||
+                self.known_entrypoints
+                    .as_ref()
+                    .is_some_and(|is_known| is_known(id))
+        };
+
+        let mut changes = Vec::with_capacity(builders.len());
+        for (tx_index, builder) in builders {
+            let errors = builder.validate_attributes();
+            if !errors.is_empty() {
+                return Err(BlockChangesError::Attributes { tx_index, errors });
+            }
+            builder
+                .validate_entrypoints(is_known)
+                .map_err(|error| BlockChangesError::EntryPoint { tx_index, error })?;
+            builder
+                .validate_lifecycle()
+                .map_err(BlockChangesError::Lifecycle)?;
+            changes.extend(builder.into_transaction_changes());
+        }
+
+        let block_changes = BlockChanges {
+            block: Some(self.block.into()),
+            changes,
+            storage_changes: if self.storage_changes {
+                get_block_storage_changes(self.block)
+            } else {
+                Vec::new()
+            },
+        };
+        block_changes
+            .validate_component_lifecycle()
+            .map_err(BlockChangesError::Lifecycle)?;
+        Ok(block_changes)
+    }
+}
+
+/// Invalid changes found while building `BlockChanges`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockChangesError {
+    /// Entity changes of a transaction violate their attribute schema.
+    Attributes { tx_index: u64, errors: Vec<SchemaError> },
+    /// Entrypoints of a transaction are inconsistent.
+    EntryPoint { tx_index: u64, error: EntryPointError },
+    /// A component is updated after its deletion.
+    Lifecycle(ComponentLifecycleError),
+}
+
+impl std::fmt::Display for BlockChangesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Attributes { tx_index, errors } => write!(
+                f,
+                "Attribute schema violated in transaction {tx_index}: {}",
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .join("; ")
+            ),
+            Self::EntryPoint { tx_index, error } => {
+                write!(f, "Invalid entrypoints in transaction {tx_index}: {error}")
+            }
+            Self::Lifecycle(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for BlockChangesError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entrypoint::create_entrypoint,
+        models::{entry_point_params::TraceData, RpcTraceData},
+    };
+
+    fn block() -> sf::Block {
+        sf::Block {
+            number: 1,
+            header: Some(sf::BlockHeader {
+                timestamp: Some(prost_types::Timestamp { seconds: 12, nanos: 0 }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn tx(index: u64) -> Transaction {
+        Transaction { hash: vec![index as u8; 32], index, ..Default::default() }
+    }
+
+    fn balance(component_id: &str, value: u8) -> BalanceChange {
+        BalanceChange {
+            token: vec![1; 20],
+            balance: vec![value],
+            component_id: component_id.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_build_sorted_by_tx_index() {
+        let block = block();
+        let mut builder = BlockChangesBuilder::new(&block);
+        let (entrypoint, params) = create_entrypoint(
+            vec![2; 20],
+            "getRate()".to_string(),
+            "pool".to_string(),
+            TraceData::Rpc(RpcTraceData { caller: None, calldata: vec![] }),
+        );
+        builder.add_protocol_component(&tx(3), &ProtocolComponent::new("pool"));
+        builder.add_entrypoint(&tx(3), &entrypoint);
+        // Params may reference an entrypoint of another transaction of the block
+        builder.add_entrypoint_params(&tx(5), &params);
+        let mut balances = HashMap::new();
+        balances.insert(
+            tx(1).hash,
+            (
+                tx(1),
+                HashMap::from([(
+                    b"pool".to_vec(),
+                    HashMap::from([(vec![1; 20], balance("pool", 9))]),
+                )]),
+            ),
+        );
+        builder.add_aggregated_balances(&balances);
+        // Touching a transaction without changes doesn't emit it
+        builder.transaction(&tx(2));
+
+        let block_changes = builder.try_build().unwrap();
+
+        assert_eq!(
+            block_changes
+                .block
+                .map(|b| (b.number, b.ts)),
+            Some((1, 12))
+        );
+        assert_eq!(
+            block_changes
+                .changes
+                .iter()
+                .map(|changes| changes.tx.as_ref().unwrap().index)
+                .collect::<Vec<_>>(),
+            vec![1, 3, 5]
+        );
+        assert_eq!(block_changes.changes[0].balance_changes, vec![balance("pool", 9)]);
+        assert!(block_changes.storage_changes.is_empty());
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        let block = block();
+        let mut builder = BlockChangesBuilder::new(&block);
+        let (_, params) = create_entrypoint(
+            vec![2; 20],
+            "getRate()".to_string(),
+            "pool".to_string(),
+            TraceData::Rpc(RpcTraceData { caller: None, calldata: vec![] }),
+        );
+        builder.add_entrypoint_params(&tx(1), &params);
+        let known_id = params.entrypoint_id.clone();
+        builder.set_known_entrypoints(move |id| id == known_id);
+        builder
+            .transaction(&tx(1))
+            .delete_component(&ProtocolComponent::new("pool"));
+        builder.add_balance_change(&tx(2), &balance("pool", 9));
+
+        let res = builder.try_build();
+
+        assert_eq!(
+            res,
+            Err(BlockChangesError::Lifecycle(ComponentLifecycleError::UpdateAfterDeletion {
+                component_id: "pool".to_string(),
+                update: format!("balance of token 0x{}", "01".repeat(20)),
+            }))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Registry of the protocol components tracked by an integration.
+//!
+//! Most integrations store their components once created, to later decide whether a log, a
+//! transfer or a contract change belongs to a tracked component. This module provides that store
+//! layout and lookups:
+//!
+//! 1. **Storing**: `store_components` saves each component under its id and under each of its
+//!    contract addresses, in a `StoreSetProto<ProtocolComponent>`.
+//! 2. **Lookups**: a `ComponentRegistry` wraps the matching `StoreGetProto<ProtocolComponent>` and
+//!    provides the predicates expected by `extract_balance_deltas_from_tx` and
+//!    `extract_contract_changes`.
+//!
+//! ## Example
+//! ```ignore
+//! #[substreams::handlers::store]
+//! pub fn store_components(changes: BlockChanges, store: StoreSetProto<ProtocolComponent>) {
+//!     component_registry::store_components(
+//!         changes.changes.iter().flat_map(|tx| tx.component_changes.iter()),
+//!         store,
+//!     );
+//! }
+//!
+//! let registry = ComponentRegistry::new(components_store);
+//! let deltas = extract_balance_deltas_from_tx(&tx, registry.balance_predicate());
+//! extract_contract_changes(&block, registry.contract_predicate(), &mut transaction_changes);
+//! ```
+use substreams::store::{StoreGet, StoreSet};
+
+use crate::{models::ProtocolComponent, store_key, store_key::StoreKey};
+
+store_key! {
+    /// Store key of a component by its id, `Component:id`.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct ComponentKey("Component") {
+        pub id: String,
+    }
+}
+
+store_key! {
+    /// Store key of a component by one of its contracts, `Contract:address_hex`.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct ContractKey("Contract") {
+        pub address: Vec<u8>,
+    }
+}
+
+/// Stores components by id and by each of their contract addresses.
+///
+/// Uses ordinal 0, as components are expected to be created once.
+///
+/// ## Panics
+/// Panics if a component id is empty or contains `:`.
+pub fn store_components<'a>(
+    components: impl IntoIterator<Item = &'a ProtocolComponent>,
+    store: impl StoreSet<ProtocolComponent>,
+) {
+    for component in components {
+        register_component(&store, 0, component);
+    }
+}
+
+/// Stores a component by id and by each of its contract addresses.
+///
+/// If several components share a contract, the contract resolves to the last registered one.
+///
+/// ## Panics
+/// Panics if the component id is empty or contains `:`.
+pub fn register_component(
+    store: &impl StoreSet<ProtocolComponent>,
+    ordinal: u64,
+    component: &ProtocolComponent,
+) {
+    store.set(ordinal, ComponentKey { id: component.id.clone() }.encode(), component);
+    for contract in component.contracts.iter() {
+        store.set(ordinal, ContractKey { address: contract.clone() }.encode(), component);
+    }
+}
+
+/// Lookups of the components stored by `store_components`.
+pub struct ComponentRegistry<S> {
+    store: S,
+}
+
+impl<S: StoreGet<ProtocolComponent>> ComponentRegistry<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    /// Returns the component with the given id.
+    pub fn component(&self, id: &str) -> Option<ProtocolComponent> {
+        let key = ComponentKey { id: id.to_string() }
+            .try_encode()
+            .ok()?;
+        self.store.get_last(key)
+    }
+
+    /// Returns whether the address is a contract of a tracked component.
+    pub fn is_tracked(&self, address: &[u8]) -> bool {
+        self.store
+            .has_last(ContractKey { address: address.to_vec() }.encode())
+    }
+
+    /// Returns the component the address is a contract of.
+    pub fn component_for(&self, address: &[u8]) -> Option<ProtocolComponent> {
+        self.store
+            .get_last(ContractKey { address: address.to_vec() }.encode())
+    }
+
+    /// Returns the tokens of the component with the given id.
+    pub fn tokens_of(&self, id: &str) -> Option<Vec<Vec<u8>>> {
+        self.component(id)
+            .map(|component| component.tokens)
+    }
+
+    /// Returns a predicate for `extract_contract_changes`, selecting the contracts of tracked
+    /// components.
+    pub fn contract_predicate(&self) -> impl Fn(&[u8]) -> bool + '_ {
+        |address| self.is_tracked(address)
+    }
+
+    /// Returns a predicate for `extract_balance_deltas_from_tx`, selecting transfers of a token
+    /// to or from a contract of a tracked component holding that token.
+    pub fn balance_predicate(&self) -> impl Fn(&[u8], &[u8]) -> bool + '_ {
+        |token, address| {
+            self.component_for(address)
+                .is_some_and(|component| {
+                    component
+                        .tokens
+                        .iter()
+                        .any(|t| t == token)
+                })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock_store::MockStore;
+    use substreams::store::StoreNew;
+
+    fn component(id: &str, contracts: &[u8], tokens: &[u8]) -> ProtocolComponent {
+        ProtocolComponent::new(id)
+            .with_contracts(
+                &contracts
+                    .iter()
+                    .map(|c| vec![*c; 20])
+                    .collect::<Vec<_>>(),
+            )
+            .with_tokens(
+                &tokens
+                    .iter()
+                    .map(|t| vec![*t; 20])
+                    .collect::<Vec<_>>(),
+            )
+    }
+
+    #[test]
+    fn test_component_registry() {
+        let store = <MockStore<ProtocolComponent> as StoreNew>::new();
+        let pool = component("0x01", &[1, 2], &[10, 11]);
+        store_components([&pool, &component("0x03", &[3], &[10])], store.clone());
+
+        let registry = ComponentRegistry::new(store);
+
+        assert_eq!(registry.component("0x01"), Some(pool.clone()));
+        assert_eq!(registry.component("0x:01"), None);
+        assert!(registry.is_tracked(&[2; 20]));
+        assert!(!registry.is_tracked(&[4; 20]));
+        assert_eq!(registry.component_for(&[2; 20]), Some(pool));
+        assert_eq!(registry.tokens_of("0x03"), Some(vec![vec![10; 20]]));
+        assert_eq!(registry.tokens_of("0x04"), None);
+
+        let contracts = registry.contract_predicate();
+        assert!(contracts(&[3; 20]));
+        let balances = registry.balance_predicate();
+        assert!(balances(&[11; 20], &[1; 20]));
+        assert!(!balances(&[11; 20], &[3; 20]));
+        assert!(!balances(&[10; 20], &[4; 20]));
+    }
+}
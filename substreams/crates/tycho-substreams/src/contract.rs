@@ -45,6 +45,12 @@ use substreams_ethereum::pb::eth::{
 /// Contracts created within the block are tracked to differentiate between new and existing
 /// contracts. The aggregation process respects transaction boundaries, ensuring that changes are
 /// mapped accurately to their originating transactions.
+///
+/// Changes of calls whose state was reverted, including calls within a reverted subtree, are
+/// ignored. Contracts removed by `SELFDESTRUCT` are emitted with a `Deletion` change type and
+/// without any other changes. Contracts re-created at the same address in a later transaction
+/// (e.g. `CREATE2` redeploys) are emitted as a `Creation`, containing only the slots written since
+/// the re-creation. See `is_account_destroyed` for when a `SELFDESTRUCT` removes a contract.
 pub fn extract_contract_changes<F: Fn(&[u8]) -> bool>(
     block: &eth::v2::Block,
     inclusion_predicate: F,
@@ -92,15 +98,28 @@ fn extract_contract_changes_generic<
         panic!("Only extended blocks are supported");
     }
     let mut changed_contracts: HashMap<Vec<u8>, InterimContractChange> = HashMap::new();
+    let eip6780_active = is_eip6780_active(block);
 
     block
         .transactions()
         .for_each(|block_tx| {
-            // Collect all accounts created in this tx
+            // Collect all accounts created in this tx, creations of reverted calls never happened
             let created_accounts: HashSet<_> = block_tx
                 .calls
                 .iter()
-                .filter(|call| call.call_type() == CallType::Create)
+                .filter(|call| call.call_type() == CallType::Create && !call.state_reverted)
+                .map(|call| call.address.clone())
+                .collect();
+
+            // Collect all tracked accounts removed in this tx
+            let destroyed_accounts: HashSet<_> = block_tx
+                .calls
+                .iter()
+                .filter(|call| {
+                    !call.state_reverted &&
+                        inclusion_predicate(&call.address) &&
+                        is_account_destroyed(call, &created_accounts, eip6780_active)
+                })
                 .map(|call| call.address.clone())
                 .collect();
 
@@ -178,12 +197,272 @@ fn extract_contract_changes_generic<
                     contract_change.set_code(&code_change.new_code);
                 });
 
+            // Destroyed accounts lose all their state at the end of the transaction. Accounts
+            // created and destroyed within the transaction never existed outside of it.
+            for address in destroyed_accounts.iter() {
+                if created_accounts.contains(address) {
+                    changed_contracts.remove(address);
+                } else {
+                    changed_contracts
+                        .insert(address.clone(), InterimContractChange::deletion(address));
+                }
+            }
+
             if !storage_changes.is_empty() ||
                 !balance_changes.is_empty() ||
-                !code_changes.is_empty()
+                !code_changes.is_empty() ||
+                !destroyed_accounts.is_empty()
             {
                 store_changes(block_tx, &changed_contracts)
             }
             changed_contracts.clear()
         });
 }
+
+/// Checks whether a call's `SELFDESTRUCT` removed its account.
+///
+/// Since EIP-6780 (Cancun), `SELFDESTRUCT` only removes accounts created within the same
+/// transaction, otherwise it only transfers the account's native balance. Before, it always
+/// removed the account.
+///
+/// ## Arguments
+/// * `call` - The call to check.
+/// * `created_accounts` - The accounts created within the call's transaction.
+/// * `eip6780_active` - Whether EIP-6780 is active for the call's block, see `is_eip6780_active`.
+pub fn is_account_destroyed(
+    call: &eth::v2::Call,
+    created_accounts: &HashSet<Vec<u8>>,
+    eip6780_active: bool,
+) -> bool {
+    call.suicide && (!eip6780_active || created_accounts.contains(&call.address))
+}
+
+/// Checks whether EIP-6780 (`SELFDESTRUCT` only in same transaction) is active for a block.
+///
+/// EIP-6780 shipped with Cancun, together with EIP-4844 which introduced the blob gas header
+/// fields. Their presence is used to detect the fork, which keeps this independent of the chain.
+pub fn is_eip6780_active(block: &eth::v2::Block) -> bool {
+    block
+        .header
+        .as_ref()
+        .is_some_and(|header| header.blob_gas_used.is_some() || header.excess_blob_gas.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ChangeType, ContractChange, ContractSlot};
+    use eth::v2::{
+        BalanceChange, BigInt, Block, BlockHeader, Call, StorageChange, TransactionTraceStatus,
+    };
+    use itertools::Itertools;
+
+    const CONTRACT: [u8; 20] = [0xc0; 20];
+    const OTHER: [u8; 20] = [0xee; 20];
+    const USER: [u8; 20] = [0x01; 20];
+
+    fn block(transactions: Vec<TransactionTrace>, cancun: bool) -> Block {
+        Block {
+            number: 1,
+            header: Some(BlockHeader {
+                blob_gas_used: cancun.then_some(0),
+                excess_blob_gas: cancun.then_some(0),
+                ..Default::default()
+            }),
+            detail_level: DetailLevel::DetaillevelExtended.into(),
+            transaction_traces: transactions,
+            ..Default::default()
+        }
+    }
+
+    fn transaction(index: u32, calls: Vec<Call>) -> TransactionTrace {
+        TransactionTrace {
+            index,
+            hash: vec![index as u8; 32],
+            from: USER.to_vec(),
+            status: TransactionTraceStatus::Succeeded.into(),
+            calls,
+            ..Default::default()
+        }
+    }
+
+    fn call(address: &[u8], call_type: CallType, storage_changes: Vec<StorageChange>) -> Call {
+        Call {
+            caller: USER.to_vec(),
+            address: address.to_vec(),
+            call_type: call_type.into(),
+            storage_changes,
+            ..Default::default()
+        }
+    }
+
+    fn storage_change(address: &[u8], key: u8, old: u8, new: u8, ordinal: u64) -> StorageChange {
+        StorageChange {
+            address: address.to_vec(),
+            key: vec![key; 32],
+            old_value: vec![old; 32],
+            new_value: vec![new; 32],
+            ordinal,
+        }
+    }
+
+    fn extract(block: &Block) -> HashMap<u64, TransactionChanges> {
+        let mut changes = HashMap::new();
+        extract_contract_changes(block, |addr| addr == CONTRACT || addr == OTHER, &mut changes);
+        changes
+    }
+
+    fn contract_changes(
+        changes: &HashMap<u64, TransactionChanges>,
+        tx: u64,
+    ) -> Vec<ContractChange> {
+        changes
+            .get(&tx)
+            .map(|tx_changes| {
+                tx_changes
+                    .contract_changes
+                    .iter()
+                    .cloned()
+                    .sorted_by_key(|c| c.address.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_extract_contract_changes_ignores_reverted_subtree() {
+        let mut reverted_create =
+            call(&OTHER, CallType::Create, vec![storage_change(&OTHER, 1, 0, 1, 3)]);
+        reverted_create.state_reverted = true;
+        let mut reverted_call =
+            call(&CONTRACT, CallType::Call, vec![storage_change(&CONTRACT, 2, 0, 2, 4)]);
+        reverted_call.state_reverted = true;
+        let block = block(
+            vec![transaction(
+                0,
+                vec![
+                    call(&CONTRACT, CallType::Call, vec![storage_change(&CONTRACT, 1, 0, 1, 2)]),
+                    reverted_create,
+                    reverted_call,
+                ],
+            )],
+            true,
+        );
+
+        let changes = contract_changes(&extract(&block), 0);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].address, CONTRACT.to_vec());
+        assert_eq!(changes[0].change, ChangeType::Update as i32);
+        assert_eq!(
+            changes[0].slots,
+            vec![ContractSlot {
+                slot: vec![1; 32],
+                value: vec![1; 32],
+                previous_value: vec![0; 32]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_extract_contract_changes_selfdestruct_pre_cancun() {
+        let mut destruct =
+            call(&CONTRACT, CallType::Call, vec![storage_change(&CONTRACT, 1, 0, 1, 2)]);
+        destruct.suicide = true;
+        destruct.balance_changes = vec![BalanceChange {
+            address: CONTRACT.to_vec(),
+            old_value: Some(BigInt { bytes: vec![10] }),
+            new_value: Some(BigInt { bytes: vec![] }),
+            ordinal: 3,
+            ..Default::default()
+        }];
+        let block = block(vec![transaction(0, vec![destruct])], false);
+
+        let changes = contract_changes(&extract(&block), 0);
+
+        assert_eq!(
+            changes,
+            vec![ContractChange {
+                address: CONTRACT.to_vec(),
+                change: ChangeType::Deletion as i32,
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_extract_contract_changes_selfdestruct_post_cancun_keeps_account() {
+        let mut destruct =
+            call(&CONTRACT, CallType::Call, vec![storage_change(&CONTRACT, 1, 0, 1, 2)]);
+        destruct.suicide = true;
+        let block = block(vec![transaction(0, vec![destruct])], true);
+
+        let changes = contract_changes(&extract(&block), 0);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].change, ChangeType::Update as i32);
+        assert_eq!(changes[0].slots.len(), 1);
+    }
+
+    #[rstest::rstest]
+    #[case::pre_cancun(false)]
+    #[case::post_cancun(true)]
+    fn test_extract_contract_changes_create_and_selfdestruct_same_tx(#[case] cancun: bool) {
+        let create = call(&CONTRACT, CallType::Create, vec![storage_change(&CONTRACT, 1, 0, 1, 2)]);
+        let mut destruct = call(&CONTRACT, CallType::Call, vec![]);
+        destruct.suicide = true;
+        let block = block(
+            vec![transaction(
+                0,
+                vec![
+                    create,
+                    destruct,
+                    call(&OTHER, CallType::Call, vec![storage_change(&OTHER, 1, 0, 1, 4)]),
+                ],
+            )],
+            cancun,
+        );
+
+        let changes = contract_changes(&extract(&block), 0);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].address, OTHER.to_vec());
+    }
+
+    #[test]
+    fn test_extract_contract_changes_create2_redeploy() {
+        let mut destruct =
+            call(&CONTRACT, CallType::Call, vec![storage_change(&CONTRACT, 1, 0, 1, 2)]);
+        destruct.suicide = true;
+        let mut redeploy =
+            call(&CONTRACT, CallType::Create, vec![storage_change(&CONTRACT, 2, 0, 2, 12)]);
+        redeploy.code_changes = vec![eth::v2::CodeChange {
+            address: CONTRACT.to_vec(),
+            new_code: vec![0x60, 0x80],
+            ordinal: 11,
+            ..Default::default()
+        }];
+        let block =
+            block(vec![transaction(0, vec![destruct]), transaction(1, vec![redeploy])], false);
+
+        let mut builders = HashMap::new();
+        extract_contract_changes_builder(&block, |addr| addr == CONTRACT, &mut builders);
+        let changes: HashMap<u64, TransactionChanges> = builders
+            .into_iter()
+            .map(|(idx, builder)| (idx, builder.build().expect("changes")))
+            .collect();
+
+        assert_eq!(contract_changes(&changes, 0)[0].change, ChangeType::Deletion as i32);
+        let redeployed = &contract_changes(&changes, 1)[0];
+        assert_eq!(redeployed.change, ChangeType::Creation as i32);
+        assert_eq!(redeployed.code, vec![0x60, 0x80]);
+        assert_eq!(
+            redeployed.slots,
+            vec![ContractSlot {
+                slot: vec![2; 32],
+                value: vec![2; 32],
+                previous_value: vec![0; 32]
+            }]
+        );
+    }
+}
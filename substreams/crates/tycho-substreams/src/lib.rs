@@ -1,15 +1,21 @@
 pub mod abi;
 pub mod attributes;
 pub mod balances;
+pub mod block_changes;
 pub mod block_storage;
+pub mod component_registry;
 pub mod contract;
 pub mod entrypoint;
 pub mod models;
 pub mod pb;
+pub mod proxy;
+pub mod schema;
+pub mod storage;
+pub mod store_key;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "testing"))]
 pub mod testing;
 
 pub mod prelude {
-    pub use super::models::*;
+    pub use super::{block_changes::BlockChangesBuilder, models::*};
 }
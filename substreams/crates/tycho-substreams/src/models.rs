@@ -1,6 +1,14 @@
+use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
+use substreams::scalar::BigInt;
 use substreams_ethereum::pb::eth::v2::{self as sf, StorageChange};
 
+use crate::{
+    attributes::AttributeCodec,
+    proxy::{resolve_implementations, ProxyImplementation},
+    schema::{self, AttributeDefinition, SchemaError},
+};
+
 // re-export the protobuf types here.
 pub use crate::pb::tycho::evm::v1::*;
 
@@ -11,18 +19,36 @@ impl TransactionChanges {
     }
 }
 
+/// Status attributes of a component, see `TransactionChangesBuilder::deprecate_component` and
+/// `TransactionChangesBuilder::change_component_pause_state`.
+const STATUS_ATTRIBUTES: [&str; 2] = ["deprecated", "paused"];
+
+/// Status of a component before the current transaction, e.g. as tracked in a store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ComponentStatus {
+    /// Whether the component has the `deprecated` attribute.
+    pub deprecated: bool,
+    /// Whether the component has the `paused` attribute.
+    pub paused: bool,
+}
+
 /// Builds `TransactionChanges` struct
 ///
-/// Ensures uniqueness for contract addresses and component ids.
+/// Ensures uniqueness for contract addresses and component ids, except for contracts deleted and
+/// re-created within the transaction, see `add_contract_changes`.
 #[derive(Default)]
 pub struct TransactionChangesBuilder {
     tx: Option<Transaction>,
     contract_changes: HashMap<Vec<u8>, InterimContractChange>,
+    recreated_contracts: HashSet<Vec<u8>>,
     entity_changes: HashMap<String, InterimEntityChanges>,
     component_changes: HashMap<String, ProtocolComponent>,
     balance_changes: HashMap<(Vec<u8>, Vec<u8>), BalanceChange>,
     entrypoints: HashSet<EntryPoint>,
     entrypoint_params: HashSet<EntryPointParams>,
+    attribute_schema: Vec<AttributeDefinition>,
+    deleted_components: HashSet<String>,
+    lifecycle_errors: Vec<ComponentLifecycleError>,
 }
 
 impl TransactionChangesBuilder {
@@ -31,10 +57,54 @@ impl TransactionChangesBuilder {
         Self { tx: Some(tx.clone()), ..Default::default() }
     }
 
+    /// Sets the attribute schema used to validate entity changes.
+    ///
+    /// Components added to this builder are validated against the schema of their
+    /// `protocol_type` instead, if it declares one. See `validate_attributes`.
+    pub fn set_attribute_schema(&mut self, schema: &[AttributeDefinition]) {
+        self.attribute_schema = schema.to_vec();
+    }
+
     /// Register a new contract change.
     ///
-    /// Will prioritize the new change over any already present one.
+    /// Will prioritize the new change over any already present one. A deletion replaces any
+    /// present change, or cancels it out if the contract was created within this builder. A
+    /// creation following a deletion is emitted after that deletion, so that all slots of the
+    /// previous contract are dropped.
     pub fn add_contract_changes(&mut self, change: &InterimContractChange) {
+        let previous_change = self
+            .contract_changes
+            .get(&change.address)
+            .map(|c| c.change);
+        match (previous_change, change.change) {
+            (Some(ChangeType::Creation), ChangeType::Deletion) => {
+                if self
+                    .recreated_contracts
+                    .remove(&change.address)
+                {
+                    self.contract_changes
+                        .insert(change.address.clone(), change.clone());
+                } else {
+                    self.contract_changes
+                        .remove(&change.address);
+                }
+                return;
+            }
+            (Some(ChangeType::Deletion), ChangeType::Creation) => {
+                self.recreated_contracts
+                    .insert(change.address.clone());
+                self.contract_changes
+                    .insert(change.address.clone(), change.clone());
+                return;
+            }
+            (_, ChangeType::Deletion) => {
+                self.contract_changes
+                    .insert(change.address.clone(), change.clone());
+                return;
+            }
+            _ => {}
+        }
+
         self.contract_changes
             .entry(change.address.clone())
             .and_modify(|c| {
@@ -81,17 +151,7 @@ impl TransactionChangesBuilder {
             value: vec![1u8],
             change: ChangeType::Update.into(),
         };
-        if let Some(entry) = self
-            .entity_changes
-            .get_mut(component_id)
-        {
-            entry.set_attribute(&attr);
-        } else {
-            let mut change = InterimEntityChanges::new(component_id);
-            change.set_attribute(&attr);
-            self.entity_changes
-                .insert(component_id.to_string(), change);
-        }
+        self.set_component_attribute(component_id, &attr);
     }
 
     /// Marks a protocol component as paused or unpaused.
@@ -114,24 +174,155 @@ impl TransactionChangesBuilder {
                 change: ChangeType::Deletion.into(),
             },
         };
+        self.set_component_attribute(component_id, &attribute);
+    }
+
+    /// Marks a protocol component as deprecated.
+    ///
+    /// Deprecated components still exist, e.g. to let liquidity providers withdraw, but are not
+    /// expected to be used anymore. Sets the `deprecated` attribute, see `reactivate_component`
+    /// to revert it. Noop if the component is already deprecated.
+    ///
+    /// ## Parameters
+    /// - `component_id`: The id of the component.
+    /// - `status`: The status of the component before this transaction, to update rather than
+    ///   create the attribute if it already exists.
+    pub fn deprecate_component(&mut self, component_id: &str, status: ComponentStatus) {
+        if self.check_not_deleted(component_id, "deprecation") {
+            self.set_status_attribute(component_id, "deprecated", status.deprecated, true);
+        }
+    }
+
+    /// Reactivates a deprecated or paused protocol component.
+    ///
+    /// Deletes the `deprecated` and `paused` attributes, if they are set. Deleted components
+    /// can't be reactivated.
+    ///
+    /// ## Parameters
+    /// - `component_id`: The id of the component.
+    /// - `status`: The status of the component before this transaction, to only delete the
+    ///   attributes that exist.
+    pub fn reactivate_component(&mut self, component_id: &str, status: ComponentStatus) {
+        if self.check_not_deleted(component_id, "reactivation") {
+            self.set_status_attribute(component_id, "deprecated", status.deprecated, false);
+            self.set_status_attribute(component_id, "paused", status.paused, false);
+        }
+    }
+
+    /// Sets or deletes a status attribute, unless it is already in that state.
+    ///
+    /// `existed` tells whether the attribute was set before this transaction, changes of this
+    /// builder take precedence over it.
+    fn set_status_attribute(&mut self, component_id: &str, name: &str, existed: bool, set: bool) {
+        let is_set = self
+            .entity_changes
+            .get(component_id)
+            .and_then(|changes| changes.attributes.get(name))
+            .map_or(existed, |attr| attr.change != i32::from(ChangeType::Deletion));
+        if is_set == set {
+            return;
+        }
+        let change = match (set, existed) {
+            (false, _) => ChangeType::Deletion,
+            (true, true) => ChangeType::Update,
+            (true, false) => ChangeType::Creation,
+        };
+        let attribute =
+            Attribute { name: name.to_string(), value: vec![u8::from(set)], change: change.into() };
+        self.set_component_attribute(component_id, &attribute);
+    }
+
+    /// Deletes a protocol component.
+    ///
+    /// Emits the component with a `Deletion` change type and sets the balances of its tokens to
+    /// zero. Pending attribute and balance changes of the component are dropped. If the component
+    /// was added to this builder, both cancel out and nothing is emitted for it.
+    ///
+    /// Deleted components must not be updated afterwards: later changes to the component are
+    /// ignored and reported by `validate_lifecycle` and `try_build`.
+    ///
+    /// ## Parameters
+    /// - `component`: The component to delete, its tokens are needed to reset its balances.
+    pub fn delete_component(&mut self, component: &ProtocolComponent) {
+        if !self
+            .deleted_components
+            .insert(component.id.clone())
+        {
+            return;
+        }
+        self.entity_changes
+            .remove(&component.id);
+        self.balance_changes
+            .retain(|(component_id, _), _| component_id != component.id.as_bytes());
+        if let Some(previous) = self
+            .component_changes
+            .remove(&component.id)
+        {
+            if previous.change == i32::from(ChangeType::Creation) {
+                return;
+            }
+        }
+
+        let mut deletion = component.clone();
+        deletion.change = ChangeType::Deletion.into();
+        for attribute in deletion.static_att.iter_mut() {
+            attribute.change = ChangeType::Deletion.into();
+        }
+        self.component_changes
+            .insert(component.id.clone(), deletion);
+        for token in component.tokens.iter() {
+            self.balance_changes.insert(
+                (component.id.as_bytes().to_vec(), token.clone()),
+                BalanceChange {
+                    token: token.clone(),
+                    balance: BigInt::zero().to_signed_bytes_be(),
+                    component_id: component.id.as_bytes().to_vec(),
+                },
+            );
+        }
+    }
 
+    /// Sets an attribute of a component, unless the component was deleted.
+    fn set_component_attribute(&mut self, component_id: &str, attribute: &Attribute) {
+        if !self.check_not_deleted(component_id, &format!("attribute {}", attribute.name)) {
+            return;
+        }
         if let Some(entry) = self
             .entity_changes
             .get_mut(component_id)
         {
-            entry.set_attribute(&attribute);
+            entry.set_attribute(attribute);
         } else {
             let mut change = InterimEntityChanges::new(component_id);
-            change.set_attribute(&attribute);
+            change.set_attribute(attribute);
             self.entity_changes
                 .insert(component_id.to_string(), change);
         }
     }
 
+    /// Returns whether the component can be updated, records an error if it was deleted.
+    fn check_not_deleted(&mut self, component_id: &str, update: &str) -> bool {
+        if !self
+            .deleted_components
+            .contains(component_id)
+        {
+            return true;
+        }
+        self.lifecycle_errors
+            .push(ComponentLifecycleError::UpdateAfterDeletion {
+                component_id: component_id.to_string(),
+                update: update.to_string(),
+            });
+        false
+    }
+
     /// Registers a new entity change.
     ///
     /// Will prioritize the new change over any already present one.
     pub fn add_entity_change(&mut self, change: &EntityChanges) {
+        if !self.check_not_deleted(&change.component_id, "entity changes") {
+            return;
+        }
         self.entity_changes
             .entry(change.component_id.clone())
             .and_modify(|ec| {
@@ -163,6 +354,9 @@ impl TransactionChangesBuilder {
     /// This method is a noop, in case the component is already present. Since
     /// components are assumed to be immutable.
     pub fn add_protocol_component(&mut self, component: &ProtocolComponent) {
+        if !self.check_not_deleted(&component.id, "component") {
+            return;
+        }
         if !self
             .component_changes
             .contains_key(&component.id)
@@ -176,6 +370,11 @@ impl TransactionChangesBuilder {
     ///
     /// Overwrites any previous balance changes of the component if present.
     pub fn add_balance_change(&mut self, change: &BalanceChange) {
+        let component_id = String::from_utf8_lossy(&change.component_id).into_owned();
+        let update = format!("balance of token 0x{}", hex::encode(&change.token));
+        if !self.check_not_deleted(&component_id, &update) {
+            return;
+        }
         self.balance_changes
             .insert((change.component_id.clone(), change.token.clone()), change.clone());
     }
@@ -187,17 +386,177 @@ impl TransactionChangesBuilder {
             .insert(entrypoint.clone());
     }
 
+    /// Adds new entrypoint parameters to the transaction. Identical parameters are only emitted
+    /// once.
     pub fn add_entrypoint_params(&mut self, entrypoint: &EntryPointParams) {
         self.entrypoint_params
             .insert(entrypoint.clone());
     }
 
+    /// Ids of the entrypoints added so far.
+    pub(crate) fn entrypoint_ids(&self) -> impl Iterator<Item = String> + '_ {
+        self.entrypoints
+            .iter()
+            .map(|ep| ep.id.clone())
+    }
+
+    /// Checks the consistency of the entrypoints and entrypoint params of the transaction.
+    ///
+    /// Every `EntryPointParams` must reference a component and an entrypoint that is either part
+    /// of this transaction or already known, as decided by `is_known_entrypoint` (e.g. by looking
+    /// up a store of previously emitted entrypoints). Every `EntryPoint` must reference a
+    /// component.
+    pub fn validate_entrypoints<F: Fn(&str) -> bool>(
+        &self,
+        is_known_entrypoint: F,
+    ) -> Result<(), EntryPointError> {
+        if let Some(entrypoint) = self
+            .entrypoints
+            .iter()
+            .find(|ep| ep.component_id.is_empty())
+        {
+            return Err(EntryPointError::MissingComponent { entrypoint_id: entrypoint.id.clone() });
+        }
+
+        let entrypoint_ids: HashSet<&str> = self
+            .entrypoints
+            .iter()
+            .map(|ep| ep.id.as_str())
+            .collect();
+        for params in self.entrypoint_params.iter() {
+            if !matches!(&params.component_id, Some(id) if !id.is_empty()) {
+                return Err(EntryPointError::MissingComponent {
+                    entrypoint_id: params.entrypoint_id.clone(),
+                });
+            }
+            if !entrypoint_ids.contains(params.entrypoint_id.as_str()) &&
+                !is_known_entrypoint(&params.entrypoint_id)
+            {
+                return Err(EntryPointError::UnknownEntryPoint {
+                    entrypoint_id: params.entrypoint_id.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `build` but validates the changes first.
+    ///
+    /// Entrypoint params may only reference entrypoints added to this builder, use
+    /// `validate_entrypoints` directly if they can reference entrypoints from previous
+    /// transactions.
+    ///
+    /// ## Errors
+    /// - `TransactionChangesError::Attributes` if the attributes violate their schema, see
+    ///   `validate_attributes`.
+    /// - `TransactionChangesError::EntryPoint` if the entrypoints are inconsistent, see
+    ///   `validate_entrypoints`.
+    /// - `TransactionChangesError::Lifecycle` if deleted components were updated, see
+    ///   `validate_lifecycle`.
+    pub fn try_build(self) -> Result<Option<TransactionChanges>, TransactionChangesError> {
+        self.validate_components()?;
+        self.validate_entrypoints(|_| false)
+            .map_err(TransactionChangesError::EntryPoint)?;
+        Ok(self.into_transaction_changes())
+    }
+
+    /// Checks the attributes against their schema and the lifecycle of the components.
+    fn validate_components(&self) -> Result<(), TransactionChangesError> {
+        let errors = self.validate_attributes();
+        if !errors.is_empty() {
+            return Err(TransactionChangesError::Attributes(errors));
+        }
+        self.validate_lifecycle()
+            .map_err(TransactionChangesError::Lifecycle)
+    }
+
+    /// Checks the component attributes of the transaction against their schema.
+    ///
+    /// Static attributes of new components and entity changes are validated against the schema
+    /// declared in the component's `protocol_type`, falling back to the schema set with
+    /// `set_attribute_schema` for components created in previous transactions. Components
+    /// without any schema are not validated.
+    pub fn validate_attributes(&self) -> Vec<SchemaError> {
+        let mut errors = Vec::new();
+        let mut component_schemas = HashMap::new();
+        for component in self.component_changes.values() {
+            let encoded = component
+                .protocol_type
+                .as_ref()
+                .map(|pt| pt.attribute_schema.as_slice())
+                .unwrap_or_default();
+            if encoded.is_empty() {
+                continue;
+            }
+            match schema::decode_schema(encoded) {
+                Ok(definitions) => {
+                    errors.extend(schema::validate_component(&definitions, component));
+                    component_schemas.insert(component.id.as_str(), definitions);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        for (component_id, interim) in self
+            .entity_changes
+            .iter()
+            .sorted_by_key(|(id, _)| *id)
+        {
+            let definitions = component_schemas
+                .get(component_id.as_str())
+                .unwrap_or(&self.attribute_schema);
+            if definitions.is_empty() {
+                continue;
+            }
+            let changes = EntityChanges {
+                component_id: component_id.clone(),
+                attributes: interim
+                    .attributes
+                    .values()
+                    .sorted_by_key(|attr| &attr.name)
+                    .cloned()
+                    .collect(),
+            };
+            errors.extend(schema::validate_entity_changes(definitions, &changes));
+        }
+        errors
+    }
+
+    /// Checks that deleted components were not updated afterwards, see `delete_component`.
+    ///
+    /// ## Errors
+    /// Returns the first update of a deleted component.
+    pub fn validate_lifecycle(&self) -> Result<(), ComponentLifecycleError> {
+        match self.lifecycle_errors.first() {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Builds the `TransactionChanges`, returns `None` if nothing changed.
+    ///
+    /// Entrypoints are not validated, since their params may reference entrypoints of previous
+    /// transactions.
+    ///
+    /// ## Panics
+    /// Panics if the attributes violate their schema or a deleted component was updated, see
+    /// `try_build` for a non-panicking version.
     pub fn build(self) -> Option<TransactionChanges> {
+        self.validate_components()
+            .unwrap_or_else(|e| panic!("{e}"));
+        self.into_transaction_changes()
+    }
+
+    /// Builds the `TransactionChanges` without validating them.
+    pub(crate) fn into_transaction_changes(self) -> Option<TransactionChanges> {
         let tx_changes = TransactionChanges {
             tx: self.tx,
             contract_changes: self
-                .contract_changes
-                .into_values()
+                .recreated_contracts
+                .iter()
+                .sorted()
+                .map(|address| InterimContractChange::deletion(address))
+                .chain(self.contract_changes.into_values())
                 .filter_map(|interim| interim.into())
                 .collect::<Vec<_>>(),
             entity_changes: self
@@ -216,10 +575,14 @@ impl TransactionChangesBuilder {
             entrypoints: self
                 .entrypoints
                 .into_iter()
+                .sorted_by(|a, b| (&a.id, &a.component_id).cmp(&(&b.id, &b.component_id)))
                 .collect::<Vec<_>>(),
             entrypoint_params: self
                 .entrypoint_params
                 .into_iter()
+                .sorted_by(|a, b| {
+                    (&a.entrypoint_id, &a.component_id).cmp(&(&b.entrypoint_id, &b.component_id))
+                })
                 .collect::<Vec<_>>(),
         };
         if tx_changes.is_empty() {
@@ -230,6 +593,175 @@ impl TransactionChangesBuilder {
     }
 }
 
+/// Invalid changes found while building `TransactionChanges`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionChangesError {
+    /// Attributes violate their schema.
+    Attributes(Vec<SchemaError>),
+    /// Entrypoints are inconsistent.
+    EntryPoint(EntryPointError),
+    /// A component is updated after its deletion.
+    Lifecycle(ComponentLifecycleError),
+}
+
+impl std::fmt::Display for TransactionChangesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Attributes(errors) => write!(
+                f,
+                "Attribute schema violated: {}",
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .join("; ")
+            ),
+            Self::EntryPoint(error) => write!(f, "Invalid entrypoints: {error}"),
+            Self::Lifecycle(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for TransactionChangesError {}
+
+/// Inconsistency between the entrypoints and entrypoint params of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryPointError {
+    /// Entrypoint params reference an entrypoint that is neither part of the transaction nor
+    /// known.
+    UnknownEntryPoint { entrypoint_id: String },
+    /// An entrypoint or entrypoint params are not linked to any component.
+    MissingComponent { entrypoint_id: String },
+}
+
+impl std::fmt::Display for EntryPointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownEntryPoint { entrypoint_id } => {
+                write!(f, "Entrypoint params reference unknown entrypoint {entrypoint_id}")
+            }
+            Self::MissingComponent { entrypoint_id } => {
+                write!(f, "Entrypoint {entrypoint_id} is not linked to any component")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EntryPointError {}
+
+/// Inconsistency in the lifecycle of a component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentLifecycleError {
+    /// A component is updated after being deleted within the same block.
+    UpdateAfterDeletion { component_id: String, update: String },
+    /// A status attribute, `deprecated` or `paused`, is set while already set or deleted while
+    /// not set within the same block.
+    RedundantStatusChange { component_id: String, attribute: String, set: bool },
+}
+
+impl std::fmt::Display for ComponentLifecycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UpdateAfterDeletion { component_id, update } => {
+                write!(f, "Component {component_id} is updated after its deletion: {update}")
+            }
+            Self::RedundantStatusChange { component_id, attribute, set: true } => {
+                write!(f, "Component {component_id} is already {attribute}")
+            }
+            Self::RedundantStatusChange { component_id, attribute, set: false } => {
+                write!(f, "Component {component_id} is not {attribute}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ComponentLifecycleError {}
+
+impl BlockChanges {
+    /// Checks that deleted components are not updated afterwards within the block.
+    ///
+    /// Once a transaction deletes a component, neither that transaction nor the following ones
+    /// may create the component again, change its attributes or emit non-zero balances for it.
+    /// Status attributes, `deprecated` and `paused`, may not be set twice or deleted twice in a
+    /// row.
+    ///
+    /// ## Errors
+    /// Returns the first update of a deleted component or redundant status change.
+    pub fn validate_component_lifecycle(&self) -> Result<(), ComponentLifecycleError> {
+        let mut deleted: HashSet<&str> = HashSet::new();
+        let mut status: HashMap<(&str, &str), bool> = HashMap::new();
+        for tx_changes in self.changes.iter() {
+            let error = |component_id: &str, update: String| {
+                Err(ComponentLifecycleError::UpdateAfterDeletion {
+                    component_id: component_id.to_string(),
+                    update,
+                })
+            };
+            let deleted_here: HashSet<&str> = tx_changes
+                .component_changes
+                .iter()
+                .filter(|c| c.change == i32::from(ChangeType::Deletion))
+                .map(|c| c.id.as_str())
+                .collect();
+
+            for component in tx_changes.component_changes.iter() {
+                if deleted.contains(component.id.as_str()) {
+                    return error(&component.id, "component".to_string());
+                }
+            }
+            for entity in tx_changes.entity_changes.iter() {
+                let id = entity.component_id.as_str();
+                if deleted.contains(id) || deleted_here.contains(id) {
+                    return error(id, "entity changes".to_string());
+                }
+                for attribute in entity
+                    .attributes
+                    .iter()
+                    .filter(|attr| STATUS_ATTRIBUTES.contains(&attr.name.as_str()))
+                {
+                    let set = attribute.change != i32::from(ChangeType::Deletion);
+                    if status.insert((id, attribute.name.as_str()), set) == Some(set) {
+                        return Err(ComponentLifecycleError::RedundantStatusChange {
+                            component_id: id.to_string(),
+                            attribute: attribute.name.clone(),
+                            set,
+                        });
+                    }
+                }
+            }
+            for balance in tx_changes.balance_changes.iter() {
+                let id = String::from_utf8_lossy(&balance.component_id);
+                let is_zero = balance.balance.iter().all(|b| *b == 0);
+                if deleted.contains(id.as_ref()) || (deleted_here.contains(id.as_ref()) && !is_zero)
+                {
+                    return error(
+                        &id,
+                        format!("balance of token 0x{}", hex::encode(&balance.token)),
+                    );
+                }
+            }
+            deleted.extend(deleted_here);
+        }
+        Ok(())
+    }
+}
+
+impl Attribute {
+    /// Creates an attribute from a typed value.
+    ///
+    /// ## Parameters
+    /// - `name`: The name of the attribute.
+    /// - `value`: The value, encoded using its `AttributeCodec` implementation.
+    /// - `change`: The type of change.
+    pub fn typed<V: AttributeCodec>(name: &str, value: &V, change: ChangeType) -> Self {
+        Self { name: name.to_string(), value: value.encode(), change: change.into() }
+    }
+
+    /// Decodes the value of this attribute into a typed value.
+    pub fn decode<V: AttributeCodec>(&self) -> Result<V, String> {
+        V::decode(&self.value).map_err(|e| format!("Failed to decode attribute {}: {e}", self.name))
+    }
+}
+
 impl From<&sf::TransactionTrace> for Transaction {
     fn from(tx: &sf::TransactionTrace) -> Self {
         Self {
@@ -319,6 +851,19 @@ impl ProtocolComponent {
         self
     }
 
+    /// Adds the contracts the component's contracts delegate to.
+    ///
+    /// Implementations are resolved transitively, so the implementation of a proxy's beacon is
+    /// included as well. Call this after `with_contracts`.
+    ///
+    /// ## Parameters
+    /// - `implementations`: Proxies and their implementations, e.g. as returned by
+    ///   [`extract_proxy_implementations`](crate::proxy::extract_proxy_implementations).
+    pub fn with_implementations(mut self, implementations: &[ProxyImplementation]) -> Self {
+        self.contracts = resolve_implementations(&self.contracts, implementations);
+        self
+    }
+
     /// Updates the static attributes of this component.
     ///
     /// Sets the change type to `Creation` for all attributes.
@@ -337,6 +882,30 @@ impl ProtocolComponent {
         self
     }
 
+    /// Adds a typed static attribute to this component.
+    ///
+    /// Unlike `with_attributes`, this keeps previously set attributes. The change type is set to
+    /// `Creation`.
+    ///
+    /// ## Parameters
+    /// - `name`: The name of the attribute.
+    /// - `value`: The value, encoded using its `AttributeCodec` implementation.
+    pub fn with_typed_attribute<V: AttributeCodec>(mut self, name: &str, value: &V) -> Self {
+        self.static_att
+            .push(Attribute::typed(name, value, ChangeType::Creation));
+        self
+    }
+
+    /// Decodes a static attribute of this component into a typed value.
+    ///
+    /// Returns `None` if the attribute is not present.
+    pub fn get_static_attribute<V: AttributeCodec>(&self, name: &str) -> Option<Result<V, String>> {
+        self.static_att
+            .iter()
+            .find(|attr| attr.name == name)
+            .map(Attribute::decode)
+    }
+
     /// Designates this component as a swap type within the protocol.
     ///
     /// Sets the `protocol_type` accordingly, including `financial_type` as `Swap` and leaving
@@ -345,11 +914,29 @@ impl ProtocolComponent {
     /// ## Parameters
     /// - `name`: The name of the swap protocol.
     /// - `implementation_type`: The implementation type of the protocol.
-    pub fn as_swap_type(mut self, name: &str, implementation_type: ImplementationType) -> Self {
+    pub fn as_swap_type(self, name: &str, implementation_type: ImplementationType) -> Self {
+        self.as_swap_type_with_schema(name, implementation_type, &[])
+    }
+
+    /// Designates this component as a swap type within the protocol, declaring its attributes.
+    ///
+    /// Same as `as_swap_type`, but populates `attribute_schema` with the encoded schema. The
+    /// schema is used by `TransactionChangesBuilder` to validate the component's attributes.
+    ///
+    /// ## Parameters
+    /// - `name`: The name of the swap protocol.
+    /// - `implementation_type`: The implementation type of the protocol.
+    /// - `schema`: The attributes of the component.
+    pub fn as_swap_type_with_schema(
+        mut self,
+        name: &str,
+        implementation_type: ImplementationType,
+        schema: &[AttributeDefinition],
+    ) -> Self {
         self.protocol_type = Some(ProtocolType {
             name: name.to_string(),
             financial_type: FinancialType::Swap.into(),
-            attribute_schema: Vec::new(),
+            attribute_schema: schema::encode_schema(schema),
             implementation_type: implementation_type.into(),
         });
         self
@@ -481,6 +1068,21 @@ impl InterimEntityChanges {
             .and_modify(|existing| *existing = attr.clone())
             .or_insert(attr.clone());
     }
+
+    /// Sets an attribute from a typed value, see `set_attribute`.
+    ///
+    /// ## Parameters
+    /// - `name`: The name of the attribute.
+    /// - `value`: The value, encoded using its `AttributeCodec` implementation.
+    /// - `change`: The type of change.
+    pub fn set_typed_attribute<V: AttributeCodec>(
+        &mut self,
+        name: &str,
+        value: &V,
+        change: ChangeType,
+    ) {
+        self.set_attribute(&Attribute::typed(name, value, change));
+    }
 }
 
 impl From<InterimEntityChanges> for Option<EntityChanges> {
@@ -541,6 +1143,11 @@ impl InterimContractChange {
         }
     }
 
+    /// Creates a change marking the contract as deleted, e.g. after a `SELFDESTRUCT`.
+    pub fn deletion(address: &[u8]) -> Self {
+        Self { change: ChangeType::Deletion, ..Self::new(address, false) }
+    }
+
     pub fn upsert_slot(&mut self, change: &StorageChange) {
         if change.address != self.address {
             panic!("Bad storage change");
@@ -639,7 +1246,9 @@ impl TransactionChanges {
         self.contract_changes.is_empty() &&
             self.component_changes.is_empty() &&
             self.balance_changes.is_empty() &&
-            self.entity_changes.is_empty()
+            self.entity_changes.is_empty() &&
+            self.entrypoints.is_empty() &&
+            self.entrypoint_params.is_empty()
     }
 }
 
@@ -648,9 +1257,24 @@ mod test {
     use rstest::rstest;
     use substreams_ethereum::pb::eth::v2::StorageChange;
 
-    use crate::models::{Attribute, ChangeType, EntityChanges, Transaction, TransactionChanges};
-
-    use super::{InterimContractChange, TransactionChangesBuilder};
+    use crate::{
+        entrypoint::create_entrypoint,
+        models::{
+            entry_point_params::TraceData, Attribute, ChangeType, EntityChanges, EntryPoint,
+            EntryPointParams, RpcTraceData, Transaction, TransactionChanges,
+        },
+    };
+
+    use super::{
+        BalanceChange, BlockChanges, ComponentLifecycleError, ComponentStatus, EntryPointError,
+        InterimContractChange, ProtocolComponent, TransactionChangesBuilder,
+        TransactionChangesError,
+    };
+    use crate::{
+        attributes::List,
+        models::ImplementationType,
+        schema::{AttributeDefinition, AttributeType, SchemaError},
+    };
 
     fn create_attribute_change(value: u8, change_type: ChangeType) -> EntityChanges {
         EntityChanges {
@@ -786,6 +1410,72 @@ mod test {
         assert!(tx_changes.is_none());
     }
 
+    #[test]
+    fn test_transaction_changes_builder_contract_deletion() {
+        let mut builder = TransactionChangesBuilder::new(&super::Transaction::default());
+        let mut update = InterimContractChange::new(&[1], false);
+        update.upsert_slot(&StorageChange {
+            address: [1].to_vec(),
+            key: [0].to_vec(),
+            old_value: [0].to_vec(),
+            new_value: [1].to_vec(),
+            ordinal: 1,
+        });
+        builder.add_contract_changes(&update);
+        builder.add_contract_changes(&InterimContractChange::deletion(&[1]));
+
+        let tx_changes = builder.build().unwrap();
+        assert_eq!(tx_changes.contract_changes.len(), 1);
+        assert!(tx_changes.contract_changes[0]
+            .slots
+            .is_empty());
+        assert_eq!(tx_changes.contract_changes[0].change, ChangeType::Deletion as i32);
+    }
+
+    #[test]
+    fn test_transaction_changes_builder_contract_created_and_deleted() {
+        let mut builder = TransactionChangesBuilder::new(&super::Transaction::default());
+        let mut creation = InterimContractChange::new(&[1], true);
+        creation.set_code(&[0x60]);
+        builder.add_contract_changes(&creation);
+        builder.add_contract_changes(&InterimContractChange::deletion(&[1]));
+
+        assert!(builder.build().is_none());
+    }
+
+    #[test]
+    fn test_transaction_changes_builder_contract_recreated() {
+        let mut builder = TransactionChangesBuilder::new(&super::Transaction::default());
+        builder.add_contract_changes(&InterimContractChange::deletion(&[1]));
+        let mut creation = InterimContractChange::new(&[1], true);
+        creation.set_code(&[0x60]);
+        builder.add_contract_changes(&creation);
+
+        let tx_changes = builder.build().unwrap();
+        assert_eq!(
+            tx_changes
+                .contract_changes
+                .iter()
+                .map(|c| (c.change, c.code.clone()))
+                .collect::<Vec<_>>(),
+            vec![(ChangeType::Deletion as i32, vec![]), (ChangeType::Creation as i32, vec![0x60])]
+        );
+    }
+
+    #[test]
+    fn test_transaction_changes_builder_contract_recreated_and_deleted() {
+        let mut builder = TransactionChangesBuilder::new(&super::Transaction::default());
+        builder.add_contract_changes(&InterimContractChange::deletion(&[1]));
+        let mut creation = InterimContractChange::new(&[1], true);
+        creation.set_code(&[0x60]);
+        builder.add_contract_changes(&creation);
+        builder.add_contract_changes(&InterimContractChange::deletion(&[1]));
+
+        let tx_changes = builder.build().unwrap();
+        assert_eq!(tx_changes.contract_changes.len(), 1);
+        assert_eq!(tx_changes.contract_changes[0].change, ChangeType::Deletion as i32);
+    }
+
     #[test]
     fn test_transaction_changes_builder_ignored_deletion() {
         let mut builder = TransactionChangesBuilder::new(&super::Transaction::default());
@@ -818,6 +1508,193 @@ mod test {
         assert!(tx_changes.is_none());
     }
 
+    fn lifecycle_component() -> ProtocolComponent {
+        ProtocolComponent::new("pool")
+            .with_tokens(&[vec![1u8; 20], vec![2u8; 20]])
+            .with_attributes(&[("fee", vec![30u8])])
+    }
+
+    fn balance_change(component_id: &str, token: u8, balance: u8) -> BalanceChange {
+        BalanceChange {
+            token: vec![token; 20],
+            balance: vec![balance],
+            component_id: component_id.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_delete_component() {
+        let component = lifecycle_component();
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.add_balance_change(&balance_change("pool", 1, 5));
+        builder.add_entity_change(&create_attribute_change(1, ChangeType::Update));
+        builder.mark_component_as_updated("pool");
+
+        builder.delete_component(&component);
+
+        assert_eq!(builder.validate_lifecycle(), Ok(()));
+        let mut tx_changes = builder.build().unwrap();
+        tx_changes
+            .balance_changes
+            .sort_by(|a, b| a.token.cmp(&b.token));
+        let deleted = tx_changes.component_changes[0].clone();
+        assert_eq!(deleted.change, i32::from(ChangeType::Deletion));
+        assert_eq!(deleted.static_att[0].change, i32::from(ChangeType::Deletion));
+        assert_eq!(
+            tx_changes.balance_changes,
+            vec![balance_change("pool", 1, 0), balance_change("pool", 2, 0)]
+        );
+        // Only the unrelated entity change is kept
+        assert_eq!(tx_changes.entity_changes.len(), 1);
+        assert_eq!(tx_changes.entity_changes[0].component_id, "component");
+    }
+
+    #[test]
+    fn test_delete_created_component() {
+        let component = lifecycle_component();
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.add_protocol_component(&component);
+
+        builder.delete_component(&component);
+
+        assert!(builder.build().is_none());
+    }
+
+    #[test]
+    fn test_update_after_delete_component() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.delete_component(&lifecycle_component());
+
+        builder.add_balance_change(&balance_change("pool", 1, 5));
+        builder.reactivate_component("pool", ComponentStatus { deprecated: true, paused: false });
+
+        let expected = ComponentLifecycleError::UpdateAfterDeletion {
+            component_id: "pool".to_string(),
+            update: format!("balance of token 0x{}", "01".repeat(20)),
+        };
+        assert_eq!(builder.validate_lifecycle(), Err(expected.clone()));
+        assert_eq!(builder.try_build(), Err(TransactionChangesError::Lifecycle(expected)));
+    }
+
+    fn status_changes(builder: TransactionChangesBuilder) -> Vec<(String, ChangeType)> {
+        let mut changes = builder
+            .build()
+            .map(|tx_changes| {
+                tx_changes.entity_changes[0]
+                    .attributes
+                    .iter()
+                    .map(|attr| (attr.name.clone(), attr.change()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        changes.sort();
+        changes
+    }
+
+    #[test]
+    fn test_deprecate_and_reactivate_component() {
+        let active = ComponentStatus::default();
+        let deprecated = ComponentStatus { deprecated: true, paused: false };
+        let paused = ComponentStatus { deprecated: false, paused: true };
+
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.deprecate_component("pool", active);
+        builder.deprecate_component("pool", active);
+        assert_eq!(status_changes(builder), vec![("deprecated".to_string(), ChangeType::Creation)]);
+
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.deprecate_component("pool", deprecated);
+        assert_eq!(status_changes(builder), vec![]);
+
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.reactivate_component("pool", paused);
+        assert_eq!(status_changes(builder), vec![("paused".to_string(), ChangeType::Deletion)]);
+
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.reactivate_component("pool", deprecated);
+        builder.deprecate_component("pool", deprecated);
+        assert_eq!(status_changes(builder), vec![("deprecated".to_string(), ChangeType::Update)]);
+
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.deprecate_component("pool", active);
+        builder.change_component_pause_state("pool", true);
+        builder.reactivate_component("pool", active);
+        assert_eq!(status_changes(builder), vec![]);
+    }
+
+    #[rstest]
+    #[case::toggled(vec![ChangeType::Creation, ChangeType::Deletion, ChangeType::Update], Ok(()))]
+    #[case::set_twice(
+        vec![ChangeType::Creation, ChangeType::Update],
+        Err(ComponentLifecycleError::RedundantStatusChange {
+            component_id: "pool".to_string(),
+            attribute: "deprecated".to_string(),
+            set: true,
+        })
+    )]
+    #[case::deleted_twice(
+        vec![ChangeType::Deletion, ChangeType::Deletion],
+        Err(ComponentLifecycleError::RedundantStatusChange {
+            component_id: "pool".to_string(),
+            attribute: "deprecated".to_string(),
+            set: false,
+        })
+    )]
+    fn test_validate_component_lifecycle_status(
+        #[case] changes: Vec<ChangeType>,
+        #[case] expected: Result<(), ComponentLifecycleError>,
+    ) {
+        let block_changes = BlockChanges {
+            changes: changes
+                .into_iter()
+                .map(|change| TransactionChanges {
+                    entity_changes: vec![EntityChanges {
+                        component_id: "pool".to_string(),
+                        attributes: vec![Attribute {
+                            name: "deprecated".to_string(),
+                            value: vec![1u8],
+                            change: change.into(),
+                        }],
+                    }],
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(block_changes.validate_component_lifecycle(), expected);
+    }
+
+    #[rstest]
+    #[case::valid(vec![], Ok(()))]
+    #[case::balance_in_later_tx(
+        vec![balance_change("pool", 1, 5)],
+        Err(ComponentLifecycleError::UpdateAfterDeletion {
+            component_id: "pool".to_string(),
+            update: format!("balance of token 0x{}", "01".repeat(20)),
+        })
+    )]
+    fn test_validate_component_lifecycle(
+        #[case] later_balances: Vec<BalanceChange>,
+        #[case] expected: Result<(), ComponentLifecycleError>,
+    ) {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.delete_component(&lifecycle_component());
+        let block_changes = BlockChanges {
+            changes: vec![
+                builder.build().unwrap(),
+                TransactionChanges {
+                    tx: Some(Transaction { index: 1, ..Default::default() }),
+                    balance_changes: later_balances,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(block_changes.validate_component_lifecycle(), expected);
+    }
+
     #[test]
     fn test_change_component_pause_state_paused() {
         let mut builder = TransactionChangesBuilder::new(&super::Transaction::default());
@@ -906,4 +1783,205 @@ mod test {
         // Should be None because creation followed by deletion cancels out
         assert!(tx_changes.is_none());
     }
+
+    fn entrypoint(component_id: &str) -> (EntryPoint, EntryPointParams) {
+        create_entrypoint(
+            vec![1; 20],
+            "getRate()".to_string(),
+            component_id.to_string(),
+            TraceData::Rpc(RpcTraceData { caller: None, calldata: vec![0x67, 0x9a, 0xef, 0xce] }),
+        )
+    }
+
+    #[test]
+    fn test_entrypoint_only_transaction() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        let (ep, ep_params) = entrypoint("component");
+
+        builder.add_entrypoint(&ep);
+        builder.add_entrypoint_params(&ep_params);
+
+        let tx_changes = builder.build().unwrap();
+        assert_eq!(tx_changes.entrypoints, vec![ep]);
+        assert_eq!(tx_changes.entrypoint_params, vec![ep_params]);
+    }
+
+    #[test]
+    fn test_entrypoint_params_only_transaction() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        let (_, ep_params) = entrypoint("component");
+
+        builder.add_entrypoint_params(&ep_params);
+
+        assert!(builder.build().is_some());
+    }
+
+    #[test]
+    fn test_entrypoints_deduplicated_and_sorted() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        let (ep_b, ep_params_b) = entrypoint("component_b");
+        let (ep_a, ep_params_a) = entrypoint("component_a");
+
+        for _ in 0..2 {
+            builder.add_entrypoint(&ep_b);
+            builder.add_entrypoint(&ep_a);
+            builder.add_entrypoint_params(&ep_params_b);
+            builder.add_entrypoint_params(&ep_params_a);
+        }
+
+        let tx_changes = builder.try_build().unwrap().unwrap();
+        assert_eq!(tx_changes.entrypoints, vec![ep_a, ep_b]);
+        assert_eq!(tx_changes.entrypoint_params, vec![ep_params_a, ep_params_b]);
+    }
+
+    #[test]
+    fn test_validate_entrypoints_unknown_entrypoint() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        let (ep, ep_params) = entrypoint("component");
+        builder.add_entrypoint_params(&ep_params);
+
+        assert_eq!(
+            builder.validate_entrypoints(|_| false),
+            Err(EntryPointError::UnknownEntryPoint { entrypoint_id: ep.id.clone() })
+        );
+        // Entrypoints emitted in previous transactions are accepted
+        assert_eq!(builder.validate_entrypoints(|id| id == ep.id), Ok(()));
+        assert!(builder.try_build().is_err());
+    }
+
+    #[test]
+    fn test_validate_entrypoints_missing_component() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        let (ep, mut ep_params) = entrypoint("component");
+        ep_params.component_id = None;
+        builder.add_entrypoint(&ep);
+        builder.add_entrypoint_params(&ep_params);
+
+        assert_eq!(
+            builder.try_build(),
+            Err(TransactionChangesError::EntryPoint(EntryPointError::MissingComponent {
+                entrypoint_id: ep.id
+            }))
+        );
+    }
+
+    #[test]
+    fn test_typed_static_attributes() {
+        let tokens = vec![[1u8; 20], [2u8; 20]];
+        let component = ProtocolComponent::new("pool")
+            .with_attributes(&[("pool_type", "WeightedPool".as_bytes())])
+            .with_typed_attribute("fee", &3000u32)
+            .with_typed_attribute("tokens", &List(tokens.clone()));
+
+        assert_eq!(component.static_att.len(), 3);
+        assert_eq!(component.static_att[1].value, vec![0x0b, 0xb8]);
+        assert_eq!(
+            component.get_static_attribute::<String>("pool_type"),
+            Some(Ok("WeightedPool".to_string()))
+        );
+        assert_eq!(component.get_static_attribute::<u32>("fee"), Some(Ok(3000)));
+        assert_eq!(component.get_static_attribute("tokens"), Some(Ok(List(tokens))));
+        assert!(component
+            .get_static_attribute::<bool>("fee")
+            .unwrap()
+            .is_err());
+        assert_eq!(component.get_static_attribute::<u32>("missing"), None);
+    }
+
+    fn schema() -> Vec<AttributeDefinition> {
+        vec![
+            AttributeDefinition::static_attr("fee", AttributeType::Uint),
+            AttributeDefinition::mutable("reserve", AttributeType::Uint),
+        ]
+    }
+
+    #[test]
+    fn test_validate_attributes_component_schema() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.add_protocol_component(
+            &ProtocolComponent::new("pool")
+                .with_attributes(&[("fee", vec![0x0b, 0xb8])])
+                .as_swap_type_with_schema("pool", ImplementationType::Custom, &schema()),
+        );
+        builder.add_entity_change(&EntityChanges {
+            component_id: "pool".to_string(),
+            attributes: vec![
+                Attribute {
+                    name: "reserve".to_string(),
+                    value: vec![0x01],
+                    change: ChangeType::Creation.into(),
+                },
+                Attribute {
+                    name: "fee".to_string(),
+                    value: vec![0x01],
+                    change: ChangeType::Update.into(),
+                },
+            ],
+        });
+
+        assert_eq!(
+            builder.validate_attributes(),
+            vec![SchemaError::StaticAttributeChanged {
+                component_id: "pool".to_string(),
+                name: "fee".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_attributes_builder_schema() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.set_attribute_schema(&schema());
+        builder.add_entity_change(&EntityChanges {
+            component_id: "pool".to_string(),
+            attributes: vec![Attribute {
+                name: "reserve".to_string(),
+                value: vec![0x01],
+                change: ChangeType::Update.into(),
+            }],
+        });
+        builder.change_component_pause_state("pool", true);
+
+        assert!(builder.validate_attributes().is_empty());
+        assert!(builder.build().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Attribute schema violated")]
+    fn test_build_unknown_attribute() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.set_attribute_schema(&schema());
+        builder.add_entity_change(&EntityChanges {
+            component_id: "pool".to_string(),
+            attributes: vec![Attribute {
+                name: "unknown".to_string(),
+                value: vec![0x01],
+                change: ChangeType::Update.into(),
+            }],
+        });
+
+        builder.build();
+    }
+
+    #[test]
+    fn test_try_build_unknown_attribute() {
+        let mut builder = TransactionChangesBuilder::new(&Transaction::default());
+        builder.set_attribute_schema(&schema());
+        builder.add_entity_change(&EntityChanges {
+            component_id: "pool".to_string(),
+            attributes: vec![Attribute {
+                name: "unknown".to_string(),
+                value: vec![0x01],
+                change: ChangeType::Update.into(),
+            }],
+        });
+
+        assert_eq!(
+            builder.try_build(),
+            Err(TransactionChangesError::Attributes(vec![SchemaError::UnknownAttribute {
+                component_id: "pool".to_string(),
+                name: "unknown".to_string()
+            }]))
+        );
+    }
 }
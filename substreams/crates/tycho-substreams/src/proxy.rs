@@ -0,0 +1,398 @@
+//! Helpers to resolve the implementation contracts behind proxies.
+//!
+//! Proxies delegate their logic to other contracts, which must be indexed as well for the
+//! component to be simulated. The following patterns are detected from the code, storage changes
+//! and logs of the extended block model:
+//! - EIP-1167 minimal proxies (clones), including the Vyper forwarder variant, from their code.
+//! - EIP-1967 transparent and UUPS proxies, from writes to the implementation slot and `Upgraded`
+//!   logs.
+//! - EIP-1822 UUPS proxies, from writes to the `PROXIABLE` slot.
+//! - EIP-1967 beacon proxies, from writes to the beacon slot. The beacon's own implementation is
+//!   resolved from the `Upgraded` logs it emits.
+//! - EIP-2535 diamonds, from the facets added or replaced in `DiamondCut` logs.
+//!
+//! ## Example
+//! ```ignore
+//! let implementations = extract_proxy_implementations(tx);
+//! let component = ProtocolComponent::new(&pool_id)
+//!     .with_contracts(&[pool_address])
+//!     .with_implementations(&implementations);
+//! ```
+use std::collections::HashSet;
+
+use ethabi::ParamType;
+use hex_literal::hex;
+use substreams_ethereum::pb::eth::v2::{Log, StorageChange, TransactionTrace};
+
+/// `keccak256("eip1967.proxy.implementation") - 1`
+pub const EIP1967_IMPLEMENTATION_SLOT: [u8; 32] =
+    hex!("360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc");
+/// `keccak256("eip1967.proxy.beacon") - 1`
+pub const EIP1967_BEACON_SLOT: [u8; 32] =
+    hex!("a3f0ad74e5423aebfd80d3ef4346578335a9a72aeaee59ff6cb3582b35133d50");
+/// `keccak256("PROXIABLE")`
+pub const EIP1822_PROXIABLE_SLOT: [u8; 32] =
+    hex!("c5f16f0fcc639fa48a6947836d9850f504798523bf8c9a3a87d5876cf622bcf7");
+/// Topic0 of `Upgraded(address indexed implementation)`.
+pub const UPGRADED_TOPIC: [u8; 32] =
+    hex!("bc7cd75a20ee27fd9adebab32041f755214dbc6bffa90cc0225b39da2e5c2d3b");
+/// Topic0 of `DiamondCut((address,uint8,bytes4[])[],address,bytes)`.
+pub const DIAMOND_CUT_TOPIC: [u8; 32] =
+    hex!("8faa70878671ccd212d20771b795c50af8fd3ff6cf27f4bde57e5d4de0aeb673");
+
+/// Code prefix of EIP-1167 minimal proxies, followed by the target address.
+const EIP1167_PREFIX: [u8; 10] = [0x36, 0x3d, 0x3d, 0x37, 0x3d, 0x3d, 0x3d, 0x36, 0x3d, 0x73];
+/// Code prefix of the Vyper `create_forwarder_to` proxies, followed by the target address.
+const VYPER_FORWARDER_PREFIX: [u8; 15] =
+    [0x36, 0x60, 0x00, 0x60, 0x00, 0x37, 0x61, 0x10, 0x00, 0x60, 0x00, 0x36, 0x60, 0x00, 0x73];
+
+/// The proxy patterns detected by this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyKind {
+    Eip1167,
+    Eip1967,
+    Eip1822,
+    Beacon,
+    Diamond,
+}
+
+/// A contract delegating to another one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProxyImplementation {
+    pub kind: ProxyKind,
+    pub proxy: Vec<u8>,
+    /// The contract the proxy delegates to. For beacon proxies, this is the beacon.
+    pub implementation: Vec<u8>,
+}
+
+/// Returns the target of an EIP-1167 minimal proxy from its runtime code.
+///
+/// Both the standard EIP-1167 code and the forwarder deployed by Vyper's `create_forwarder_to`
+/// are supported. Returns `None` if the code is not a minimal proxy.
+pub fn eip1167_target(code: &[u8]) -> Option<Vec<u8>> {
+    [&EIP1167_PREFIX[..], &VYPER_FORWARDER_PREFIX[..]]
+        .iter()
+        .find(|prefix| code.starts_with(prefix))
+        .and_then(|prefix| code.get(prefix.len()..prefix.len() + 20))
+        .map(<[u8]>::to_vec)
+}
+
+/// Returns the proxy kind and the address written to a standard proxy slot.
+///
+/// Returns `None` if the change is not to an EIP-1967 implementation or beacon slot, nor to the
+/// EIP-1822 slot, or if it clears the slot.
+pub fn implementation_from_storage(change: &StorageChange) -> Option<(ProxyKind, Vec<u8>)> {
+    let kind = match change.key.as_slice() {
+        key if key == EIP1967_IMPLEMENTATION_SLOT => ProxyKind::Eip1967,
+        key if key == EIP1822_PROXIABLE_SLOT => ProxyKind::Eip1822,
+        key if key == EIP1967_BEACON_SLOT => ProxyKind::Beacon,
+        _ => return None,
+    };
+    address_from_word(&change.new_value).map(|address| (kind, address))
+}
+
+/// Returns the facets added or replaced by an EIP-2535 `DiamondCut` log.
+///
+/// Returns `None` if the log is not a `DiamondCut` or can't be decoded.
+pub fn diamond_facets(log: &Log) -> Option<Vec<Vec<u8>>> {
+    if log.topics.first()? != &DIAMOND_CUT_TOPIC {
+        return None;
+    }
+    let facet_cut = ParamType::Tuple(vec![
+        ParamType::Address,
+        ParamType::Uint(8),
+        ParamType::Array(Box::new(ParamType::FixedBytes(4))),
+    ]);
+    let params = ethabi::decode(
+        &[ParamType::Array(Box::new(facet_cut)), ParamType::Address, ParamType::Bytes],
+        &log.data,
+    )
+    .ok()?;
+
+    let cuts = params
+        .into_iter()
+        .next()?
+        .into_array()?;
+    let mut facets = Vec::new();
+    for cut in cuts {
+        let mut fields = cut.into_tuple()?.into_iter();
+        let facet = fields.next()?.into_address()?;
+        // Removed selectors point to the zero address, which isn't a facet.
+        if !facet.is_zero() {
+            facets.push(facet.as_bytes().to_vec());
+        }
+    }
+    Some(facets)
+}
+
+/// Extracts the proxies of a transaction and the contracts they delegate to.
+///
+/// Calls whose state was reverted are ignored. When a proxy is upgraded several times within the
+/// transaction, only its last implementation is returned, except for diamonds which keep all
+/// their facets.
+///
+/// ## Arguments
+/// * `tx` - The transaction to inspect, expected to come from an extended block model.
+pub fn extract_proxy_implementations(tx: &TransactionTrace) -> Vec<ProxyImplementation> {
+    let mut found = Vec::new();
+    for call in tx
+        .calls
+        .iter()
+        .filter(|call| !call.state_reverted)
+    {
+        for code_change in call.code_changes.iter() {
+            if let Some(target) = eip1167_target(&code_change.new_code) {
+                found.push((
+                    code_change.ordinal,
+                    ProxyImplementation {
+                        kind: ProxyKind::Eip1167,
+                        proxy: code_change.address.clone(),
+                        implementation: target,
+                    },
+                ));
+            }
+        }
+        for change in call.storage_changes.iter() {
+            if let Some((kind, implementation)) = implementation_from_storage(change) {
+                found.push((
+                    change.ordinal,
+                    ProxyImplementation { kind, proxy: change.address.clone(), implementation },
+                ));
+            }
+        }
+        for log in call.logs.iter() {
+            if log.topics.len() == 2 && log.topics[0] == UPGRADED_TOPIC {
+                // Emitted by EIP-1967 proxies and by beacons on upgrades.
+                if let Some(implementation) = address_from_word(&log.topics[1]) {
+                    found.push((
+                        log.ordinal,
+                        ProxyImplementation {
+                            kind: ProxyKind::Eip1967,
+                            proxy: log.address.clone(),
+                            implementation,
+                        },
+                    ));
+                }
+            } else if let Some(facets) = diamond_facets(log) {
+                found.extend(facets.into_iter().map(|facet| {
+                    (
+                        log.ordinal,
+                        ProxyImplementation {
+                            kind: ProxyKind::Diamond,
+                            proxy: log.address.clone(),
+                            implementation: facet,
+                        },
+                    )
+                }));
+            }
+        }
+    }
+    found.sort_by_key(|(ordinal, _)| *ordinal);
+
+    let mut implementations: Vec<ProxyImplementation> = Vec::new();
+    for (_, entry) in found {
+        if implementations.contains(&entry) {
+            continue;
+        }
+        if entry.kind != ProxyKind::Diamond {
+            implementations.retain(|other| other.proxy != entry.proxy || other.kind != entry.kind);
+        }
+        implementations.push(entry);
+    }
+    implementations
+}
+
+/// Returns the given contracts followed by the contracts they delegate to.
+///
+/// Implementations are resolved transitively, e.g. a beacon proxy resolves to its beacon and the
+/// beacon's implementation. Each contract is returned once, in discovery order.
+pub fn resolve_implementations<B: AsRef<[u8]>>(
+    contracts: &[B],
+    implementations: &[ProxyImplementation],
+) -> Vec<Vec<u8>> {
+    let mut resolved: Vec<Vec<u8>> = Vec::new();
+    let mut seen = HashSet::new();
+    for contract in contracts {
+        if seen.insert(contract.as_ref().to_vec()) {
+            resolved.push(contract.as_ref().to_vec());
+        }
+    }
+
+    let mut idx = 0;
+    while idx < resolved.len() {
+        let proxy = resolved[idx].clone();
+        for entry in implementations
+            .iter()
+            .filter(|entry| entry.proxy == proxy)
+        {
+            if seen.insert(entry.implementation.clone()) {
+                resolved.push(entry.implementation.clone());
+            }
+        }
+        idx += 1;
+    }
+    resolved
+}
+
+/// Returns the address stored in a 32 bytes word, or `None` if it's zero or has dirty high bytes.
+fn address_from_word(word: &[u8]) -> Option<Vec<u8>> {
+    if word.len() > 32 {
+        return None;
+    }
+    let padding = word.len().saturating_sub(20);
+    let (high, address) = word.split_at(padding);
+    if high.iter().any(|b| *b != 0) || address.iter().all(|b| *b == 0) {
+        return None;
+    }
+    let mut result = vec![0u8; 20 - address.len()];
+    result.extend_from_slice(address);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::keys::keccak256;
+    use ethabi::{ethereum_types::Address, Token};
+    use rstest::rstest;
+    use substreams_ethereum::pb::eth::v2::{Call, CodeChange};
+
+    fn word(address: &[u8]) -> Vec<u8> {
+        [vec![0u8; 12], address.to_vec()].concat()
+    }
+
+    fn slot_change(proxy: u8, slot: [u8; 32], value: u8, ordinal: u64) -> StorageChange {
+        StorageChange {
+            address: vec![proxy; 20],
+            key: slot.to_vec(),
+            new_value: word(&[value; 20]),
+            ordinal,
+            ..Default::default()
+        }
+    }
+
+    fn entry(kind: ProxyKind, proxy: u8, implementation: u8) -> ProxyImplementation {
+        ProxyImplementation {
+            kind,
+            proxy: vec![proxy; 20],
+            implementation: vec![implementation; 20],
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        let minus_one = |mut hash: [u8; 32]| {
+            hash[31] -= 1;
+            hash
+        };
+        assert_eq!(
+            EIP1967_IMPLEMENTATION_SLOT,
+            minus_one(keccak256(b"eip1967.proxy.implementation"))
+        );
+        assert_eq!(EIP1967_BEACON_SLOT, minus_one(keccak256(b"eip1967.proxy.beacon")));
+        assert_eq!(EIP1822_PROXIABLE_SLOT, keccak256(b"PROXIABLE"));
+        assert_eq!(UPGRADED_TOPIC, keccak256(b"Upgraded(address)"));
+        assert_eq!(
+            DIAMOND_CUT_TOPIC,
+            keccak256(b"DiamondCut((address,uint8,bytes4[])[],address,bytes)")
+        );
+    }
+
+    #[rstest]
+    #[case::eip1167(
+        concat!(
+            "363d3d373d3d3d363d73",
+            "bebebebebebebebebebebebebebebebebebebebe",
+            "5af43d82803e903d91602b57fd5bf3"
+        ),
+        Some("bebebebebebebebebebebebebebebebebebebebe")
+    )]
+    #[case::vyper_forwarder(
+        concat!(
+            "366000600037611000600036600073",
+            "bebebebebebebebebebebebebebebebebebebebe",
+            "5af4602c57600080fd5b6110006000f3"
+        ),
+        Some("bebebebebebebebebebebebebebebebebebebebe")
+    )]
+    #[case::truncated("363d3d373d3d3d363d73bebebebe", None)]
+    #[case::other_code("6080604052", None)]
+    fn test_eip1167_target(#[case] code: &str, #[case] expected: Option<&str>) {
+        let code = hex::decode(code).unwrap();
+        assert_eq!(eip1167_target(&code), expected.map(|target| hex::decode(target).unwrap()));
+    }
+
+    #[test]
+    fn test_diamond_facets() {
+        let cut = |facet: u8, action: u8| {
+            Token::Tuple(vec![
+                Token::Address(Address::repeat_byte(facet)),
+                Token::Uint(action.into()),
+                Token::Array(vec![Token::FixedBytes(vec![0xaa; 4])]),
+            ])
+        };
+        let log = Log {
+            topics: vec![DIAMOND_CUT_TOPIC.to_vec()],
+            data: ethabi::encode(&[
+                Token::Array(vec![cut(1, 0), cut(2, 1), cut(0, 2)]),
+                Token::Address(Address::zero()),
+                Token::Bytes(vec![]),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(diamond_facets(&log), Some(vec![vec![1; 20], vec![2; 20]]));
+    }
+
+    #[test]
+    fn test_extract_proxy_implementations() {
+        let upgraded = Log {
+            address: vec![4; 20],
+            topics: vec![UPGRADED_TOPIC.to_vec(), word(&[5; 20])],
+            ordinal: 4,
+            ..Default::default()
+        };
+        let tx = TransactionTrace {
+            calls: vec![
+                Call {
+                    code_changes: vec![CodeChange {
+                        address: vec![1; 20],
+                        new_code: [EIP1167_PREFIX.to_vec(), vec![2; 20]].concat(),
+                        ordinal: 1,
+                        ..Default::default()
+                    }],
+                    storage_changes: vec![
+                        slot_change(3, EIP1967_IMPLEMENTATION_SLOT, 6, 2),
+                        slot_change(3, EIP1967_IMPLEMENTATION_SLOT, 7, 5),
+                        slot_change(8, EIP1967_BEACON_SLOT, 4, 3),
+                    ],
+                    logs: vec![upgraded],
+                    ..Default::default()
+                },
+                Call {
+                    state_reverted: true,
+                    storage_changes: vec![slot_change(9, EIP1822_PROXIABLE_SLOT, 9, 6)],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let implementations = extract_proxy_implementations(&tx);
+
+        assert_eq!(
+            implementations,
+            vec![
+                entry(ProxyKind::Eip1167, 1, 2),
+                entry(ProxyKind::Beacon, 8, 4),
+                entry(ProxyKind::Eip1967, 4, 5),
+                entry(ProxyKind::Eip1967, 3, 7),
+            ]
+        );
+        // The beacon proxy resolves to the beacon and its implementation.
+        assert_eq!(
+            resolve_implementations(&[vec![8u8; 20], vec![1u8; 20]], &implementations),
+            vec![vec![8; 20], vec![1; 20], vec![4; 20], vec![2; 20], vec![5; 20]]
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! Declarative attribute schemas for protocol components.
+//!
+//! An integration declares the attributes of its components once, including their type and
+//! whether they are static (set once on the `ProtocolComponent`) or mutable (changed through
+//! `EntityChanges`). The schema is emitted as `ProtocolType.attribute_schema` and used by
+//! `TransactionChangesBuilder` to catch attributes drifting from it.
+//!
+//! ## Example
+//! ```ignore
+//! fn schema() -> Vec<AttributeDefinition> {
+//!     vec![
+//!         AttributeDefinition::static_attr("fee", AttributeType::Uint),
+//!         AttributeDefinition::mutable("reserve0", AttributeType::Uint),
+//!         AttributeDefinition::mutable("reserve1", AttributeType::Uint),
+//!     ]
+//! }
+//!
+//! let component = ProtocolComponent::new(&pool_id)
+//!     .with_tokens(&[token0, token1])
+//!     .with_attributes(&[("fee", fee.to_signed_bytes_be())])
+//!     .as_swap_type_with_schema("uniswap_v2_pool", ImplementationType::Custom, &schema());
+//! ```
+use serde_json::json;
+
+use crate::{
+    attributes::{json_serialize_value, AttributeCodec, List},
+    balances::NEGATIVE_BALANCE_ATTRIBUTE_PREFIX,
+    models::{Attribute, ChangeType, EntityChanges, ProtocolComponent},
+};
+
+/// Attributes set by `TransactionChangesBuilder` itself, these are valid for any schema.
+const BUILDER_ATTRIBUTES: [&str; 3] = ["update_marker", "paused", "deprecated"];
+
+/// Whether an attribute is set by the SDK itself rather than by the integration.
+fn is_builtin_attribute(name: &str) -> bool {
+    BUILDER_ATTRIBUTES.contains(&name) || name.starts_with(NEGATIVE_BALANCE_ATTRIBUTE_PREFIX)
+}
+
+/// The type of an attribute value, following the encodings of [`AttributeCodec`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+    /// An unsigned integer, encoded as a big endian signed integer.
+    Uint,
+    /// A signed integer, encoded as a big endian signed integer.
+    Int,
+    /// A boolean, encoded as a single `0` or `1` byte.
+    Bool,
+    /// A 20 bytes address.
+    Address,
+    /// An utf-8 string.
+    String,
+    /// Arbitrary bytes.
+    Bytes,
+    /// A json list of 0x prefixed hex strings.
+    List,
+}
+
+impl AttributeType {
+    /// The name used for this type in the encoded schema.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Uint => "uint",
+            Self::Int => "int",
+            Self::Bool => "bool",
+            Self::Address => "address",
+            Self::String => "string",
+            Self::Bytes => "bytes",
+            Self::List => "list",
+        }
+    }
+
+    /// Parses a type from its name in the encoded schema.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "uint" => Some(Self::Uint),
+            "int" => Some(Self::Int),
+            "bool" => Some(Self::Bool),
+            "address" => Some(Self::Address),
+            "string" => Some(Self::String),
+            "bytes" => Some(Self::Bytes),
+            "list" => Some(Self::List),
+            _ => None,
+        }
+    }
+
+    /// Checks whether an encoded value is valid for this type.
+    pub fn accepts(&self, value: &[u8]) -> bool {
+        match self {
+            Self::Uint => !value
+                .first()
+                .is_some_and(|b| b & 0x80 != 0),
+            Self::Int | Self::Bytes => true,
+            Self::Bool => bool::decode(value).is_ok(),
+            Self::Address => value.len() == 20,
+            Self::String => std::str::from_utf8(value).is_ok(),
+            Self::List => List::<Vec<u8>>::decode(value).is_ok(),
+        }
+    }
+}
+
+/// Declares a single attribute of a protocol component.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeDefinition {
+    /// The attribute name.
+    pub name: String,
+    /// The type of the attribute value.
+    pub attribute_type: AttributeType,
+    /// Whether the attribute is set once at creation as a static attribute of the component.
+    /// Static attributes must not change afterwards.
+    pub is_static: bool,
+}
+
+impl AttributeDefinition {
+    /// Declares a static attribute, set once on the `ProtocolComponent`.
+    pub fn static_attr(name: &str, attribute_type: AttributeType) -> Self {
+        Self { name: name.to_string(), attribute_type, is_static: true }
+    }
+
+    /// Declares a mutable attribute, changed through `EntityChanges`.
+    pub fn mutable(name: &str, attribute_type: AttributeType) -> Self {
+        Self { name: name.to_string(), attribute_type, is_static: false }
+    }
+
+    /// Encodes this definition as an entry of `ProtocolType.attribute_schema`.
+    ///
+    /// The attribute value is a json object containing the type and whether the attribute is
+    /// static, e.g. `{"static":false,"type":"uint"}`.
+    pub fn to_attribute(&self) -> Attribute {
+        Attribute {
+            name: self.name.clone(),
+            value: json_serialize_value(
+                json!({"type": self.attribute_type.as_str(), "static": self.is_static}),
+            ),
+            change: ChangeType::Creation.into(),
+        }
+    }
+
+    /// Decodes a definition from an entry of `ProtocolType.attribute_schema`.
+    pub fn from_attribute(attribute: &Attribute) -> Result<Self, SchemaError> {
+        let invalid = || SchemaError::InvalidDefinition { name: attribute.name.clone() };
+        let value: serde_json::Value =
+            serde_json::from_slice(&attribute.value).map_err(|_| invalid())?;
+        let attribute_type = value["type"]
+            .as_str()
+            .and_then(AttributeType::parse)
+            .ok_or_else(invalid)?;
+        let is_static = value["static"]
+            .as_bool()
+            .ok_or_else(invalid)?;
+        Ok(Self { name: attribute.name.clone(), attribute_type, is_static })
+    }
+}
+
+/// Encodes a schema as `ProtocolType.attribute_schema`.
+pub fn encode_schema(schema: &[AttributeDefinition]) -> Vec<Attribute> {
+    schema
+        .iter()
+        .map(AttributeDefinition::to_attribute)
+        .collect()
+}
+
+/// Decodes a schema from `ProtocolType.attribute_schema`.
+pub fn decode_schema(attributes: &[Attribute]) -> Result<Vec<AttributeDefinition>, SchemaError> {
+    attributes
+        .iter()
+        .map(AttributeDefinition::from_attribute)
+        .collect()
+}
+
+/// Checks the static attributes of a newly created component against a schema.
+///
+/// Every static attribute must be declared as static and hold a valid value for its type.
+pub fn validate_component(
+    schema: &[AttributeDefinition],
+    component: &ProtocolComponent,
+) -> Vec<SchemaError> {
+    component
+        .static_att
+        .iter()
+        .filter_map(|attr| match find(schema, &attr.name) {
+            None => Some(SchemaError::UnknownAttribute {
+                component_id: component.id.clone(),
+                name: attr.name.clone(),
+            }),
+            Some(definition) if !definition.is_static => {
+                Some(SchemaError::MutableAttributeAsStatic {
+                    component_id: component.id.clone(),
+                    name: attr.name.clone(),
+                })
+            }
+            Some(definition) => check_value(&component.id, definition, attr),
+        })
+        .collect()
+}
+
+/// Checks the attribute changes of a component against a schema.
+///
+/// Every changed attribute must be declared as mutable and hold a valid value for its type.
+/// Attributes set by the SDK itself (e.g. `paused`) are always accepted.
+pub fn validate_entity_changes(
+    schema: &[AttributeDefinition],
+    changes: &EntityChanges,
+) -> Vec<SchemaError> {
+    changes
+        .attributes
+        .iter()
+        .filter(|attr| !is_builtin_attribute(&attr.name))
+        .filter_map(|attr| match find(schema, &attr.name) {
+            None => Some(SchemaError::UnknownAttribute {
+                component_id: changes.component_id.clone(),
+                name: attr.name.clone(),
+            }),
+            Some(definition) if definition.is_static => Some(SchemaError::StaticAttributeChanged {
+                component_id: changes.component_id.clone(),
+                name: attr.name.clone(),
+            }),
+            Some(_) if attr.change == i32::from(ChangeType::Deletion) => None,
+            Some(definition) => check_value(&changes.component_id, definition, attr),
+        })
+        .collect()
+}
+
+fn find<'a>(schema: &'a [AttributeDefinition], name: &str) -> Option<&'a AttributeDefinition> {
+    schema
+        .iter()
+        .find(|definition| definition.name == name)
+}
+
+fn check_value(
+    component_id: &str,
+    definition: &AttributeDefinition,
+    attr: &Attribute,
+) -> Option<SchemaError> {
+    (!definition
+        .attribute_type
+        .accepts(&attr.value))
+    .then(|| SchemaError::InvalidValue {
+        component_id: component_id.to_string(),
+        name: attr.name.clone(),
+        expected: definition.attribute_type,
+    })
+}
+
+/// A violation of a component attribute schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    /// The attribute is not declared in the schema.
+    UnknownAttribute { component_id: String, name: String },
+    /// A static attribute is changed through `EntityChanges`.
+    StaticAttributeChanged { component_id: String, name: String },
+    /// A mutable attribute is set as static attribute of the component.
+    MutableAttributeAsStatic { component_id: String, name: String },
+    /// The attribute value is not valid for the declared type.
+    InvalidValue { component_id: String, name: String, expected: AttributeType },
+    /// An entry of an encoded schema could not be decoded.
+    InvalidDefinition { name: String },
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownAttribute { component_id, name } => {
+                write!(f, "Attribute {name} of component {component_id} is not part of the schema")
+            }
+            Self::StaticAttributeChanged { component_id, name } => {
+                write!(f, "Static attribute {name} of component {component_id} is changed")
+            }
+            Self::MutableAttributeAsStatic { component_id, name } => {
+                write!(f, "Mutable attribute {name} of component {component_id} is set as static")
+            }
+            Self::InvalidValue { component_id, name, expected } => {
+                write!(
+                    f,
+                    "Attribute {name} of component {component_id} is not a valid {}",
+                    expected.as_str()
+                )
+            }
+            Self::InvalidDefinition { name } => {
+                write!(f, "Invalid schema definition for attribute {name}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn schema() -> Vec<AttributeDefinition> {
+        vec![
+            AttributeDefinition::static_attr("fee", AttributeType::Uint),
+            AttributeDefinition::static_attr("tokens", AttributeType::List),
+            AttributeDefinition::mutable("reserve", AttributeType::Uint),
+            AttributeDefinition::mutable("tick", AttributeType::Int),
+        ]
+    }
+
+    fn attribute(name: &str, value: Vec<u8>, change: ChangeType) -> Attribute {
+        Attribute { name: name.to_string(), value, change: change.into() }
+    }
+
+    #[test]
+    fn test_schema_roundtrip() {
+        let encoded = encode_schema(&schema());
+
+        assert_eq!(encoded[0].value, br#"{"static":true,"type":"uint"}"#.to_vec());
+        assert_eq!(decode_schema(&encoded), Ok(schema()));
+    }
+
+    #[test]
+    fn test_decode_invalid_schema() {
+        let encoded =
+            [attribute("fee", br#"{"type":"float","static":true}"#.to_vec(), ChangeType::Creation)];
+
+        assert_eq!(
+            decode_schema(&encoded),
+            Err(SchemaError::InvalidDefinition { name: "fee".to_string() })
+        );
+    }
+
+    #[rstest]
+    #[case::uint(AttributeType::Uint, vec![0x00, 0x80], true)]
+    #[case::negative_uint(AttributeType::Uint, vec![0x80], false)]
+    #[case::bool(AttributeType::Bool, vec![0x01], true)]
+    #[case::invalid_bool(AttributeType::Bool, vec![0x02], false)]
+    #[case::address(AttributeType::Address, vec![0x01; 20], true)]
+    #[case::invalid_address(AttributeType::Address, vec![0x01; 32], false)]
+    #[case::list(AttributeType::List, br#"["0x01"]"#.to_vec(), true)]
+    #[case::invalid_list(AttributeType::List, vec![0x01], false)]
+    fn test_attribute_type_accepts(
+        #[case] attribute_type: AttributeType,
+        #[case] value: Vec<u8>,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(attribute_type.accepts(&value), expected);
+    }
+
+    #[test]
+    fn test_validate_component() {
+        let component = ProtocolComponent::new("pool").with_attributes(&[
+            ("fee", vec![0x0b, 0xb8]),
+            ("reserve", vec![0x01]),
+            ("unknown", vec![0x01]),
+        ]);
+
+        assert_eq!(
+            validate_component(&schema(), &component),
+            vec![
+                SchemaError::MutableAttributeAsStatic {
+                    component_id: "pool".to_string(),
+                    name: "reserve".to_string()
+                },
+                SchemaError::UnknownAttribute {
+                    component_id: "pool".to_string(),
+                    name: "unknown".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_entity_changes() {
+        let changes = EntityChanges {
+            component_id: "pool".to_string(),
+            attributes: vec![
+                attribute("reserve", vec![0x01], ChangeType::Update),
+                attribute("tick", vec![0xff], ChangeType::Update),
+                attribute("reserve", vec![0xff], ChangeType::Update),
+                attribute("fee", vec![0x01], ChangeType::Update),
+                attribute("unknown", vec![0x01], ChangeType::Update),
+                attribute("paused", vec![0x01], ChangeType::Creation),
+                attribute("negative_balance_01", vec![0xff], ChangeType::Update),
+            ],
+        };
+
+        assert_eq!(
+            validate_entity_changes(&schema(), &changes),
+            vec![
+                SchemaError::InvalidValue {
+                    component_id: "pool".to_string(),
+                    name: "reserve".to_string(),
+                    expected: AttributeType::Uint
+                },
+                SchemaError::StaticAttributeChanged {
+                    component_id: "pool".to_string(),
+                    name: "fee".to_string()
+                },
+                SchemaError::UnknownAttribute {
+                    component_id: "pool".to_string(),
+                    name: "unknown".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Storage key derivation following the Solidity storage layout rules.
+//!
+//! See the [Solidity documentation](https://docs.soliditylang.org/en/latest/internals/layout_in_storage.html)
+//! for details on how mappings and dynamic arrays are laid out in storage.
+//!
+//! ## Example
+//! ```ignore
+//! // mapping(address => mapping(address => uint256)) allowance; declared at slot 4
+//! let slot = nested_mapping_slot(
+//!     &slot_from_index(4),
+//!     &[MappingKey::address(&owner), MappingKey::address(&spender)],
+//! );
+//! ```
+use std::collections::HashMap;
+
+use substreams::scalar::BigInt;
+use tiny_keccak::{Hasher, Keccak};
+
+/// Computes the keccak256 hash of the given data.
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut output = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    hasher.finalize(&mut output);
+    output
+}
+
+/// Left pads the input to 32 bytes using the given padding value.
+///
+/// Use `0x00` for unsigned values and `0xff` for negative signed values.
+///
+/// ## Panics
+/// Panics if the input is longer than 32 bytes.
+pub fn left_pad(input: &[u8], padding_value: u8) -> [u8; 32] {
+    if input.len() > 32 {
+        panic!("Cannot left pad {} bytes to 32 bytes", input.len());
+    }
+    let mut data = [padding_value; 32];
+    data[32 - input.len()..].copy_from_slice(input);
+    data
+}
+
+/// Returns the slot at the given position of the contract storage.
+///
+/// State variables that are not mappings or dynamic arrays are laid out contiguously starting at
+/// slot 0, this converts such a position into a storage key.
+pub fn slot_from_index(index: u64) -> [u8; 32] {
+    let mut slot = [0u8; 32];
+    slot[24..].copy_from_slice(&index.to_be_bytes());
+    slot
+}
+
+/// A key of a Solidity mapping.
+///
+/// Value type keys are padded to 32 bytes before hashing, while `string` and `bytes` keys are
+/// hashed as is.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MappingKey {
+    /// A value type key (`uint`, `int`, `address`, `bool` or `bytesN`), already padded.
+    Word([u8; 32]),
+    /// A `string` or `bytes` key.
+    Bytes(Vec<u8>),
+}
+
+impl MappingKey {
+    /// An `address` key.
+    ///
+    /// ## Panics
+    /// Panics if the address is longer than 32 bytes.
+    pub fn address(address: &[u8]) -> Self {
+        Self::Word(left_pad(address, 0))
+    }
+
+    /// An unsigned integer key.
+    ///
+    /// ## Panics
+    /// Panics if the value is negative or does not fit in 32 bytes.
+    pub fn uint(value: &BigInt) -> Self {
+        if *value < BigInt::zero() {
+            panic!("Negative value {value} used as unsigned mapping key");
+        }
+        Self::Word(left_pad(&value.to_bytes_be().1, 0))
+    }
+
+    /// A signed integer key, sign extended to 32 bytes.
+    ///
+    /// ## Panics
+    /// Panics if the value does not fit in 32 bytes.
+    pub fn int(value: &BigInt) -> Self {
+        let padding = if *value < BigInt::zero() { 0xff } else { 0 };
+        Self::Word(left_pad(&value.to_signed_bytes_be(), padding))
+    }
+
+    /// A `bool` key.
+    pub fn bool(value: bool) -> Self {
+        Self::Word(slot_from_index(value.into()))
+    }
+
+    /// A fixed size `bytesN` key, which is right padded.
+    ///
+    /// ## Panics
+    /// Panics if the value is longer than 32 bytes.
+    pub fn fixed_bytes(value: &[u8]) -> Self {
+        if value.len() > 32 {
+            panic!("Cannot right pad {} bytes to 32 bytes", value.len());
+        }
+        let mut word = [0u8; 32];
+        word[..value.len()].copy_from_slice(value);
+        Self::Word(word)
+    }
+
+    /// A dynamically sized `string` or `bytes` key.
+    pub fn bytes(value: &[u8]) -> Self {
+        Self::Bytes(value.to_vec())
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Word(word) => word,
+            Self::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+impl From<u64> for MappingKey {
+    fn from(value: u64) -> Self {
+        Self::Word(slot_from_index(value))
+    }
+}
+
+/// Computes the storage slot of a mapping entry.
+///
+/// The slot of the value corresponding to `key` in a mapping located at `base_slot` is
+/// `keccak256(key . base_slot)`, where `key` is already padded to 32 bytes.
+pub fn mapping_slot(key: &[u8; 32], base_slot: &[u8; 32]) -> [u8; 32] {
+    let mut preimage = [0u8; 64];
+    preimage[..32].copy_from_slice(key);
+    preimage[32..].copy_from_slice(base_slot);
+    keccak256(&preimage)
+}
+
+/// Computes the storage slot of a mapping entry for any kind of key.
+pub fn mapping_key_slot(key: &MappingKey, base_slot: &[u8; 32]) -> [u8; 32] {
+    let key = key.as_bytes();
+    let mut preimage = Vec::with_capacity(key.len() + 32);
+    preimage.extend_from_slice(key);
+    preimage.extend_from_slice(base_slot);
+    keccak256(&preimage)
+}
+
+/// Computes the storage slot of an entry in nested mappings.
+///
+/// Keys are applied from the outermost mapping inwards, i.e. `map[a][b]` is derived with
+/// `keys = [a, b]`. Passing no keys returns `base_slot`.
+pub fn nested_mapping_slot(base_slot: &[u8; 32], keys: &[MappingKey]) -> [u8; 32] {
+    keys.iter()
+        .fold(*base_slot, |slot, key| mapping_key_slot(key, &slot))
+}
+
+/// Computes the storage slot of a struct member.
+///
+/// Struct members are laid out contiguously starting at the struct slot, following the same
+/// packing rules as state variables. `member_slot` is the position of the member's slot relative
+/// to the start of the struct.
+pub fn struct_member_slot(struct_slot: &[u8; 32], member_slot: u64) -> [u8; 32] {
+    add_to_slot(struct_slot, member_slot.into())
+}
+
+/// Computes the storage slot of a dynamic array element.
+///
+/// Elements of a dynamic array located at `base_slot` start at `keccak256(base_slot)`, each
+/// element taking `slots_per_element` consecutive slots.
+///
+/// ## Note
+/// Arrays of elements of 16 bytes or less pack several elements into a single slot, in that case
+/// `slots_per_element` can't express the layout and the slot has to be computed manually.
+pub fn array_element_slot(base_slot: &[u8; 32], index: u64, slots_per_element: u64) -> [u8; 32] {
+    let start = keccak256(base_slot);
+    add_to_slot(&start, u128::from(index) * u128::from(slots_per_element))
+}
+
+/// Adds an offset to a slot, wrapping around on overflow like the EVM does.
+pub fn add_to_slot(slot: &[u8; 32], offset: u128) -> [u8; 32] {
+    let mut result = *slot;
+    let offset = offset.to_be_bytes();
+    let mut carry = 0u16;
+    for i in (0..32).rev() {
+        let addend = if i >= 16 { offset[i - 16] } else { 0 };
+        let sum = u16::from(result[i]) + u16::from(addend) + carry;
+        result[i] = sum as u8;
+        carry = sum >> 8;
+    }
+    result
+}
+
+/// Reverse lookup from storage slots to mapping entries.
+///
+/// Storage changes only expose the hashed slot, so finding out which mapping entry changed requires
+/// deriving the slots of all candidate keys upfront. Entries can span several slots (e.g. structs),
+/// in which case lookups also return the position of the changed slot within the entry.
+///
+/// ## Example
+/// ```ignore
+/// // mapping(int24 => Tick.Info) ticks; declared at slot 5, each entry takes 4 slots
+/// let index = MappingSlotIndex::new(
+///     &slot_from_index(5),
+///     4,
+///     tick_indices.iter().map(|idx| (idx.clone(), MappingKey::int(idx))),
+/// );
+/// if let Some((tick_idx, member)) = index.get(&storage_change.key) {
+///     // ...
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MappingSlotIndex<K> {
+    entries: HashMap<[u8; 32], (K, u64)>,
+}
+
+impl<K> MappingSlotIndex<K> {
+    /// Builds the index for the candidate entries of a mapping.
+    ///
+    /// ## Parameters
+    /// - `base_slot`: The slot at which the mapping is declared.
+    /// - `slots_per_entry`: The number of slots each mapping value occupies.
+    /// - `candidates`: Pairs of a user defined identifier and the corresponding mapping key.
+    pub fn new<I: IntoIterator<Item = (K, MappingKey)>>(
+        base_slot: &[u8; 32],
+        slots_per_entry: u64,
+        candidates: I,
+    ) -> Self
+    where
+        K: Clone,
+    {
+        let mut entries = HashMap::new();
+        for (id, key) in candidates {
+            let entry_slot = mapping_key_slot(&key, base_slot);
+            for member in 0..slots_per_entry {
+                entries.insert(struct_member_slot(&entry_slot, member), (id.clone(), member));
+            }
+        }
+        Self { entries }
+    }
+
+    /// Returns the identifier of the entry the slot belongs to, and the position of the slot
+    /// within the entry.
+    pub fn get(&self, slot: &[u8]) -> Option<(&K, u64)> {
+        let slot: [u8; 32] = slot.try_into().ok()?;
+        self.entries
+            .get(&slot)
+            .map(|(id, member)| (id, *member))
+    }
+}
+
+/// Finds which of the candidate keys a changed slot belongs to in a mapping.
+///
+/// Convenience for one off lookups, prefer [`MappingSlotIndex`] when looking up many slots against
+/// the same candidates.
+pub fn find_mapping_key<'a, I: IntoIterator<Item = &'a MappingKey>>(
+    slot: &[u8],
+    base_slot: &[u8; 32],
+    candidates: I,
+) -> Option<&'a MappingKey> {
+    candidates
+        .into_iter()
+        .find(|key| mapping_key_slot(key, base_slot) == slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_slot(slot: &str) -> [u8; 32] {
+        hex::decode(slot)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_array_element_slot() {
+        // Elements of a dynamic array declared at slot 0 start at keccak256(0)
+        let start = decode_slot("290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563");
+        assert_eq!(array_element_slot(&slot_from_index(0), 0, 1), start);
+        assert_eq!(array_element_slot(&slot_from_index(0), 3, 2), add_to_slot(&start, 6));
+        assert_eq!(
+            array_element_slot(&slot_from_index(1), 0, 1),
+            decode_slot("b10e2d527612073b26eecdfd717e6a320cf44b4afac2b0732d9fcbe2b7fa0cf6")
+        );
+    }
+
+    #[test]
+    fn test_add_to_slot_carry() {
+        let slot = decode_slot("00000000000000000000000000000000ffffffffffffffffffffffffffffffff");
+        assert_eq!(
+            add_to_slot(&slot, 1),
+            decode_slot("0000000000000000000000000000000100000000000000000000000000000000")
+        );
+        assert_eq!(add_to_slot(&[0xff; 32], 1), [0u8; 32]);
+    }
+
+    #[test]
+    fn test_mapping_slot_weth_balance() {
+        // WETH `balanceOf` is declared at slot 3. Balance of the Uniswap V2 WETH pair
+        // 0x0f9e3401a5155a02c86353c3d9b24214876779dd, as seen in Ethereum block 23490768.
+        let pair = hex::decode("0f9e3401a5155a02c86353c3d9b24214876779dd").unwrap();
+        assert_eq!(
+            mapping_key_slot(&MappingKey::address(&pair), &slot_from_index(3)),
+            decode_slot("77f05379c72cc19907ba9648dcd0bda409fabc68ca111b532de62ffdb67e868f")
+        );
+    }
+
+    #[test]
+    fn test_nested_mapping_slot() {
+        // mapping(address => mapping(address => uint256)) allowance; declared at slot 4
+        let owner = MappingKey::address(&[0x11; 20]);
+        let spender = MappingKey::address(&[0x22; 20]);
+        let outer = mapping_key_slot(&owner, &slot_from_index(4));
+
+        assert_eq!(
+            nested_mapping_slot(&slot_from_index(4), &[owner, spender.clone()]),
+            mapping_key_slot(&spender, &outer)
+        );
+        assert_eq!(nested_mapping_slot(&slot_from_index(4), &[]), slot_from_index(4));
+    }
+
+    #[test]
+    fn test_mapping_key_encoding() {
+        assert_eq!(MappingKey::int(&BigInt::from(-1)), MappingKey::Word([0xff; 32]));
+        assert_eq!(MappingKey::int(&BigInt::from(256)), MappingKey::from(256));
+        assert_eq!(MappingKey::uint(&BigInt::from(256)), MappingKey::from(256));
+        assert_eq!(MappingKey::bool(true), MappingKey::from(1));
+
+        let mut word = [0u8; 32];
+        word[0] = 0xab;
+        assert_eq!(MappingKey::fixed_bytes(&[0xab]), MappingKey::Word(word));
+    }
+
+    #[test]
+    fn test_string_key_is_not_padded() {
+        let key = MappingKey::bytes(b"tycho");
+        let mut preimage = b"tycho".to_vec();
+        preimage.extend_from_slice(&slot_from_index(1));
+
+        assert_eq!(mapping_key_slot(&key, &slot_from_index(1)), keccak256(&preimage));
+    }
+
+    #[test]
+    fn test_mapping_slot_index() {
+        // Balances of the ERC20 0x11dfc652eb62c723ad8c2ae731fcede58ab07564 are declared at slot 2
+        let pair = hex::decode("0f9e3401a5155a02c86353c3d9b24214876779dd").unwrap();
+        let other = hex::decode("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap();
+        let index = MappingSlotIndex::new(
+            &slot_from_index(2),
+            2,
+            [("pair", MappingKey::address(&pair)), ("other", MappingKey::address(&other))],
+        );
+        let changed =
+            decode_slot("8f60e36f69a92730149f231ad2475b4aa8a8e50f4072f62a1f099ffc11d0f647");
+
+        assert_eq!(index.get(&changed), Some((&"pair", 0)));
+        assert_eq!(index.get(&add_to_slot(&changed, 1)), Some((&"pair", 1)));
+        assert_eq!(index.get(&add_to_slot(&changed, 2)), None);
+        assert_eq!(index.get(&changed[1..]), None);
+    }
+
+    #[test]
+    fn test_find_mapping_key() {
+        let candidates =
+            [MappingKey::int(&BigInt::from(-887220)), MappingKey::int(&BigInt::from(60))];
+        let slot = mapping_key_slot(&candidates[1], &slot_from_index(5));
+
+        assert_eq!(find_mapping_key(&slot, &slot_from_index(5), &candidates), Some(&candidates[1]));
+        assert_eq!(find_mapping_key(&slot, &slot_from_index(6), &candidates), None);
+    }
+}
@@ -0,0 +1,376 @@
+//! Helpers to decode contract storage into protocol attributes.
+//!
+//! Native integrations usually track a handful of values that live at well known storage
+//! locations of the protocol contracts, often packed together with other values in the same slot.
+//! This module allows describing where each value lives once, using [`StorageLocation`], and turns
+//! the raw `StorageChange`s of the extended block model into named `Attribute`s.
+//!
+//! ## Example
+//! ```ignore
+//! const SLOT0: [u8; 32] = [0u8; 32];
+//!
+//! const TRACKED: [StorageLocation; 2] = [
+//!     StorageLocation::new("sqrt_price_x96", SLOT0).packed(0, 20),
+//!     StorageLocation::new("tick", SLOT0)
+//!         .packed(20, 3)
+//!         .signed(),
+//! ];
+//!
+//! let attributes = get_changed_attributes(&call.storage_changes, &TRACKED);
+//! ```
+//!
+//! ## Warning
+//! ⚠️ Storage changes are *only* available on the **extended block model**,
+//! more [here](https://streamingfastio.medium.com/new-block-model-to-accelerate-chain-integration-9f65126e5425)
+use std::{borrow::Cow, collections::HashMap};
+
+use substreams::scalar::BigInt;
+use substreams_ethereum::pb::eth::v2::StorageChange;
+
+use crate::models::{Attribute, ChangeType};
+
+pub mod keys;
+
+use keys::MappingKey;
+
+/// Describes a value stored at a specific location within a contract's storage.
+///
+/// Values can occupy a full slot or be packed together with other values. Following the Solidity
+/// layout, packed values are aligned to the right: `offset` is counted in bytes starting from the
+/// least significant (rightmost) byte of the slot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageLocation<'a> {
+    /// The attribute name the value is emitted under.
+    pub name: Cow<'a, str>,
+    /// The slot in the contract storage where the value is stored.
+    pub slot: [u8; 32],
+    /// The offset in bytes from the least significant byte of the slot.
+    pub offset: usize,
+    /// The size of the value in bytes.
+    pub number_of_bytes: usize,
+    /// Whether the value is a two's complement signed integer.
+    pub signed: bool,
+    /// Whether the value becoming zero means it no longer exists.
+    ///
+    /// If set, a transition from zero to a non-zero value is emitted as a `Creation` and a
+    /// transition to zero as a `Deletion`. This is useful for mapping entries that are cleared
+    /// once unused (e.g. initialized ticks). Otherwise, all changes are emitted as `Update`s.
+    pub deletable: bool,
+}
+
+impl<'a> StorageLocation<'a> {
+    /// Creates a location for an unsigned value occupying a full slot.
+    pub const fn new(name: &'a str, slot: [u8; 32]) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            slot,
+            offset: 0,
+            number_of_bytes: 32,
+            signed: false,
+            deletable: false,
+        }
+    }
+
+    /// Creates a location for the value of a mapping entry.
+    ///
+    /// ## Parameters
+    /// - `name`: The attribute name, usually including the mapping key.
+    /// - `base_slot`: The slot at which the mapping is declared.
+    /// - `key`: The mapping key.
+    pub fn mapping_entry(
+        name: impl Into<Cow<'a, str>>,
+        base_slot: &[u8; 32],
+        key: &MappingKey,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            slot: keys::mapping_key_slot(key, base_slot),
+            offset: 0,
+            number_of_bytes: 32,
+            signed: false,
+            deletable: false,
+        }
+    }
+
+    /// Creates a location for an element of a dynamic array.
+    ///
+    /// ## Parameters
+    /// - `name`: The attribute name, usually including the array index.
+    /// - `base_slot`: The slot at which the array is declared.
+    /// - `index`: The index of the element.
+    /// - `slots_per_element`: The number of slots each element occupies.
+    pub fn array_element(
+        name: impl Into<Cow<'a, str>>,
+        base_slot: &[u8; 32],
+        index: u64,
+        slots_per_element: u64,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            slot: keys::array_element_slot(base_slot, index, slots_per_element),
+            offset: 0,
+            number_of_bytes: 32,
+            signed: false,
+            deletable: false,
+        }
+    }
+
+    /// Restricts the location to a value packed within the slot.
+    pub const fn packed(mut self, offset: usize, number_of_bytes: usize) -> Self {
+        self.offset = offset;
+        self.number_of_bytes = number_of_bytes;
+        self
+    }
+
+    /// Marks the value as a signed integer.
+    pub const fn signed(mut self) -> Self {
+        self.signed = true;
+        self
+    }
+
+    /// Marks the value as deletable, see [`StorageLocation::deletable`].
+    pub const fn deletable(mut self) -> Self {
+        self.deletable = true;
+        self
+    }
+
+    /// Extracts the raw bytes of this value from a slot value.
+    ///
+    /// ## Panics
+    /// Panics if the value does not fit within the slot value.
+    pub fn read<'b>(&self, slot_value: &'b [u8]) -> &'b [u8] {
+        read_bytes(slot_value, self.offset, self.number_of_bytes)
+    }
+
+    /// Decodes this value from a slot value as an integer.
+    ///
+    /// ## Panics
+    /// Panics if the value does not fit within the slot value.
+    pub fn decode(&self, slot_value: &[u8]) -> BigInt {
+        let data = self.read(slot_value);
+        if self.signed {
+            BigInt::from_signed_bytes_be(data)
+        } else {
+            BigInt::from_unsigned_bytes_be(data)
+        }
+    }
+}
+
+/// Reads `number_of_bytes` bytes from a buffer, `offset` bytes away from its end.
+///
+/// ## Panics
+/// Panics if the requested bytes exceed the buffer.
+pub fn read_bytes(buf: &[u8], offset: usize, number_of_bytes: usize) -> &[u8] {
+    let end = buf
+        .len()
+        .checked_sub(offset)
+        .unwrap_or_else(|| panic!("Offset {offset} exceeds buffer size {}", buf.len()));
+    let start = end
+        .checked_sub(number_of_bytes)
+        .unwrap_or_else(|| {
+            panic!(
+                "Reading {number_of_bytes} bytes at offset {offset} exceeds buffer size {}",
+                buf.len()
+            )
+        });
+    &buf[start..end]
+}
+
+/// Extracts the attributes changed by a set of storage changes.
+///
+/// Multiple changes to the same slot are collapsed, comparing the value before the first change
+/// with the value after the last change (by ordinal). An attribute is emitted for each location
+/// whose value differs, with the new value encoded as a big endian signed integer.
+///
+/// ## Arguments
+/// * `storage_changes` - The storage changes of a single contract.
+/// * `locations` - The storage locations to check for changes.
+///
+/// ## Returns
+/// The changed attributes, in the order of `locations`.
+///
+/// ## Panics
+/// Panics if a location does not fit within the changed slot values.
+pub fn get_changed_attributes(
+    storage_changes: &[StorageChange],
+    locations: &[StorageLocation],
+) -> Vec<Attribute> {
+    let mut changes: Vec<&StorageChange> = storage_changes.iter().collect();
+    changes.sort_by_key(|change| change.ordinal);
+
+    // Collect the initial and final value per slot
+    let mut slot_values: HashMap<&[u8], (&[u8], &[u8])> = HashMap::new();
+    for change in changes {
+        slot_values
+            .entry(change.key.as_slice())
+            .and_modify(|(_, new_value)| *new_value = change.new_value.as_slice())
+            .or_insert((change.old_value.as_slice(), change.new_value.as_slice()));
+    }
+
+    locations
+        .iter()
+        .filter_map(|location| {
+            let (old_value, new_value) = slot_values.get(location.slot.as_slice())?;
+            let old_data = location.read(old_value);
+            let new_data = location.read(new_value);
+            if old_data == new_data {
+                return None;
+            }
+
+            let change = if !location.deletable {
+                ChangeType::Update
+            } else if is_zero(old_data) {
+                ChangeType::Creation
+            } else if is_zero(new_data) {
+                ChangeType::Deletion
+            } else {
+                ChangeType::Update
+            };
+
+            Some(Attribute {
+                name: location.name.to_string(),
+                value: location
+                    .decode(new_value)
+                    .to_signed_bytes_be(),
+                change: change.into(),
+            })
+        })
+        .collect()
+}
+
+fn is_zero(data: &[u8]) -> bool {
+    data.iter().all(|b| *b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(n: u8) -> [u8; 32] {
+        let mut slot = [0u8; 32];
+        slot[31] = n;
+        slot
+    }
+
+    fn storage_change(
+        key: [u8; 32],
+        old_value: &str,
+        new_value: &str,
+        ordinal: u64,
+    ) -> StorageChange {
+        StorageChange {
+            address: vec![1; 20],
+            key: key.to_vec(),
+            old_value: hex::decode(old_value).unwrap(),
+            new_value: hex::decode(new_value).unwrap(),
+            ordinal,
+        }
+    }
+
+    #[test]
+    fn test_read_bytes() {
+        let buf = hex::decode("aabbccdd").unwrap();
+        assert_eq!(read_bytes(&buf, 0, 1), [0xdd]);
+        assert_eq!(read_bytes(&buf, 1, 2), [0xbb, 0xcc]);
+        assert_eq!(read_bytes(&buf, 0, 4), [0xaa, 0xbb, 0xcc, 0xdd]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_read_bytes_overflow() {
+        let buf = hex::decode("aabb").unwrap();
+        read_bytes(&buf, 1, 2);
+    }
+
+    #[test]
+    fn test_decode_packed_values() {
+        // Uniswap V3 slot0: tick (int24) packed after sqrtPriceX96 (uint160)
+        let value = hex::decode("000100000100010000fce8e800000000000000000000000000000000000000a2")
+            .unwrap();
+        let sqrt_price = StorageLocation::new("sqrt_price_x96", slot(0)).packed(0, 20);
+        let tick = StorageLocation::new("tick", slot(0))
+            .packed(20, 3)
+            .signed();
+
+        assert_eq!(sqrt_price.decode(&value), BigInt::from(0xa2));
+        assert_eq!(tick.decode(&value), BigInt::from(-202520));
+    }
+
+    #[test]
+    fn test_get_changed_attributes() {
+        let locations = [
+            StorageLocation::new("low", slot(0)).packed(0, 16),
+            StorageLocation::new("high", slot(0)).packed(16, 16),
+            StorageLocation::new("untouched", slot(1)),
+        ];
+        let changes = [storage_change(
+            slot(0),
+            "0000000000000000000000000000000100000000000000000000000000000002",
+            "0000000000000000000000000000000100000000000000000000000000000003",
+            0,
+        )];
+
+        let attributes = get_changed_attributes(&changes, &locations);
+
+        assert_eq!(
+            attributes,
+            vec![Attribute {
+                name: "low".to_string(),
+                value: vec![3],
+                change: ChangeType::Update.into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_get_changed_attributes_collapses_changes() {
+        let locations = [StorageLocation::new("value", slot(0))];
+        let changes = [
+            storage_change(
+                slot(0),
+                "0000000000000000000000000000000000000000000000000000000000000005",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                2,
+            ),
+            storage_change(
+                slot(0),
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000005",
+                1,
+            ),
+        ];
+
+        // Value is back to its initial value, nothing changed
+        assert!(get_changed_attributes(&changes, &locations).is_empty());
+    }
+
+    #[test]
+    fn test_get_changed_attributes_deletable() {
+        let zero = "0000000000000000000000000000000000000000000000000000000000000000";
+        let one = "0000000000000000000000000000000000000000000000000000000000000001";
+        let two = "0000000000000000000000000000000000000000000000000000000000000002";
+        let location =
+            StorageLocation::mapping_entry("ticks/1", &slot(5), &MappingKey::from(1)).deletable();
+        let key = location.slot;
+
+        let change_types = [(zero, one), (one, two), (two, zero)]
+            .into_iter()
+            .map(|(old, new)| {
+                let attributes = get_changed_attributes(
+                    &[storage_change(key, old, new, 0)],
+                    std::slice::from_ref(&location),
+                );
+                attributes[0].change
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            change_types,
+            vec![
+                i32::from(ChangeType::Creation),
+                i32::from(ChangeType::Update),
+                i32::from(ChangeType::Deletion)
+            ]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Typed store keys.
+//!
+//! Store keys are strings made of segments separated by `:`, optionally starting with a constant
+//! prefix, e.g. `Pool:0x...` or `component_id:token`. This is the layout expected by
+//! `substreams::key::segment_at` and friends. Instead of building and parsing such keys with
+//! `format!` and `split`, declare them with [`store_key!`](crate::store_key!) and use
+//! [`StoreKey::encode`] and [`StoreKey::decode`]:
+//!
+//! ```ignore
+//! store_key! {
+//!     /// Pools created by the factory.
+//!     pub struct PoolKey("Pool") {
+//!         pub address: Vec<u8>,
+//!     }
+//! }
+//!
+//! store.set(ord, PoolKey { address: pool.clone() }.encode(), &component);
+//! let pool = PoolKey::decode(&delta.key)?.address;
+//! ```
+//!
+//! Segments are encoded with [`KeySegment`]: byte arrays as lowercase hex without `0x`, integers
+//! in decimal and strings as is. Encoding fails if a segment is empty or contains the separator,
+//! so keys always decode back to the same value.
+use std::str::FromStr;
+
+use substreams::scalar::BigInt;
+
+/// Separator between the segments of a store key.
+pub const SEPARATOR: char = ':';
+
+/// Typed encoding and decoding of a single store key segment.
+pub trait KeySegment: Sized {
+    /// Encodes the value into its key segment representation.
+    fn encode_segment(&self) -> String;
+
+    /// Decodes a value from its key segment representation.
+    fn decode_segment(segment: &str) -> Result<Self, String>;
+}
+
+impl KeySegment for String {
+    fn encode_segment(&self) -> String {
+        self.clone()
+    }
+
+    fn decode_segment(segment: &str) -> Result<Self, String> {
+        Ok(segment.to_string())
+    }
+}
+
+impl KeySegment for Vec<u8> {
+    fn encode_segment(&self) -> String {
+        hex::encode(self)
+    }
+
+    fn decode_segment(segment: &str) -> Result<Self, String> {
+        let segment = segment
+            .strip_prefix("0x")
+            .unwrap_or(segment);
+        hex::decode(segment).map_err(|_| format!("{segment} is not valid hex"))
+    }
+}
+
+impl<const N: usize> KeySegment for [u8; N] {
+    fn encode_segment(&self) -> String {
+        hex::encode(self)
+    }
+
+    fn decode_segment(segment: &str) -> Result<Self, String> {
+        let bytes = Vec::<u8>::decode_segment(segment)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| format!("expected {N} bytes, got {len}"))
+    }
+}
+
+impl KeySegment for BigInt {
+    fn encode_segment(&self) -> String {
+        self.to_string()
+    }
+
+    fn decode_segment(segment: &str) -> Result<Self, String> {
+        BigInt::from_str(segment).map_err(|_| format!("{segment} is not a valid integer"))
+    }
+}
+
+macro_rules! impl_integer_segment {
+    ($($t:ty),*) => {
+        $(
+            impl KeySegment for $t {
+                fn encode_segment(&self) -> String {
+                    self.to_string()
+                }
+
+                fn decode_segment(segment: &str) -> Result<Self, String> {
+                    segment
+                        .parse()
+                        .map_err(|_| format!("{segment} is not a valid {}", stringify!($t)))
+                }
+            }
+        )*
+    };
+}
+
+impl_integer_segment!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+/// A store key made of typed segments, see the [module documentation](self).
+///
+/// Implement it with the [`store_key!`](crate::store_key!) macro rather than by hand.
+pub trait StoreKey: Sized {
+    /// Constant first segment of the keys, if any.
+    const PREFIX: Option<&'static str>;
+
+    /// Returns the encoded segments of the key, without the prefix.
+    fn segments(&self) -> Vec<String>;
+
+    /// Builds the key from its encoded segments, without the prefix.
+    fn from_segments(segments: &[&str]) -> Result<Self, String>;
+
+    /// Encodes the key.
+    ///
+    /// ## Errors
+    /// Returns an error if a segment is empty or contains the separator.
+    fn try_encode(&self) -> Result<String, String> {
+        let segments = Self::PREFIX
+            .map(str::to_string)
+            .into_iter()
+            .chain(self.segments())
+            .collect::<Vec<_>>();
+        if let Some(segment) = segments
+            .iter()
+            .find(|segment| segment.is_empty() || segment.contains(SEPARATOR))
+        {
+            return Err(format!("invalid key segment '{segment}'"));
+        }
+        Ok(segments.join(&SEPARATOR.to_string()))
+    }
+
+    /// Encodes the key.
+    ///
+    /// ## Panics
+    /// Panics if a segment is empty or contains the separator, see `try_encode`.
+    fn encode(&self) -> String {
+        self.try_encode()
+            .unwrap_or_else(|e| panic!("Failed to encode store key: {e}"))
+    }
+
+    /// Decodes a key.
+    ///
+    /// ## Errors
+    /// Returns an error if the key doesn't start with the prefix, has the wrong number of
+    /// segments, or a segment can't be decoded.
+    fn decode(key: &str) -> Result<Self, String> {
+        let mut segments = key.split(SEPARATOR).collect::<Vec<_>>();
+        if let Some(prefix) = Self::PREFIX {
+            if segments.first() != Some(&prefix) {
+                return Err(format!("key {key} doesn't start with {prefix}"));
+            }
+            segments.remove(0);
+        }
+        if segments
+            .iter()
+            .any(|segment| segment.is_empty())
+        {
+            return Err(format!("key {key} has an empty segment"));
+        }
+        Self::from_segments(&segments).map_err(|e| format!("invalid key {key}: {e}"))
+    }
+
+    /// Returns whether the key starts with the prefix of this key type.
+    ///
+    /// Useful to tell keys apart in stores holding several key types. Always true for keys
+    /// without prefix.
+    fn matches(key: &str) -> bool {
+        match Self::PREFIX {
+            Some(prefix) => key.split(SEPARATOR).next() == Some(prefix),
+            None => true,
+        }
+    }
+
+    /// Decodes a single segment of a key, skipping the prefix.
+    ///
+    /// This is a typed version of `substreams::key::segment_at`, e.g. to read one field of a key
+    /// without decoding all of them.
+    ///
+    /// ## Errors
+    /// Returns an error if the segment is missing or can't be decoded.
+    fn segment_at<T: KeySegment>(key: &str, index: usize) -> Result<T, String> {
+        let index = index + usize::from(Self::PREFIX.is_some());
+        let segment = key
+            .split(SEPARATOR)
+            .nth(index)
+            .ok_or_else(|| format!("key {key} has no segment {index}"))?;
+        T::decode_segment(segment)
+    }
+}
+
+/// Declares a struct implementing [`StoreKey`], with one key segment per field.
+///
+/// Fields must implement [`KeySegment`] and are encoded in declaration order. An optional prefix
+/// can be given after the struct name.
+///
+/// ```ignore
+/// store_key! {
+///     /// Balance of a token held by a component.
+///     #[derive(Clone, Debug, PartialEq)]
+///     pub struct TokenBalanceKey("Balance") {
+///         pub component_id: String,
+///         pub token: Vec<u8>,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! store_key {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident $(($prefix:literal))? {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::store_key::StoreKey for $name {
+            const PREFIX: Option<&'static str> = $crate::store_key!(@prefix $($prefix)?);
+
+            fn segments(&self) -> Vec<String> {
+                vec![$($crate::store_key::KeySegment::encode_segment(&self.$field)),*]
+            }
+
+            fn from_segments(segments: &[&str]) -> Result<Self, String> {
+                let fields: &[&str] = &[$(stringify!($field)),*];
+                if segments.len() != fields.len() {
+                    return Err(format!(
+                        "expected {} segments, got {}",
+                        fields.len(),
+                        segments.len()
+                    ));
+                }
+                let mut segments = segments.iter();
+                Ok(Self {
+                    $($field: segments
+                        .next()
+                        .map(|segment| $crate::store_key::KeySegment::decode_segment(segment))
+                        .expect("segment count was checked")
+                        .map_err(|e| format!("invalid {}: {e}", stringify!($field)))?),*
+                })
+            }
+        }
+    };
+    (@prefix) => { None };
+    (@prefix $prefix:literal) => { Some($prefix) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use substreams::key;
+
+    store_key! {
+        #[derive(Debug, PartialEq)]
+        struct PoolKey("Pool") {
+            address: [u8; 20],
+            fee: u32,
+        }
+    }
+
+    store_key! {
+        #[derive(Debug, PartialEq)]
+        struct LiquidityKey {
+            pool: String,
+            tick: i32,
+            liquidity: BigInt,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let key = PoolKey { address: [0xab; 20], fee: 3000 };
+
+        let encoded = key.encode();
+
+        assert_eq!(encoded, format!("Pool:{}:3000", "ab".repeat(20)));
+        assert_eq!(PoolKey::decode(&encoded), Ok(key));
+        assert_eq!(PoolKey::segment_at::<u32>(&encoded, 1), Ok(3000));
+        assert_eq!(key::segment_at(&encoded, 2), "3000");
+        assert!(PoolKey::matches(&encoded));
+        assert!(!PoolKey::matches("Token:ab"));
+    }
+
+    #[test]
+    fn test_encode_decode_without_prefix() {
+        let key = LiquidityKey {
+            pool: "0xc0ffee".to_string(),
+            tick: -887272,
+            liquidity: BigInt::from(-5),
+        };
+
+        let encoded = key.encode();
+
+        assert_eq!(encoded, "0xc0ffee:-887272:-5");
+        assert_eq!(LiquidityKey::decode(&encoded), Ok(key));
+        assert_eq!(LiquidityKey::segment_at::<String>(&encoded, 0), Ok("0xc0ffee".to_string()));
+        assert_eq!(
+            LiquidityKey::decode("0xc0ffee:x:1"),
+            Err("invalid key 0xc0ffee:x:1: invalid tick: x is not a valid i32".to_string())
+        );
+    }
+
+    #[test]
+    fn test_encode_invalid_segment() {
+        let key = LiquidityKey { pool: "a:b".to_string(), tick: 0, liquidity: BigInt::zero() };
+
+        assert_eq!(key.try_encode(), Err("invalid key segment 'a:b'".to_string()));
+    }
+
+    #[rstest]
+    #[case::wrong_prefix("Token:abab:1", "key Token:abab:1 doesn't start with Pool")]
+    #[case::missing_segment("Pool:abab", "invalid key Pool:abab: expected 2 segments, got 1")]
+    #[case::empty_segment("Pool::1", "key Pool::1 has an empty segment")]
+    #[case::invalid_address(
+        "Pool:abab:1",
+        "invalid key Pool:abab:1: invalid address: expected 20 bytes, got 2"
+    )]
+    fn test_decode_invalid(#[case] key: &str, #[case] expected: &str) {
+        assert_eq!(PoolKey::decode(key), Err(expected.to_string()));
+    }
+
+    #[rstest]
+    #[case::prefixed("Pool:abab:1", true)]
+    #[case::prefix_only("Pool", true)]
+    #[case::other_prefix("Token:abab", false)]
+    #[case::prefix_substring("Pools:abab", false)]
+    #[case::empty("", false)]
+    fn test_matches(#[case] key: &str, #[case] expected: bool) {
+        assert_eq!(PoolKey::matches(key), expected);
+        assert!(LiquidityKey::matches(key));
+    }
+
+    #[test]
+    fn test_segment_at() {
+        let encoded = format!("Pool:{}:3000", "ab".repeat(20));
+
+        assert_eq!(PoolKey::segment_at::<[u8; 20]>(&encoded, 0), Ok([0xab; 20]));
+        assert_eq!(PoolKey::segment_at::<u32>(&encoded, 1), Ok(3000));
+        assert_eq!(
+            PoolKey::segment_at::<u32>(&encoded, 2),
+            Err(format!("key {encoded} has no segment 3"))
+        );
+        assert_eq!(LiquidityKey::segment_at::<i32>("0xc0ffee:-887272:-5", 1), Ok(-887272));
+        assert!(LiquidityKey::segment_at::<i32>("0xc0ffee:x:-5", 1).is_err());
+    }
+}
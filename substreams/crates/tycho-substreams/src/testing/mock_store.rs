@@ -1,95 +1,455 @@
-//! Contains a mock store for internal testing.
+//! Contains an in-memory mock store to unit test store handlers and modules consuming stores.
 //!
-//! Might make this public alter to users can test their store handlers.
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+//! `MockStore<V>` mirrors the semantics of the substreams stores for values of type `V`: it
+//! implements the writer traits (`StoreSet`, `StoreSetIfNotExists`, `StoreAdd`, `Appender`,
+//! `StoreDelete`) as well as `StoreGet`, so it can replace `StoreSetProto`, `StoreGetProto`,
+//! `StoreGetString`, `StoreAddBigInt`, `StoreAppend`, etc. in handlers written against the store
+//! traits. Clones share the same underlying data, so a clone can be handed to a handler as writer
+//! while the test reads from the original one.
+//!
+//! Every write is recorded together with its ordinal. Reads at an ordinal observe all writes
+//! with a lower or equal ordinal, and `deltas` returns the changes as a module consuming the store
+//! in deltas mode would receive them. `commit` ends a block: the writes become the store's
+//! state at the start of the next block and are no longer reported as deltas.
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 use substreams::{
-    prelude::{BigInt, StoreDelete, StoreGet, StoreNew},
-    store::StoreAdd,
+    pb::substreams::{store_delta::Operation, StoreDelta, StoreDeltas},
+    scalar::{BigDecimal, BigInt},
+    store::{Appender, StoreAdd, StoreDelete, StoreGet, StoreNew, StoreSet, StoreSetIfNotExists},
 };
 
-type BigIntStore = HashMap<String, Vec<(u64, BigInt)>>;
+/// Separator the substreams runtime appends to each item of an append store.
+const APPEND_SEPARATOR: char = ';';
+
+/// History of a key: its values ordered by ordinal, `None` marks a deletion.
+type History<V> = Vec<(u64, Option<V>)>;
 
 #[derive(Debug, Clone)]
-pub struct MockStore {
-    data: Rc<RefCell<BigIntStore>>,
+pub struct MockStore<V = BigInt> {
+    /// State at the start of the current block.
+    base: Rc<RefCell<HashMap<String, V>>>,
+    /// Writes made during the current block.
+    data: Rc<RefCell<HashMap<String, History<V>>>>,
 }
 
-impl StoreDelete for MockStore {
-    fn delete_prefix(&self, _ord: i64, prefix: &String) {
-        self.data
-            .borrow_mut()
-            .retain(|k, _| !k.starts_with(prefix));
+/// Encodes values the way the substreams runtime stores them, used to build `StoreDeltas`.
+///
+/// Protobuf messages are encoded by `MockStore::proto_deltas` instead.
+pub trait MockStoreValue {
+    fn encode_store_value(&self) -> Vec<u8>;
+}
+
+impl MockStoreValue for BigInt {
+    fn encode_store_value(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
     }
 }
 
-impl StoreNew for MockStore {
-    fn new() -> Self {
-        Self { data: Rc::new(RefCell::new(HashMap::new())) }
+impl MockStoreValue for BigDecimal {
+    fn encode_store_value(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
     }
 }
 
-impl StoreAdd<BigInt> for MockStore {
-    fn add<K: AsRef<str>>(&self, ord: u64, key: K, value: BigInt) {
-        let mut guard = self.data.borrow_mut();
-        guard
-            .entry(key.as_ref().to_string())
-            .and_modify(|v| {
-                let prev_value = v.last().unwrap().1.clone();
-                v.push((ord, prev_value + value.clone()));
-            })
-            .or_insert(vec![(ord, value)]);
+impl MockStoreValue for i64 {
+    fn encode_store_value(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
     }
+}
 
-    fn add_many<K: AsRef<str>>(&self, _ord: u64, _keys: &Vec<K>, _value: BigInt) {
-        todo!()
+impl MockStoreValue for String {
+    fn encode_store_value(&self) -> Vec<u8> {
+        self.clone().into_bytes()
     }
 }
 
-impl StoreGet<BigInt> for MockStore {
-    fn new(_idx: u32) -> Self {
-        Self { data: Rc::new(RefCell::new(HashMap::new())) }
+/// Appended items, each followed by `;`.
+impl MockStoreValue for Vec<String> {
+    fn encode_store_value(&self) -> Vec<u8> {
+        self.iter()
+            .flat_map(|item| format!("{item}{APPEND_SEPARATOR}").into_bytes())
+            .collect()
+    }
+}
+
+impl MockStoreValue for Vec<u8> {
+    fn encode_store_value(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl<V: Clone> MockStore<V> {
+    fn empty() -> Self {
+        Self {
+            base: Rc::new(RefCell::new(HashMap::new())),
+            data: Rc::new(RefCell::new(HashMap::new())),
+        }
     }
 
-    fn get_at<K: AsRef<str>>(&self, ord: u64, key: K) -> Option<BigInt> {
+    /// Ends the current block: its writes become the state the next block starts from, and are
+    /// no longer returned by `deltas`.
+    pub fn commit(&self) {
+        let mut base = self.base.borrow_mut();
+        for (key, history) in self.data.borrow_mut().drain() {
+            match history.into_iter().last() {
+                Some((_, Some(value))) => {
+                    base.insert(key, value);
+                }
+                Some((_, None)) => {
+                    base.remove(&key);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Records a write of `key` at ordinal `ord`, after any write with the same ordinal.
+    fn write(&self, ord: u64, key: &str, value: Option<V>) {
+        let mut data = self.data.borrow_mut();
+        let history = data.entry(key.to_string()).or_default();
+        let idx = history.partition_point(|(current_ord, _)| *current_ord <= ord);
+        history.insert(idx, (ord, value));
+    }
+
+    /// Returns the value of `key` after all writes with an ordinal lower or equal to `ord`.
+    fn value_at(&self, ord: u64, key: &str) -> Option<V> {
         self.data
             .borrow()
-            .get(&key.as_ref().to_string())
-            .map(|v| {
-                v.iter()
-                    .find(|(current_ord, _)| *current_ord == ord)
-                    .unwrap()
-                    .1
-                    .clone()
+            .get(key)
+            .and_then(|history| {
+                history
+                    .iter()
+                    .take_while(|(current_ord, _)| *current_ord <= ord)
+                    .last()
+                    .map(|(_, value)| value.clone())
             })
+            .unwrap_or_else(|| self.base.borrow().get(key).cloned())
     }
 
-    fn get_last<K: AsRef<str>>(&self, key: K) -> Option<BigInt> {
-        self.data
+    fn last_value(&self, key: &str) -> Option<V> {
+        self.value_at(u64::MAX, key)
+    }
+
+    /// Returns the value of `key` at the start of the block, or its first value written in the
+    /// block if it didn't exist before.
+    fn first_value(&self, key: &str) -> Option<V> {
+        self.base
             .borrow()
-            .get(&key.as_ref().to_string())
-            .map(|v| v.last().unwrap().1.clone())
+            .get(key)
+            .cloned()
+            .or_else(|| {
+                self.data
+                    .borrow()
+                    .get(key)
+                    .and_then(|history| history.first())
+                    .and_then(|(_, value)| value.clone())
+            })
     }
 
-    fn get_first<K: AsRef<str>>(&self, key: K) -> Option<BigInt> {
-        self.data
+    /// Returns the changes made to the store, as they would be received by a module consuming
+    /// the store in deltas mode, encoding values with `encode`.
+    ///
+    /// Deltas are sorted by ordinal, then by key.
+    pub fn deltas_with<F: Fn(&V) -> Vec<u8>>(&self, encode: F) -> StoreDeltas {
+        let base = self.base.borrow();
+        let data = self.data.borrow();
+        let mut deltas = data
+            .iter()
+            .flat_map(|(key, history)| {
+                let mut previous: Option<&V> = base.get(key);
+                history
+                    .iter()
+                    .filter_map(|(ord, value)| {
+                        let operation = match (previous, value) {
+                            (None, None) => return None,
+                            (None, Some(_)) => Operation::Create,
+                            (Some(_), Some(_)) => Operation::Update,
+                            (Some(_), None) => Operation::Delete,
+                        };
+                        let delta = StoreDelta {
+                            operation: operation.into(),
+                            ordinal: *ord,
+                            key: key.clone(),
+                            old_value: previous
+                                .map(&encode)
+                                .unwrap_or_default(),
+                            new_value: value
+                                .as_ref()
+                                .map(&encode)
+                                .unwrap_or_default(),
+                        };
+                        previous = value.as_ref();
+                        Some(delta)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        deltas.sort_by(|a, b| (a.ordinal, &a.key).cmp(&(b.ordinal, &b.key)));
+        StoreDeltas { deltas }
+    }
+}
+
+impl<V: Clone + MockStoreValue> MockStore<V> {
+    /// Returns the changes made to the store, as they would be received by a module consuming
+    /// the store in deltas mode.
+    ///
+    /// Deltas are sorted by ordinal, then by key.
+    pub fn deltas(&self) -> StoreDeltas {
+        self.deltas_with(MockStoreValue::encode_store_value)
+    }
+}
+
+impl<V: Clone + prost::Message> MockStore<V> {
+    /// Returns the changes made to a store of protobuf messages, as they would be received by a
+    /// module consuming the store in deltas mode.
+    ///
+    /// Deltas are sorted by ordinal, then by key.
+    pub fn proto_deltas(&self) -> StoreDeltas {
+        self.deltas_with(prost::Message::encode_to_vec)
+    }
+}
+
+impl<V: Clone> StoreDelete for MockStore<V> {
+    fn delete_prefix(&self, ord: i64, prefix: &String) {
+        let keys = self
+            .base
             .borrow()
-            .get(&key.as_ref().to_string())
-            .map(|v| v.first().unwrap().1.clone())
+            .keys()
+            .chain(self.data.borrow().keys())
+            .filter(|key| key.starts_with(prefix.as_str()))
+            .cloned()
+            .collect::<HashSet<_>>();
+        for key in keys {
+            if self.last_value(&key).is_some() {
+                self.write(ord as u64, &key, None);
+            }
+        }
+    }
+}
+
+impl<V: Clone> StoreNew for MockStore<V> {
+    fn new() -> Self {
+        Self::empty()
+    }
+}
+
+impl<V: Clone> StoreSet<V> for MockStore<V> {
+    fn set<K: AsRef<str>>(&self, ord: u64, key: K, value: &V) {
+        self.write(ord, key.as_ref(), Some(value.clone()));
+    }
+
+    fn set_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: &V) {
+        for key in keys {
+            self.set(ord, key, value);
+        }
+    }
+}
+
+impl<V: Clone> StoreSetIfNotExists<V> for MockStore<V> {
+    fn set_if_not_exists<K: AsRef<str>>(&self, ord: u64, key: K, value: &V) {
+        if self.last_value(key.as_ref()).is_none() {
+            self.write(ord, key.as_ref(), Some(value.clone()));
+        }
+    }
+
+    fn set_if_not_exists_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: &V) {
+        for key in keys {
+            self.set_if_not_exists(ord, key, value);
+        }
+    }
+}
+
+impl StoreAdd<BigInt> for MockStore<BigInt> {
+    fn add<K: AsRef<str>>(&self, ord: u64, key: K, value: BigInt) {
+        let previous = self
+            .value_at(ord, key.as_ref())
+            .unwrap_or_else(BigInt::zero);
+        self.write(ord, key.as_ref(), Some(previous + value));
+    }
+
+    fn add_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: BigInt) {
+        for key in keys {
+            self.add(ord, key, value.clone());
+        }
+    }
+}
+
+impl StoreAdd<i64> for MockStore<i64> {
+    fn add<K: AsRef<str>>(&self, ord: u64, key: K, value: i64) {
+        let previous = self
+            .value_at(ord, key.as_ref())
+            .unwrap_or_default();
+        self.write(ord, key.as_ref(), Some(previous + value));
+    }
+
+    fn add_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: i64) {
+        for key in keys {
+            self.add(ord, key, value);
+        }
+    }
+}
+
+/// Mirrors `StoreAppend`: items are appended to the list stored under the key.
+///
+/// Read the items back through `StoreGet<Vec<String>>`, like `StoreGetArray<String>` does.
+impl<T: Into<String>> Appender<T> for MockStore<Vec<String>> {
+    fn new() -> Self {
+        Self::empty()
+    }
+
+    fn append<K: AsRef<str>>(&self, ord: u64, key: K, item: T) {
+        self.append_all(ord, key, vec![item]);
+    }
+
+    fn append_all<K: AsRef<str>>(&self, ord: u64, key: K, items: Vec<T>) {
+        let mut value = self
+            .value_at(ord, key.as_ref())
+            .unwrap_or_default();
+        value.extend(items.into_iter().map(Into::into));
+        self.write(ord, key.as_ref(), Some(value));
+    }
+}
+
+impl<V: Clone> StoreGet<V> for MockStore<V> {
+    fn new(_idx: u32) -> Self {
+        Self::empty()
+    }
+
+    fn get_at<K: AsRef<str>>(&self, ord: u64, key: K) -> Option<V> {
+        self.value_at(ord, key.as_ref())
+    }
+
+    fn get_last<K: AsRef<str>>(&self, key: K) -> Option<V> {
+        self.last_value(key.as_ref())
+    }
+
+    fn get_first<K: AsRef<str>>(&self, key: K) -> Option<V> {
+        self.first_value(key.as_ref())
     }
 
     fn has_at<K: AsRef<str>>(&self, ord: u64, key: K) -> bool {
-        self.data
-            .borrow()
-            .get(&key.as_ref().to_string())
-            .map(|v| v.iter().any(|(v, _)| *v == ord))
-            .unwrap_or(false)
+        self.get_at(ord, key).is_some()
     }
 
-    fn has_last<K: AsRef<str>>(&self, _key: K) -> bool {
-        todo!()
+    fn has_last<K: AsRef<str>>(&self, key: K) -> bool {
+        self.get_last(key).is_some()
     }
 
-    fn has_first<K: AsRef<str>>(&self, _key: K) -> bool {
-        todo!()
+    fn has_first<K: AsRef<str>>(&self, key: K) -> bool {
+        self.get_first(key).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ProtocolComponent;
+
+    fn component(id: &str) -> ProtocolComponent {
+        ProtocolComponent { id: id.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_set_ordinal_semantics() {
+        let store = <MockStore<String> as StoreNew>::new();
+        store.set(10, "key", &"b".to_string());
+        store.set(5, "key", &"a".to_string());
+
+        assert_eq!(store.get_at(4, "key"), None);
+        assert_eq!(store.get_at(5, "key"), Some("a".to_string()));
+        assert_eq!(store.get_at(9, "key"), Some("a".to_string()));
+        assert_eq!(store.get_at(10, "key"), Some("b".to_string()));
+        assert_eq!(store.get_first("key"), Some("a".to_string()));
+        assert_eq!(store.get_last("key"), Some("b".to_string()));
+        assert!(!store.has_at(4, "key"));
+        assert!(store.has_first("key"));
+        assert!(store.has_last("key"));
+        assert!(!store.has_last("other"));
+    }
+
+    #[test]
+    fn test_set_if_not_exists() {
+        let store = <MockStore<ProtocolComponent> as StoreNew>::new();
+        store.set_if_not_exists(1, "pool", &component("first"));
+        store.set_if_not_exists(2, "pool", &component("second"));
+
+        assert_eq!(store.get_last("pool"), Some(component("first")));
+        assert_eq!(store.proto_deltas().deltas.len(), 1);
+    }
+
+    #[test]
+    fn test_add_many() {
+        let store = <MockStore as StoreNew>::new();
+        store.add_many(1, &vec!["a", "b"], BigInt::from(2));
+        store.add(2, "a", BigInt::from(3));
+
+        assert_eq!(store.get_last("a"), Some(BigInt::from(5)));
+        assert_eq!(store.get_last("b"), Some(BigInt::from(2)));
+        assert_eq!(store.get_at(1, "a"), Some(BigInt::from(2)));
+    }
+
+    #[test]
+    fn test_append() {
+        let store = <MockStore<Vec<String>> as StoreNew>::new();
+        store.append(1, "binds", "a");
+        store.append_all(2, "binds", vec!["b", "c"]);
+
+        assert_eq!(
+            store.get_last("binds"),
+            Some(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+        );
+        assert_eq!(store.get_at(1, "binds"), Some(vec!["a".to_string()]));
+        assert_eq!(store.deltas().deltas[1].new_value, b"a;b;c;".to_vec());
+    }
+
+    #[test]
+    fn test_deltas() {
+        let store = <MockStore as StoreNew>::new();
+        store.add(1, "pool:a", BigInt::from(1));
+        store.add(2, "pool:a", BigInt::from(1));
+        store.add(2, "other", BigInt::from(7));
+        store.delete_prefix(3, &"pool:".to_string());
+
+        let delta = |operation: Operation, ordinal, key: &str, old: &str, new: &str| StoreDelta {
+            operation: operation.into(),
+            ordinal,
+            key: key.to_string(),
+            old_value: old.as_bytes().to_vec(),
+            new_value: new.as_bytes().to_vec(),
+        };
+        assert_eq!(
+            store.deltas().deltas,
+            vec![
+                delta(Operation::Create, 1, "pool:a", "", "1"),
+                delta(Operation::Create, 2, "other", "", "7"),
+                delta(Operation::Update, 2, "pool:a", "1", "2"),
+                delta(Operation::Delete, 3, "pool:a", "2", ""),
+            ]
+        );
+        assert_eq!(store.get_last("pool:a"), None);
+        assert_eq!(store.get_at(2, "pool:a"), Some(BigInt::from(2)));
+    }
+
+    #[test]
+    fn test_commit() {
+        let store = <MockStore as StoreNew>::new();
+        store.add(1, "a", BigInt::from(1));
+        store.add(1, "b", BigInt::from(1));
+        store.commit();
+        store.add(7, "a", BigInt::from(2));
+
+        let deltas = store.deltas().deltas;
+        assert_eq!(deltas.len(), 1);
+        assert_eq!(deltas[0].operation, Operation::Update as i32);
+        assert_eq!(deltas[0].old_value, b"1".to_vec());
+        assert_eq!(deltas[0].new_value, b"3".to_vec());
+        assert_eq!(store.get_first("a"), Some(BigInt::from(1)));
+        assert_eq!(store.get_at(6, "a"), Some(BigInt::from(1)));
+        assert_eq!(store.get_last("a"), Some(BigInt::from(3)));
+        assert_eq!(store.get_last("b"), Some(BigInt::from(1)));
     }
 }
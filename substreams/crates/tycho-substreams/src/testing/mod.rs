@@ -1,2 +1,5 @@
+//! Helpers to unit test substreams modules, available with the `testing` feature.
 pub mod assets;
 pub mod mock_store;
+pub mod pipeline;
+pub mod snapshot;
@@ -0,0 +1,805 @@
+//! Runs a substreams package offline against in-memory stores.
+//!
+//! The `Pipeline` parses a substreams manifest (`substreams.yaml`), and runs the handlers
+//! registered for its modules in dependency order for each block, wiring module inputs as the
+//! substreams runtime would: `source` blocks, `params`, `map` outputs and `store` inputs in `get`
+//! or `deltas` mode. Stores are `MockStore`s kept across blocks, so recorded block fixtures can be
+//! replayed to assert the produced `BlockChanges` without any network access.
+//!
+//! Handlers are registered by module name, as the runtime can't call the wasm exports. They
+//! receive a `ModuleInputs` to access their declared inputs, e.g. for a module declared as
+//! `inputs: [{ map: map_events }, { store: store_pools }]`:
+//!
+//! ```ignore
+//! let changes: Vec<BlockChanges> = Pipeline::from_file("substreams.yaml")?
+//!     .with_map("map_events", |inputs| map_events(inputs.block().clone()))
+//!     .with_proto_store::<Pool, _>("store_pools", |inputs, store| {
+//!         store_pools(inputs.map("map_events")?, store);
+//!         Ok(())
+//!     })
+//!     .with_map("map_protocol_changes", |inputs| {
+//!         map_protocol_changes(
+//!             inputs.block().clone(),
+//!             inputs.map("map_events")?,
+//!             inputs.store::<Pool>("store_pools")?,
+//!         )
+//!     })
+//!     .replay("./fixtures", "map_protocol_changes")?;
+//! ```
+//!
+//! Module handlers need to be written against the store traits (`StoreGet`, `StoreSet`, ...)
+//! rather than the concrete store types to accept the mock stores.
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    path::Path,
+};
+
+use serde::Deserialize;
+use substreams::{pb::substreams::StoreDeltas, store::StoreNew};
+use substreams_ethereum::pb::eth;
+
+use crate::testing::{
+    assets::read_block,
+    mock_store::{MockStore, MockStoreValue},
+};
+
+type HandlerError = substreams::errors::Error;
+type MapHandler = Box<dyn Fn(&ModuleInputs) -> Result<Vec<u8>, HandlerError>>;
+type StoreHandler = Box<dyn Fn(&ModuleInputs) -> Result<(), HandlerError>>;
+
+#[derive(Debug)]
+pub enum PipelineError {
+    /// The manifest can't be read or parsed.
+    Manifest(String),
+    /// The module isn't declared in the manifest.
+    UnknownModule(String),
+    /// No handler was registered for a module required to compute the output.
+    MissingHandler(String),
+    /// The handler registered for a module doesn't match the module kind.
+    KindMismatch { module: String, expected: ModuleKind },
+    /// A handler accessed an input its module doesn't declare, or with another mode.
+    UndeclaredInput { module: String, input: String },
+    /// The module declares a `params` input, but no value is configured for it.
+    MissingParams(String),
+    /// A store was accessed with another value type than it was registered with.
+    StoreType(String),
+    /// The modules depend on each other cyclically.
+    Cycle(String),
+    /// A map output can't be decoded into the requested type.
+    Decode { module: String, error: String },
+    /// A handler returned an error.
+    Handler { module: String, block: u64, error: String },
+}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::Manifest(error) => write!(f, "Invalid manifest: {error}"),
+            PipelineError::UnknownModule(module) => {
+                write!(f, "Module {module} is not declared in the manifest")
+            }
+            PipelineError::MissingHandler(module) => {
+                write!(f, "No handler registered for module {module}")
+            }
+            PipelineError::KindMismatch { module, expected } => {
+                write!(f, "Module {module} is a {expected:?} module, but was registered otherwise")
+            }
+            PipelineError::UndeclaredInput { module, input } => {
+                write!(f, "Module {module} does not declare input {input}")
+            }
+            PipelineError::MissingParams(module) => {
+                write!(f, "No params configured for module {module}")
+            }
+            PipelineError::StoreType(module) => {
+                write!(f, "Store {module} was registered with another value type")
+            }
+            PipelineError::Cycle(module) => write!(f, "Module {module} depends on itself"),
+            PipelineError::Decode { module, error } => {
+                write!(f, "Failed to decode output of module {module}: {error}")
+            }
+            PipelineError::Handler { module, block, error } => {
+                write!(f, "Module {module} failed at block {block}: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ModuleKind {
+    Map,
+    Store,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StoreMode {
+    #[default]
+    Get,
+    Deltas,
+}
+
+/// A module input, as declared in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ModuleInput {
+    Source {
+        source: String,
+    },
+    Params {
+        params: String,
+    },
+    Map {
+        map: String,
+    },
+    Store {
+        store: String,
+        #[serde(default)]
+        mode: StoreMode,
+    },
+}
+
+impl ModuleInput {
+    /// Returns the name of the module this input depends on, if any.
+    fn dependency(&self) -> Option<&str> {
+        match self {
+            ModuleInput::Map { map } => Some(map),
+            ModuleInput::Store { store, .. } => Some(store),
+            ModuleInput::Source { .. } | ModuleInput::Params { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleDefinition {
+    pub name: String,
+    pub kind: ModuleKind,
+    #[serde(default)]
+    pub initial_block: Option<u64>,
+    #[serde(default)]
+    pub inputs: Vec<ModuleInput>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NetworkDefinition {
+    #[serde(default)]
+    initial_block: HashMap<String, u64>,
+    #[serde(default)]
+    params: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    network: Option<String>,
+    #[serde(default)]
+    networks: HashMap<String, NetworkDefinition>,
+    #[serde(default)]
+    params: HashMap<String, String>,
+    modules: Vec<ModuleDefinition>,
+}
+
+/// A store registered in the pipeline, erasing its value type.
+trait PipelineStore {
+    fn as_any(&self) -> &dyn Any;
+    fn deltas(&self) -> StoreDeltas;
+    fn commit(&self);
+}
+
+struct TypedStore<V> {
+    store: MockStore<V>,
+    encode: fn(&V) -> Vec<u8>,
+}
+
+impl<V: Clone + 'static> PipelineStore for TypedStore<V> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn deltas(&self) -> StoreDeltas {
+        self.store.deltas_with(self.encode)
+    }
+
+    fn commit(&self) {
+        self.store.commit()
+    }
+}
+
+enum Handler {
+    Map(MapHandler),
+    Store { handler: StoreHandler, store: Box<dyn PipelineStore> },
+}
+
+impl Handler {
+    fn kind(&self) -> ModuleKind {
+        match self {
+            Handler::Map(_) => ModuleKind::Map,
+            Handler::Store { .. } => ModuleKind::Store,
+        }
+    }
+}
+
+/// The inputs of a module for the block being processed.
+///
+/// Accessing an input the module doesn't declare in the manifest is an error, this keeps the
+/// registered handlers consistent with the manifest.
+pub struct ModuleInputs<'a> {
+    module: &'a ModuleDefinition,
+    block: &'a eth::v2::Block,
+    params: Option<&'a str>,
+    outputs: &'a HashMap<String, Vec<u8>>,
+    handlers: &'a HashMap<String, Handler>,
+}
+
+impl ModuleInputs<'_> {
+    /// Returns the block being processed.
+    pub fn block(&self) -> &eth::v2::Block {
+        self.block
+    }
+
+    /// Returns the params of the module.
+    ///
+    /// ## Errors
+    /// If the module has no `params` input or no value configured for it.
+    pub fn params(&self) -> Result<&str, PipelineError> {
+        if !self
+            .module
+            .inputs
+            .iter()
+            .any(|input| matches!(input, ModuleInput::Params { .. }))
+        {
+            return Err(self.undeclared("params"));
+        }
+        self.params
+            .ok_or_else(|| PipelineError::MissingParams(self.module.name.clone()))
+    }
+
+    /// Returns the output of a map module.
+    ///
+    /// ## Errors
+    /// If the map isn't an input of the module or its output can't be decoded into `T`.
+    pub fn map<T: prost::Message + Default>(&self, name: &str) -> Result<T, PipelineError> {
+        self.ensure_declared(&ModuleInput::Map { map: name.to_string() })?;
+        let output = self
+            .outputs
+            .get(name)
+            .ok_or_else(|| PipelineError::MissingHandler(name.to_string()))?;
+        T::decode(output.as_slice())
+            .map_err(|e| PipelineError::Decode { module: name.to_string(), error: e.to_string() })
+    }
+
+    /// Returns a store input in `get` mode, reflecting all writes made up to this block.
+    ///
+    /// ## Errors
+    /// If the store isn't a `get` mode input of the module or holds other values than `V`.
+    pub fn store<V: Clone + 'static>(&self, name: &str) -> Result<MockStore<V>, PipelineError> {
+        self.ensure_declared(&ModuleInput::Store {
+            store: name.to_string(),
+            mode: StoreMode::Get,
+        })?;
+        self.pipeline_store(name)?
+            .as_any()
+            .downcast_ref::<TypedStore<V>>()
+            .map(|typed| typed.store.clone())
+            .ok_or_else(|| PipelineError::StoreType(name.to_string()))
+    }
+
+    /// Returns a store input in `deltas` mode: the changes made to the store in this block.
+    ///
+    /// ## Errors
+    /// If the store isn't a `deltas` mode input of the module.
+    pub fn deltas(&self, name: &str) -> Result<StoreDeltas, PipelineError> {
+        self.ensure_declared(&ModuleInput::Store {
+            store: name.to_string(),
+            mode: StoreMode::Deltas,
+        })?;
+        Ok(self.pipeline_store(name)?.deltas())
+    }
+
+    fn pipeline_store(&self, name: &str) -> Result<&dyn PipelineStore, PipelineError> {
+        match self.handlers.get(name) {
+            Some(Handler::Store { store, .. }) => Ok(store.as_ref()),
+            Some(Handler::Map(_)) => Err(PipelineError::KindMismatch {
+                module: name.to_string(),
+                expected: ModuleKind::Map,
+            }),
+            None => Err(PipelineError::MissingHandler(name.to_string())),
+        }
+    }
+
+    fn ensure_declared(&self, input: &ModuleInput) -> Result<(), PipelineError> {
+        if self.module.inputs.contains(input) {
+            Ok(())
+        } else {
+            Err(self.undeclared(&format!("{input:?}")))
+        }
+    }
+
+    fn undeclared(&self, input: &str) -> PipelineError {
+        PipelineError::UndeclaredInput {
+            module: self.module.name.clone(),
+            input: input.to_string(),
+        }
+    }
+}
+
+/// Offline runner for the modules of a substreams manifest.
+pub struct Pipeline {
+    modules: HashMap<String, ModuleDefinition>,
+    initial_blocks: HashMap<String, u64>,
+    params: HashMap<String, String>,
+    handlers: HashMap<String, Handler>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline from a substreams manifest file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, PipelineError> {
+        let content = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| PipelineError::Manifest(format!("{}: {e}", path.as_ref().display())))?;
+        Self::from_yaml(&content)
+    }
+
+    /// Creates a pipeline from the content of a substreams manifest.
+    ///
+    /// Initial blocks and params are taken from the manifest's `network` entry in `networks`,
+    /// falling back to the top level `params` and the modules' `initialBlock`.
+    pub fn from_yaml(content: &str) -> Result<Self, PipelineError> {
+        let mut manifest: Manifest =
+            serde_yaml::from_str(content).map_err(|e| PipelineError::Manifest(e.to_string()))?;
+        let network = manifest
+            .network
+            .as_ref()
+            .and_then(|network| manifest.networks.remove(network))
+            .unwrap_or_default();
+
+        let mut params = manifest.params;
+        params.extend(network.params);
+        let mut initial_blocks: HashMap<_, _> = manifest
+            .modules
+            .iter()
+            .filter_map(|module| {
+                module
+                    .initial_block
+                    .map(|block| (module.name.clone(), block))
+            })
+            .collect();
+        initial_blocks.extend(network.initial_block);
+
+        Ok(Self {
+            modules: manifest
+                .modules
+                .into_iter()
+                .map(|module| (module.name.clone(), module))
+                .collect(),
+            initial_blocks,
+            params,
+            handlers: HashMap::new(),
+        })
+    }
+
+    /// Sets the params of a module, overriding the manifest.
+    pub fn with_params(mut self, module: &str, params: &str) -> Self {
+        self.params
+            .insert(module.to_string(), params.to_string());
+        self
+    }
+
+    /// Registers the handler of a map module.
+    pub fn with_map<O, F>(mut self, module: &str, handler: F) -> Self
+    where
+        O: prost::Message,
+        F: Fn(&ModuleInputs) -> Result<O, HandlerError> + 'static,
+    {
+        self.handlers.insert(
+            module.to_string(),
+            Handler::Map(Box::new(move |inputs| handler(inputs).map(|out| out.encode_to_vec()))),
+        );
+        self
+    }
+
+    /// Registers the handler of a store module holding scalar values, e.g. `BigInt` or `String`.
+    pub fn with_store<V, F>(self, module: &str, handler: F) -> Self
+    where
+        V: Clone + MockStoreValue + 'static,
+        F: Fn(&ModuleInputs, MockStore<V>) -> Result<(), HandlerError> + 'static,
+    {
+        self.with_typed_store(module, MockStoreValue::encode_store_value, handler)
+    }
+
+    /// Registers the handler of a store module holding protobuf messages.
+    pub fn with_proto_store<V, F>(self, module: &str, handler: F) -> Self
+    where
+        V: Clone + prost::Message + 'static,
+        F: Fn(&ModuleInputs, MockStore<V>) -> Result<(), HandlerError> + 'static,
+    {
+        self.with_typed_store(module, prost::Message::encode_to_vec, handler)
+    }
+
+    fn with_typed_store<V, F>(mut self, module: &str, encode: fn(&V) -> Vec<u8>, handler: F) -> Self
+    where
+        V: Clone + 'static,
+        F: Fn(&ModuleInputs, MockStore<V>) -> Result<(), HandlerError> + 'static,
+    {
+        let store = <MockStore<V> as StoreNew>::new();
+        let handler_store = store.clone();
+        self.handlers.insert(
+            module.to_string(),
+            Handler::Store {
+                handler: Box::new(move |inputs| handler(inputs, handler_store.clone())),
+                store: Box::new(TypedStore { store, encode }),
+            },
+        );
+        self
+    }
+
+    /// Runs all modules required by `output_module` for a block and returns its encoded output.
+    ///
+    /// Modules are skipped for blocks before their initial block, their output is then empty.
+    /// After the block, the writes of all stores are committed.
+    pub fn run_block(
+        &self,
+        block: &eth::v2::Block,
+        output_module: &str,
+    ) -> Result<Vec<u8>, PipelineError> {
+        let execution_order = self.execution_order(output_module)?;
+        let mut outputs: HashMap<String, Vec<u8>> = HashMap::new();
+
+        for module in execution_order {
+            if block.number < self.initial_block(module)? {
+                outputs.insert(module.name.clone(), Vec::new());
+                continue;
+            }
+
+            let inputs = ModuleInputs {
+                module,
+                block,
+                params: self
+                    .params
+                    .get(&module.name)
+                    .map(String::as_str),
+                outputs: &outputs,
+                handlers: &self.handlers,
+            };
+            let handler_error = |error: HandlerError| PipelineError::Handler {
+                module: module.name.clone(),
+                block: block.number,
+                error: error.to_string(),
+            };
+            match &self.handlers[&module.name] {
+                Handler::Map(handler) => {
+                    let output = handler(&inputs).map_err(handler_error)?;
+                    outputs.insert(module.name.clone(), output);
+                }
+                Handler::Store { handler, .. } => handler(&inputs).map_err(handler_error)?,
+            }
+        }
+
+        self.handlers
+            .values()
+            .for_each(|handler| {
+                if let Handler::Store { store, .. } = handler {
+                    store.commit();
+                }
+            });
+
+        Ok(outputs
+            .remove(output_module)
+            .unwrap_or_default())
+    }
+
+    /// Runs `output_module` over the given blocks, in order, and decodes its outputs.
+    pub fn run<T: prost::Message + Default>(
+        &self,
+        blocks: &[eth::v2::Block],
+        output_module: &str,
+    ) -> Result<Vec<T>, PipelineError> {
+        blocks
+            .iter()
+            .map(|block| {
+                let output = self.run_block(block, output_module)?;
+                T::decode(output.as_slice()).map_err(|e| PipelineError::Decode {
+                    module: output_module.to_string(),
+                    error: e.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Runs `output_module` over all block fixtures in a directory, ordered by file name.
+    ///
+    /// Fixtures are base64 encoded `eth::v2::Block`s, as read by `read_block`.
+    pub fn replay<T: prost::Message + Default, P: AsRef<Path>>(
+        &self,
+        fixtures_dir: P,
+        output_module: &str,
+    ) -> Result<Vec<T>, PipelineError> {
+        let mut paths = std::fs::read_dir(fixtures_dir.as_ref())
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                PipelineError::Manifest(format!("{}: {e}", fixtures_dir.as_ref().display()))
+            })?;
+        paths.retain(|path| path.is_file());
+        paths.sort();
+
+        let blocks = paths
+            .iter()
+            .map(|path| read_block::<eth::v2::Block>(&path.to_string_lossy()))
+            .collect::<Vec<_>>();
+        self.run(&blocks, output_module)
+    }
+
+    /// Returns the modules `output_module` depends on, followed by itself, in execution order.
+    fn execution_order(
+        &self,
+        output_module: &str,
+    ) -> Result<Vec<&ModuleDefinition>, PipelineError> {
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        let mut visiting = HashSet::new();
+        self.visit(output_module, &mut visited, &mut visiting, &mut order)?;
+        Ok(order)
+    }
+
+    fn visit<'a>(
+        &'a self,
+        name: &str,
+        visited: &mut HashSet<String>,
+        visiting: &mut HashSet<String>,
+        order: &mut Vec<&'a ModuleDefinition>,
+    ) -> Result<(), PipelineError> {
+        if visited.contains(name) {
+            return Ok(());
+        }
+        if !visiting.insert(name.to_string()) {
+            return Err(PipelineError::Cycle(name.to_string()));
+        }
+
+        let module = self
+            .modules
+            .get(name)
+            .ok_or_else(|| PipelineError::UnknownModule(name.to_string()))?;
+        let handler = self
+            .handlers
+            .get(name)
+            .ok_or_else(|| PipelineError::MissingHandler(name.to_string()))?;
+        if handler.kind() != module.kind {
+            return Err(PipelineError::KindMismatch {
+                module: name.to_string(),
+                expected: module.kind,
+            });
+        }
+
+        for dependency in module
+            .inputs
+            .iter()
+            .filter_map(ModuleInput::dependency)
+        {
+            self.visit(dependency, visited, visiting, order)?;
+        }
+
+        visiting.remove(name);
+        visited.insert(name.to_string());
+        order.push(module);
+        Ok(())
+    }
+
+    /// Returns the initial block of a module: the configured one, or else the highest initial
+    /// block of its dependencies.
+    fn initial_block(&self, module: &ModuleDefinition) -> Result<u64, PipelineError> {
+        if let Some(block) = self.initial_blocks.get(&module.name) {
+            return Ok(*block);
+        }
+        module
+            .inputs
+            .iter()
+            .filter_map(ModuleInput::dependency)
+            .map(|dependency| {
+                self.modules
+                    .get(dependency)
+                    .ok_or_else(|| PipelineError::UnknownModule(dependency.to_string()))
+                    .and_then(|dependency| self.initial_block(dependency))
+            })
+            .try_fold(0, |max, block| block.map(|block| max.max(block)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BlockChanges, ProtocolComponent, Transaction, TransactionChanges};
+    use substreams::{
+        scalar::BigInt,
+        store::{StoreAdd, StoreGet, StoreSetIfNotExists},
+    };
+
+    const MANIFEST: &str = r#"
+specVersion: v0.1.0
+network: ethereum
+networks:
+  ethereum:
+    initialBlock:
+      map_components: 10
+    params:
+      map_components: "prefix=pool"
+
+modules:
+  - name: map_components
+    kind: map
+    inputs:
+      - params: string
+      - source: sf.ethereum.type.v2.Block
+    output:
+      type: proto:tycho.evm.v1.BlockChanges
+
+  - name: store_components
+    kind: store
+    updatePolicy: set_if_not_exists
+    valueType: proto:tycho.evm.v1.ProtocolComponent
+    inputs:
+      - map: map_components
+
+  - name: store_block_count
+    kind: store
+    updatePolicy: add
+    valueType: bigint
+    inputs:
+      - source: sf.ethereum.type.v2.Block
+
+  - name: map_protocol_changes
+    kind: map
+    inputs:
+      - source: sf.ethereum.type.v2.Block
+      - map: map_components
+      - store: store_components
+      - store: store_components
+        mode: deltas
+      - store: store_block_count
+    output:
+      type: proto:tycho.evm.v1.BlockChanges
+"#;
+
+    fn block(number: u64) -> eth::v2::Block {
+        eth::v2::Block { number, ..Default::default() }
+    }
+
+    /// Creates a component named after the block, plus an already known one.
+    fn map_components(inputs: &ModuleInputs) -> Result<BlockChanges, HandlerError> {
+        let prefix = inputs
+            .params()?
+            .trim_start_matches("prefix=");
+        let components = [format!("{prefix}-{}", inputs.block().number), format!("{prefix}-10")]
+            .into_iter()
+            .map(|id| ProtocolComponent { id, ..Default::default() })
+            .collect();
+        Ok(BlockChanges {
+            changes: vec![TransactionChanges {
+                tx: Some(Transaction::default()),
+                component_changes: components,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+    }
+
+    fn pipeline() -> Pipeline {
+        Pipeline::from_yaml(MANIFEST)
+            .unwrap()
+            .with_map("map_components", map_components)
+            .with_proto_store::<ProtocolComponent, _>("store_components", |inputs, store| {
+                let changes: BlockChanges = inputs.map("map_components")?;
+                for component in changes
+                    .changes
+                    .iter()
+                    .flat_map(|tx| tx.component_changes.iter())
+                {
+                    store.set_if_not_exists(0, &component.id, component);
+                }
+                Ok(())
+            })
+            .with_store::<BigInt, _>("store_block_count", |_, store| {
+                store.add(0, "blocks", BigInt::from(1));
+                Ok(())
+            })
+            .with_map("map_protocol_changes", |inputs| {
+                let known = inputs.store::<ProtocolComponent>("store_components")?;
+                let new_components = inputs
+                    .deltas("store_components")?
+                    .deltas
+                    .into_iter()
+                    .map(|delta| ProtocolComponent { id: delta.key, ..Default::default() })
+                    .collect::<Vec<_>>();
+                assert!(new_components
+                    .iter()
+                    .all(|c| known.has_last(&c.id)));
+                let block_count = inputs
+                    .store::<BigInt>("store_block_count")?
+                    .get_last("blocks")
+                    .unwrap()
+                    .to_u64();
+                Ok(BlockChanges {
+                    block: Some(crate::models::Block { number: block_count, ..Default::default() }),
+                    changes: vec![TransactionChanges {
+                        component_changes: new_components,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                })
+            })
+    }
+
+    #[test]
+    fn test_run_pipeline() {
+        let outputs: Vec<BlockChanges> = pipeline()
+            .run(&[block(9), block(10), block(11)], "map_protocol_changes")
+            .unwrap();
+
+        // map_protocol_changes inherits the initial block of map_components
+        assert_eq!(outputs[0], BlockChanges::default());
+        let new_ids = outputs[1..]
+            .iter()
+            .map(|changes| {
+                changes.changes[0]
+                    .component_changes
+                    .iter()
+                    .map(|c| c.id.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(new_ids, vec![vec!["pool-10"], vec!["pool-11"]]);
+        // store_block_count has no initial block, it also counted block 9
+        assert_eq!(
+            outputs[1..]
+                .iter()
+                .map(|changes| changes.block.as_ref().unwrap().number)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_run_pipeline_params_override() {
+        let outputs: Vec<BlockChanges> = pipeline()
+            .with_params("map_components", "prefix=pair")
+            .run(&[block(12)], "map_components")
+            .unwrap();
+
+        assert_eq!(outputs[0].changes[0].component_changes[0].id, "pair-12");
+    }
+
+    #[test]
+    fn test_run_pipeline_missing_handler() {
+        let pipeline = Pipeline::from_yaml(MANIFEST)
+            .unwrap()
+            .with_map("map_protocol_changes", |_| Ok(BlockChanges::default()));
+
+        let res = pipeline.run_block(&block(10), "map_protocol_changes");
+
+        assert!(
+            matches!(res, Err(PipelineError::MissingHandler(module)) if module == "map_components")
+        );
+    }
+
+    #[test]
+    fn test_run_pipeline_undeclared_input() {
+        let pipeline = pipeline().with_map("map_components", |inputs| {
+            inputs.deltas("store_components")?;
+            Ok(BlockChanges::default())
+        });
+
+        let res = pipeline.run_block(&block(10), "map_components");
+
+        assert!(
+            matches!(res, Err(PipelineError::Handler { module, .. }) if module == "map_components")
+        );
+    }
+}
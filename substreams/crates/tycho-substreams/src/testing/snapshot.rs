@@ -0,0 +1,605 @@
+//! Golden file snapshot testing for `BlockChanges`.
+//!
+//! `BlockChanges` are rendered into a stable, human-readable form: bytes are hex encoded, enums
+//! are named, and entities are keyed by their identity (contract address, slot, component id,
+//! ...) so that the ordering of repeated fields doesn't affect the snapshot. Contract code is
+//! summarised by its hash and size. Transactions are kept in block order.
+//!
+//! Snapshots are compared structurally against a golden file, `.json` or `.yaml`/`.yml`
+//! depending on its extension. On mismatch, the test fails listing each changed path, colorized
+//! unless `NO_COLOR` is set. Running the tests with `UPDATE_SNAPSHOTS=1` (re)writes the golden
+//! files instead.
+//!
+//! ```ignore
+//! #[test]
+//! fn test_map_pool_events() {
+//!     assert_block_snapshot(
+//!         "./assets/block-19000000.binpb.base64",
+//!         "./snapshots/map_pool_events-19000000.yaml",
+//!         |block| map_pool_events(block, ...).unwrap(),
+//!     );
+//! }
+//! ```
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+use serde_json::{json, Map, Value};
+use substreams_ethereum::pb::eth;
+
+use crate::{
+    models::{
+        entry_point_params::TraceData, Attribute, BlockChanges, ChangeType, ContractSlot,
+        FinancialType, ImplementationType, ProtocolComponent, Transaction,
+        TransactionStorageChanges,
+    },
+    storage::keys::keccak256,
+    testing::assets::read_block,
+};
+
+/// Environment variable enabling update mode when set to `1` or `true`.
+pub const UPDATE_SNAPSHOTS_ENV: &str = "UPDATE_SNAPSHOTS";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The golden file can't be read or written.
+    Io { path: PathBuf, error: String },
+    /// The golden file can't be parsed, or has an unsupported extension.
+    Format { path: PathBuf, error: String },
+    /// The golden file doesn't exist.
+    Missing(PathBuf),
+    /// The snapshot differs from the golden file. Contains the rendered differences.
+    Mismatch { path: PathBuf, diff: String },
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io { path, error } => {
+                write!(f, "Failed to access snapshot {}: {error}", path.display())
+            }
+            SnapshotError::Format { path, error } => {
+                write!(f, "Invalid snapshot {}: {error}", path.display())
+            }
+            SnapshotError::Missing(path) => write!(
+                f,
+                "Snapshot {} does not exist, run with {UPDATE_SNAPSHOTS_ENV}=1 to create it",
+                path.display()
+            ),
+            SnapshotError::Mismatch { path, diff } => write!(
+                f,
+                "Snapshot {} differs, run with {UPDATE_SNAPSHOTS_ENV}=1 to update it:\n{diff}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Json,
+    Yaml,
+}
+
+impl SnapshotFormat {
+    fn from_path(path: &Path) -> Result<Self, SnapshotError> {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("json") => Ok(SnapshotFormat::Json),
+            Some("yaml" | "yml") => Ok(SnapshotFormat::Yaml),
+            _ => Err(SnapshotError::Format {
+                path: path.to_path_buf(),
+                error: "expected a .json, .yaml or .yml extension".to_string(),
+            }),
+        }
+    }
+
+    /// Renders a snapshot value in this format.
+    pub fn render(&self, value: &Value) -> String {
+        match self {
+            SnapshotFormat::Json => {
+                let mut rendered =
+                    serde_json::to_string_pretty(value).expect("Snapshot values are valid JSON");
+                rendered.push('\n');
+                rendered
+            }
+            SnapshotFormat::Yaml => {
+                serde_yaml::to_string(value).expect("Snapshot values are valid YAML")
+            }
+        }
+    }
+
+    fn parse(&self, content: &str) -> Result<Value, String> {
+        match self {
+            SnapshotFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+            SnapshotFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Returns whether update mode is enabled through `UPDATE_SNAPSHOTS`.
+pub fn update_mode() -> bool {
+    std::env::var(UPDATE_SNAPSHOTS_ENV).is_ok_and(|value| value == "1" || value == "true")
+}
+
+/// Compares a snapshot value with a golden file, or writes the golden file in update mode.
+///
+/// ## Errors
+/// Returns `SnapshotError::Mismatch` with the rendered differences if the golden file differs,
+/// or `SnapshotError::Missing` if it doesn't exist and `update` is false.
+pub fn check_snapshot<P: AsRef<Path>>(
+    path: P,
+    value: &Value,
+    update: bool,
+) -> Result<(), SnapshotError> {
+    let path = path.as_ref();
+    let format = SnapshotFormat::from_path(path)?;
+    let io_error =
+        |e: std::io::Error| SnapshotError::Io { path: path.to_path_buf(), error: e.to_string() };
+
+    if update {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        return std::fs::write(path, format.render(value)).map_err(io_error);
+    }
+
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(SnapshotError::Missing(path.to_path_buf()))
+        }
+        Err(e) => return Err(io_error(e)),
+    };
+    let expected = format
+        .parse(&content)
+        .map_err(|error| SnapshotError::Format { path: path.to_path_buf(), error })?;
+
+    let mut differences = Vec::new();
+    diff_values("", &expected, value, &mut differences);
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(SnapshotError::Mismatch {
+            path: path.to_path_buf(),
+            diff: differences
+                .iter()
+                .map(Difference::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        })
+    }
+}
+
+/// Asserts that `changes` match the golden file at `path`.
+///
+/// ## Panics
+/// Panics with the differences if the snapshot doesn't match. In update mode, see
+/// `UPDATE_SNAPSHOTS_ENV`, the golden file is written instead.
+pub fn assert_snapshot<P: AsRef<Path>>(path: P, changes: &BlockChanges) {
+    check_snapshot(path, &block_changes_value(changes), update_mode())
+        .unwrap_or_else(|e| panic!("{e}"));
+}
+
+/// Asserts that the `BlockChanges` of several blocks match the golden file at `path`.
+///
+/// ## Panics
+/// Panics with the differences if the snapshot doesn't match.
+pub fn assert_snapshots<P: AsRef<Path>>(path: P, changes: &[BlockChanges]) {
+    let value = Value::Array(
+        changes
+            .iter()
+            .map(block_changes_value)
+            .collect(),
+    );
+    check_snapshot(path, &value, update_mode()).unwrap_or_else(|e| panic!("{e}"));
+}
+
+/// Runs `module` on a block fixture, read with `read_block`, and asserts its output matches the
+/// golden file at `snapshot_path`.
+///
+/// ## Panics
+/// Panics if the fixture can't be read or the snapshot doesn't match.
+pub fn assert_block_snapshot<F, P>(fixture_path: &str, snapshot_path: P, module: F)
+where
+    F: FnOnce(eth::v2::Block) -> BlockChanges,
+    P: AsRef<Path>,
+{
+    let block = read_block::<eth::v2::Block>(fixture_path);
+    assert_snapshot(snapshot_path, &module(block));
+}
+
+/// Converts `BlockChanges` into their stable snapshot representation.
+pub fn block_changes_value(changes: &BlockChanges) -> Value {
+    let mut transactions = changes
+        .changes
+        .iter()
+        .collect::<Vec<_>>();
+    transactions.sort_by_key(|tx| tx.tx.as_ref().map(|tx| tx.index));
+    let mut storage_changes = changes
+        .storage_changes
+        .iter()
+        .collect::<Vec<_>>();
+    storage_changes.sort_by_key(|tx| tx.tx.as_ref().map(|tx| tx.index));
+
+    json!({
+        "block": changes.block.as_ref().map(|block| json!({
+            "number": block.number,
+            "hash": hex(&block.hash),
+            "parent_hash": hex(&block.parent_hash),
+            "ts": block.ts,
+        })),
+        "changes": transactions
+            .into_iter()
+            .map(|tx_changes| {
+                json!({
+                    "tx": tx_changes.tx.as_ref().map(transaction_value),
+                    "contract_changes": keyed(tx_changes.contract_changes.iter().map(|change| {
+                        (hex(&change.address), json!({
+                            "change": change_type(change.change),
+                            "balance": hex(&change.balance),
+                            "code_hash": (!change.code.is_empty())
+                                .then(|| hex(&keccak256(&change.code))),
+                            "code_size": change.code.len(),
+                            "slots": slots_value(&change.slots),
+                            "token_balances": keyed(change.token_balances.iter().map(|b| {
+                                (hex(&b.token), Value::String(hex(&b.balance)))
+                            })),
+                        }))
+                    })),
+                    "component_changes": keyed(tx_changes.component_changes.iter().map(|c| {
+                        (c.id.clone(), component_value(c))
+                    })),
+                    "entity_changes": keyed(tx_changes.entity_changes.iter().map(|e| {
+                        (e.component_id.clone(), attributes_value(&e.attributes))
+                    })),
+                    "balance_changes": keyed(tx_changes.balance_changes.iter().map(|b| {
+                        (
+                            format!("{}/{}", component_id(&b.component_id), hex(&b.token)),
+                            Value::String(hex(&b.balance)),
+                        )
+                    })),
+                    "entrypoints": keyed(tx_changes.entrypoints.iter().map(|e| {
+                        (e.id.clone(), json!({
+                            "target": hex(&e.target),
+                            "signature": e.signature,
+                            "component_id": e.component_id,
+                        }))
+                    })),
+                    "entrypoint_params": keyed(tx_changes.entrypoint_params.iter().map(|p| {
+                        (
+                            format!(
+                                "{}/{}",
+                                p.entrypoint_id,
+                                p.component_id.as_deref().unwrap_or_default()
+                            ),
+                            match &p.trace_data {
+                                Some(TraceData::Rpc(rpc)) => json!({
+                                    "rpc": {
+                                        "caller": rpc.caller.as_deref().map(hex),
+                                        "calldata": hex(&rpc.calldata),
+                                    }
+                                }),
+                                None => Value::Null,
+                            },
+                        )
+                    })),
+                })
+            })
+            .collect::<Vec<_>>(),
+        "storage_changes": storage_changes
+            .into_iter()
+            .map(storage_changes_value)
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn transaction_value(tx: &Transaction) -> Value {
+    json!({
+        "hash": hex(&tx.hash),
+        "from": hex(&tx.from),
+        "to": hex(&tx.to),
+        "index": tx.index,
+    })
+}
+
+fn storage_changes_value(changes: &TransactionStorageChanges) -> Value {
+    json!({
+        "tx": changes.tx.as_ref().map(transaction_value),
+        "contracts": keyed(changes.storage_changes.iter().map(|change| {
+            (hex(&change.address), json!({
+                "slots": slots_value(&change.slots),
+                "native_balance": change.native_balance.as_deref().map(hex),
+            }))
+        })),
+    })
+}
+
+fn component_value(component: &ProtocolComponent) -> Value {
+    json!({
+        "change": change_type(component.change),
+        "tokens": component.tokens.iter().map(|t| hex(t)).collect::<Vec<_>>(),
+        "contracts": component.contracts.iter().map(|c| hex(c)).collect::<Vec<_>>(),
+        "static_attributes": attributes_value(&component.static_att),
+        "protocol_type": component.protocol_type.as_ref().map(|protocol_type| json!({
+            "name": protocol_type.name,
+            "financial_type": FinancialType::from_i32(protocol_type.financial_type)
+                .map(|t| t.as_str_name().to_string())
+                .unwrap_or_else(|| protocol_type.financial_type.to_string()),
+            "implementation_type": ImplementationType::from_i32(protocol_type.implementation_type)
+                .map(|t| t.as_str_name().to_string())
+                .unwrap_or_else(|| protocol_type.implementation_type.to_string()),
+            "attribute_schema": attributes_value(&protocol_type.attribute_schema),
+        })),
+    })
+}
+
+fn slots_value(slots: &[ContractSlot]) -> Value {
+    keyed(slots.iter().map(|slot| {
+        (
+            hex(&slot.slot),
+            json!({
+                "value": hex(&slot.value),
+                "previous_value": hex(&slot.previous_value),
+            }),
+        )
+    }))
+}
+
+fn attributes_value(attributes: &[Attribute]) -> Value {
+    keyed(attributes.iter().map(|attr| {
+        (
+            attr.name.clone(),
+            json!({
+                "value": hex(&attr.value),
+                "change": change_type(attr.change),
+            }),
+        )
+    }))
+}
+
+/// Builds an object from key value pairs. Repeated keys are suffixed with `#<n>` so that no
+/// entry is lost.
+fn keyed<I: Iterator<Item = (String, Value)>>(entries: I) -> Value {
+    let mut map = Map::new();
+    for (key, value) in entries {
+        let mut unique_key = key.clone();
+        let mut n = 1;
+        while map.contains_key(&unique_key) {
+            unique_key = format!("{key}#{n}");
+            n += 1;
+        }
+        map.insert(unique_key, value);
+    }
+    Value::Object(map)
+}
+
+fn change_type(change: i32) -> String {
+    ChangeType::from_i32(change)
+        .map(|c| c.as_str_name().to_string())
+        .unwrap_or_else(|| change.to_string())
+}
+
+/// Component ids are usually utf-8 encoded strings, other ids are hex encoded.
+fn component_id(id: &[u8]) -> String {
+    match std::str::from_utf8(id) {
+        Ok(id) if id.chars().all(|c| c.is_ascii_graphic()) => id.to_string(),
+        _ => hex(id),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// A difference between the golden and the actual snapshot at a path.
+enum Difference<'a> {
+    Removed(String, &'a Value),
+    Added(String, &'a Value),
+    Changed(String, &'a Value, &'a Value),
+}
+
+impl Display for Difference<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::Removed(path, value) => {
+                write!(f, "{}", format!("- {path}: {value}").red())
+            }
+            Difference::Added(path, value) => {
+                write!(f, "{}", format!("+ {path}: {value}").green())
+            }
+            Difference::Changed(path, expected, actual) => write!(
+                f,
+                "{} {path}: {} -> {}",
+                "~".yellow(),
+                expected.to_string().red(),
+                actual.to_string().green()
+            ),
+        }
+    }
+}
+
+fn diff_values<'a>(
+    path: &str,
+    expected: &'a Value,
+    actual: &'a Value,
+    differences: &mut Vec<Difference<'a>>,
+) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected_value) in expected {
+                let key_path = if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+                match actual.get(key) {
+                    Some(actual_value) => {
+                        diff_values(&key_path, expected_value, actual_value, differences)
+                    }
+                    None => differences.push(Difference::Removed(key_path, expected_value)),
+                }
+            }
+            for (key, actual_value) in actual {
+                if !expected.contains_key(key) {
+                    let key_path =
+                        if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+                    differences.push(Difference::Added(key_path, actual_value));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (idx, expected_value) in expected.iter().enumerate() {
+                let idx_path = format!("{path}[{idx}]");
+                match actual.get(idx) {
+                    Some(actual_value) => {
+                        diff_values(&idx_path, expected_value, actual_value, differences)
+                    }
+                    None => differences.push(Difference::Removed(idx_path, expected_value)),
+                }
+            }
+            for (idx, actual_value) in actual
+                .iter()
+                .enumerate()
+                .skip(expected.len())
+            {
+                differences.push(Difference::Added(format!("{path}[{idx}]"), actual_value));
+            }
+        }
+        _ if expected != actual => {
+            differences.push(Difference::Changed(path.to_string(), expected, actual))
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Block, ContractChange, InterimContractChange, TransactionChangesBuilder};
+    use substreams_ethereum::pb::eth::v2::StorageChange;
+
+    fn changes(slot_value: u8) -> BlockChanges {
+        let mut builder = TransactionChangesBuilder::new(&Transaction {
+            hash: vec![0xaa; 32],
+            index: 3,
+            ..Default::default()
+        });
+        let mut contract = InterimContractChange::new(&[0xc0; 20], false);
+        for key in [2u8, 1u8] {
+            contract.upsert_slot(&StorageChange {
+                address: vec![0xc0; 20],
+                key: vec![key],
+                old_value: vec![0],
+                new_value: vec![slot_value],
+                ordinal: key.into(),
+            });
+        }
+        builder.add_contract_changes(&contract);
+        builder.add_protocol_component(&ProtocolComponent {
+            id: "pool".to_string(),
+            tokens: vec![vec![0x02], vec![0x01]],
+            change: ChangeType::Creation.into(),
+            ..Default::default()
+        });
+        BlockChanges {
+            block: Some(Block { number: 1, ..Default::default() }),
+            changes: vec![builder.build().unwrap()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_block_changes_value_is_stable() {
+        let mut reordered = changes(1);
+        reordered.changes[0].contract_changes[0]
+            .slots
+            .reverse();
+
+        let value = block_changes_value(&changes(1));
+
+        assert_eq!(value, block_changes_value(&reordered));
+        assert_eq!(
+            value["changes"][0]["contract_changes"]["0xc0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0"]
+                ["slots"]["0x01"]["value"],
+            "0x01"
+        );
+        assert_eq!(
+            value["changes"][0]["component_changes"]["pool"]["change"],
+            "CHANGE_TYPE_CREATION"
+        );
+        assert_eq!(value["changes"][0]["tx"]["index"], 3);
+    }
+
+    #[test]
+    fn test_keyed_keeps_repeated_keys() {
+        let value = keyed([("a".to_string(), json!(1)), ("a".to_string(), json!(2))].into_iter());
+
+        assert_eq!(value, json!({"a": 1, "a#1": 2}));
+    }
+
+    #[rstest::rstest]
+    #[case::json("changes.json")]
+    #[case::yaml("changes.yaml")]
+    fn test_check_snapshot(#[case] file: &str) {
+        let dir = std::env::temp_dir().join(format!("tycho-snapshot-{}", std::process::id()));
+        let path = dir.join(file);
+        let value = block_changes_value(&changes(1));
+
+        assert!(matches!(check_snapshot(&path, &value, false), Err(SnapshotError::Missing(_))));
+        check_snapshot(&path, &value, true).unwrap();
+        check_snapshot(&path, &value, false).unwrap();
+
+        let err = check_snapshot(&path, &block_changes_value(&changes(2)), false).unwrap_err();
+        let SnapshotError::Mismatch { diff, .. } = err else { panic!("Expected mismatch: {err}") };
+        assert_eq!(diff.lines().count(), 2);
+        assert!(diff.contains("slots.0x01.value"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_check_snapshot_unsupported_extension() {
+        let res = check_snapshot("changes.txt", &json!({}), false);
+
+        assert!(matches!(res, Err(SnapshotError::Format { .. })));
+    }
+
+    #[test]
+    fn test_diff_values() {
+        let expected = json!({"a": 1, "b": [1, 2], "c": {"d": "x"}});
+        let actual = json!({"a": 2, "b": [1], "c": {"d": "x", "e": true}});
+
+        let mut differences = Vec::new();
+        diff_values("", &expected, &actual, &mut differences);
+
+        let paths = differences
+            .iter()
+            .map(|d| match d {
+                Difference::Removed(path, _) => format!("-{path}"),
+                Difference::Added(path, _) => format!("+{path}"),
+                Difference::Changed(path, _, _) => format!("~{path}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["~a", "-b[1]", "+c.e"]);
+    }
+
+    #[test]
+    fn test_contract_code_is_summarised() {
+        let mut block_changes = changes(1);
+        block_changes.changes[0].contract_changes = vec![ContractChange {
+            address: vec![0x01],
+            code: vec![0x60, 0x80],
+            change: ChangeType::Creation.into(),
+            ..Default::default()
+        }];
+
+        let value = block_changes_value(&block_changes);
+
+        let contract = &value["changes"][0]["contract_changes"]["0x01"];
+        assert_eq!(contract["code_size"], 2);
+        assert_eq!(contract["code_hash"], hex(&keccak256(&[0x60, 0x80])));
+    }
+}